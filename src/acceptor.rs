@@ -0,0 +1,55 @@
+//! The managed certificates for servers other than `HttpServer`.
+//!
+//! `LetsEncrypt::tls_acceptor` is an `actix_tls` service factory that
+//! performs the TLS handshake, so a raw `actix_server::Server` running some
+//! other protocol can be given automatic certificates too:
+//!
+//! ```ignore
+//! let acceptor = enabler.tls_acceptor("mail.example.com")?;
+//! actix_server::Server::build().bind("smtps", "0.0.0.0:465", move || {
+//!     acceptor.clone().and_then(handle_connection)
+//! })?;
+//! ```
+//!
+//! Like the listeners `attach_certificates_to` binds, it picks the
+//! certificate by SNI and serves renewed ones without being rebuilt.  The
+//! enabler's HTTP challenges must still be answered by some `HttpServer`
+//! the enabler is registered with.
+//!
+//! `LetsEncrypt::acceptor_for` is the openssl acceptor builder underneath,
+//! for binding `HttpServer`s (or test servers) by hand rather than through
+//! `attach_certificates_to`.
+
+use {
+    crate::{Domain, Error, LetsEncrypt},
+    actix_tls::openssl::Acceptor,
+    openssl::ssl::SslAcceptorBuilder,
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+
+impl LetsEncrypt {
+    /// An acceptor for connections to `domain`, presenting the certificate
+    /// covering it to clients that don't send SNI.
+    pub fn tls_acceptor<D, T>(&self, domain: D) -> Result<Acceptor<T>, Error>
+    where
+        D: AsRef<str>,
+        T: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        Ok(Acceptor::new(self.acceptor_for(domain)?.build()))
+    }
+
+    /// The acceptor builder `attach_certificates_to` would bind the cert
+    /// covering `domain` with, e.g. for `HttpServer::bind_openssl`.  It
+    /// can be adjusted (ALPN, session caching) before it's built; replacing
+    /// its servername callback stops it from switching certificates.
+    pub fn acceptor_for<D: AsRef<str>>(&self, domain: D) -> Result<SslAcceptorBuilder, Error> {
+        let domain = Domain::new(domain)?;
+        let cert_builder = self
+            .config
+            .cert_builders
+            .iter()
+            .find(|c| c.domains.contains(&domain))
+            .ok_or_else(|| Error::NotManaged(domain.clone()))?;
+        self.listener_acceptor(cert_builder)
+    }
+}
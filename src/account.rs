@@ -0,0 +1,98 @@
+//! The ACME account key, stored apart from the certificates' keys.
+//!
+//! The account key is what revokes certificates and authorizes future
+//! orders, so rather than letting acme-client generate a throwaway one per
+//! order it is kept in its own file: `account_key_path` (by default
+//! `account.key` in the ssl directory; each tenant keeps one in its own
+//! directory), with `account_key_mode` permissions in a directory only the
+//! owner can enter, and wrapped like any other key when a `KeyWrap` is
+//! configured.  Under systemd, an `account.key` credential takes precedence
+//! over all of that; see the `credentials` module.
+
+use {
+    crate::{credentials, tenant, CertBuilder, Error, KeyType, Layout, LetsEncrypt},
+    log::info,
+    openssl::pkey::{PKey, Private},
+    std::{fs::DirBuilder, os::unix::fs::DirBuilderExt, path::PathBuf},
+    zeroize::Zeroizing,
+};
+
+const ACCOUNT_KEY_FILE: &str = "account.key";
+
+// acme-client only signs with RSA account keys.
+const ACCOUNT_KEY_TYPE: KeyType = KeyType::Rsa2048;
+
+impl LetsEncrypt {
+    pub(crate) fn account_key_file(&self, tenant: Option<&str>) -> PathBuf {
+        let credential = match tenant {
+            Some(tenant) => format!("{}.{}", tenant, ACCOUNT_KEY_FILE),
+            None => ACCOUNT_KEY_FILE.to_string(),
+        };
+        if let Some(path) = credentials::path(&credential) {
+            return path;
+        }
+        match (tenant, &self.config.account_key_path) {
+            (Some(tenant), _) => {
+                tenant::assert_valid_name(tenant);
+                self.config
+                    .ssl_directory
+                    .join("tenants")
+                    .join(tenant)
+                    .join(ACCOUNT_KEY_FILE)
+            }
+            (None, Some(path)) => self.config.ssl_directory.join(path),
+            (None, None) => self.config.ssl_directory.join(ACCOUNT_KEY_FILE),
+        }
+    }
+
+    /// The account key for `cert_builder`'s tenant, generated and stored
+    /// the first time it's needed.
+    pub(crate) fn account_key(&self, cert_builder: &CertBuilder) -> Result<PKey<Private>, Error> {
+        let tenant = cert_builder.tenant.as_deref();
+        let path = self.account_key_file(tenant);
+        if path.exists() {
+            return self.read_private_key(&path);
+        }
+        if self.config.layout == Layout::Certbot {
+            if let Some(key) = self.certbot_account_key(cert_builder) {
+                self.store_account_key(tenant, &Zeroizing::new(key.private_key_to_pem_pkcs8()?))?;
+                return Ok(key);
+            }
+        }
+        let key = ACCOUNT_KEY_TYPE.generate()?;
+        self.store_account_key(tenant, &Zeroizing::new(key.private_key_to_pem_pkcs8()?))?;
+        info!("generated ACME account key {}", path.display());
+        Ok(key)
+    }
+
+    fn store_account_key(&self, tenant: Option<&str>, pem: &[u8]) -> Result<(), Error> {
+        let path = self.account_key_file(tenant);
+        if let Some(directory) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(directory)?;
+        }
+        self.write_private_key(&path, pem, self.config.account_key_mode)
+    }
+
+    /// The (unwrapped) PEM account key of `tenant`, or of the enabler when
+    /// None, e.g. to move it to another deployment.
+    pub fn export_account_key(&self, tenant: Option<&str>) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let key = self.read_private_key(&self.account_key_file(tenant))?;
+        Ok(Zeroizing::new(key.private_key_to_pem_pkcs8()?))
+    }
+
+    /// Replaces the account key of `tenant`, or of the enabler when None,
+    /// with a PEM private key; later orders and revocations are made with
+    /// that account.
+    pub fn import_account_key(&self, tenant: Option<&str>, pem: &[u8]) -> Result<(), Error> {
+        let key = PKey::private_key_from_pem(pem)?;
+        self.store_account_key(tenant, &Zeroizing::new(key.private_key_to_pem_pkcs8()?))?;
+        info!(
+            "imported ACME account key {}",
+            self.account_key_file(tenant).display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! An optional, token protected HTTP API for operating the enabler.
+//!
+//! All endpoints live under the configured path and require an
+//! `Authorization: Bearer <token>` header:
+//!
+//! * `GET  certs` - status of every managed certificate
+//! * `POST certs` - start managing the `CertBuilder` in the body, e.g.
+//!   `{"domains": ["example.com"]}`; its file and export paths are always
+//!   chosen here, and its `tenant` must be one already configured
+//! * `GET  errors` - certificates whose last renewal failed, with the
+//!   error and the CA's problem document in `last_error`
+//! * `GET  certs/{domain}` - status of the certificate covering the domain
+//! * `POST certs/{domain}/renew` - re-issue now
+//! * `POST certs/{domain}/revoke` - revoke, then re-issue
+//! * `POST certs/{domain}/domains` - add `{"domain": ".."}` to the cert and re-issue
+//!
+//! The `certs/{domain}` endpoints answer 404 for a domain no managed
+//! certificate covers.
+
+use {
+    crate::{credentials::Secret, tenant, CertBuilder, Domain, LetsEncrypt},
+    actix_web::{web, HttpRequest, HttpResponse, Scope},
+    openssl::memcmp,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    serde_json::json,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminApi {
+    #[serde(default = "AdminApi::default_path")]
+    path: String,
+    token: Secret,
+}
+
+#[derive(Deserialize)]
+struct NewDomain {
+    domain: String,
+}
+
+impl AdminApi {
+    pub fn new<T: Into<String>>(token: T) -> Self {
+        AdminApi {
+            path: Self::default_path(),
+            token: Secret::from(token.into()),
+        }
+    }
+
+    fn default_path() -> String {
+        "/lets-encrypt/admin".to_string()
+    }
+
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    pub(crate) fn scope(&self) -> Scope {
+        web::scope(&self.path)
+            .route("/certs", web::get().to(list))
+            .route("/certs", web::post().to(add_cert))
+            .route("/errors", web::get().to(errors))
+            .route("/certs/{domain}", web::get().to(cert))
+            .route("/certs/{domain}/renew", web::post().to(renew))
+            .route("/certs/{domain}/revoke", web::post().to(revoke))
+            .route("/certs/{domain}/domains", web::post().to(add_domain))
+    }
+}
+
+fn error(mut response: actix_web::dev::HttpResponseBuilder, message: String) -> HttpResponse {
+    response.json(json!({ "error": message }))
+}
+
+// None if the request may proceed, otherwise the response to send.
+fn reject(req: &HttpRequest, enabler: &LetsEncrypt) -> Option<HttpResponse> {
+    let admin = enabler.config.admin.as_ref()?;
+    let presented = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    let token = admin.token.expose();
+    if presented.len() == token.len() && memcmp::eq(presented.as_bytes(), token.as_bytes()) {
+        None
+    } else {
+        Some(error(HttpResponse::Unauthorized(), "bad or missing token".to_string()))
+    }
+}
+
+// Bodies are taken as bytes and only parsed once the token checks out, so
+// unauthenticated callers can't make the server do more than compare it.
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, HttpResponse> {
+    serde_json::from_slice(body).map_err(|e| error(HttpResponse::BadRequest(), e.to_string()))
+}
+
+fn domain(req: &HttpRequest) -> Result<Domain, HttpResponse> {
+    Domain::new(req.match_info().query("domain"))
+        .map_err(|e| error(HttpResponse::BadRequest(), e.to_string()))
+}
+
+// The domain in the path, if a managed certificate covers it.
+fn managed_domain(req: &HttpRequest, enabler: &LetsEncrypt) -> Result<Domain, HttpResponse> {
+    let domain = domain(req)?;
+    match enabler.cert_info(&domain) {
+        Some(_) => Ok(domain),
+        None => Err(error(
+            HttpResponse::NotFound(),
+            format!("{}: not a managed domain", domain),
+        )),
+    }
+}
+
+fn accepted<E: std::fmt::Display>(result: Result<(), E>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => error(HttpResponse::ServiceUnavailable(), e.to_string()),
+    }
+}
+
+async fn list(req: HttpRequest, enabler: web::Data<LetsEncrypt>) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    HttpResponse::Ok().json(enabler.shared.status.all())
+}
+
+async fn add_cert(
+    req: HttpRequest,
+    enabler: web::Data<LetsEncrypt>,
+    body: web::Bytes,
+) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    let cert: CertBuilder = match parse(&body) {
+        Ok(cert) => cert,
+        Err(response) => return response,
+    };
+    if cert.domains.is_empty() {
+        return error(HttpResponse::BadRequest(), "no domains given".to_string());
+    }
+    if cert.key_path.is_some() || cert.cert_path.is_some() {
+        return error(
+            HttpResponse::BadRequest(),
+            "key_path and cert_path can't be set remotely".to_string(),
+        );
+    }
+    if !cert.export_paths().is_empty() {
+        return error(
+            HttpResponse::BadRequest(),
+            "export paths can't be set remotely".to_string(),
+        );
+    }
+    if let Some(name) = &cert.tenant {
+        if !tenant::valid_name(name) || enabler.tenant(&cert).is_none() {
+            return error(
+                HttpResponse::BadRequest(),
+                format!("{}: not a configured tenant", name),
+            );
+        }
+    }
+    let managed = enabler.shared.status.all();
+    if let Some(taken) = cert
+        .domains
+        .iter()
+        .find(|d| managed.iter().any(|status| status.domains.contains(d)))
+    {
+        return error(HttpResponse::Conflict(), format!("{}: already managed", taken));
+    }
+    let domains = cert.domains.clone();
+    match enabler.add_cert_runtime(cert) {
+        Ok(()) => HttpResponse::Accepted().json(json!({ "domains": domains })),
+        Err(e) => error(HttpResponse::ServiceUnavailable(), e.to_string()),
+    }
+}
+
+async fn errors(req: HttpRequest, enabler: web::Data<LetsEncrypt>) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    let failing: Vec<_> = enabler
+        .shared
+        .status
+        .all()
+        .into_iter()
+        .filter(|status| status.last_renewal.as_ref().map_or(false, |r| !r.success))
+        .collect();
+    HttpResponse::Ok().json(failing)
+}
+
+async fn cert(req: HttpRequest, enabler: web::Data<LetsEncrypt>) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    let domain = match domain(&req) {
+        Ok(domain) => domain,
+        Err(response) => return response,
+    };
+    match enabler.cert_info(&domain) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => error(HttpResponse::NotFound(), format!("{}: not a managed domain", domain)),
+    }
+}
+
+async fn renew(req: HttpRequest, enabler: web::Data<LetsEncrypt>) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    match managed_domain(&req, &enabler) {
+        Ok(domain) => accepted(enabler.force_renew(domain)),
+        Err(response) => response,
+    }
+}
+
+async fn revoke(req: HttpRequest, enabler: web::Data<LetsEncrypt>) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    match managed_domain(&req, &enabler) {
+        Ok(domain) => accepted(enabler.revoke(domain)),
+        Err(response) => response,
+    }
+}
+
+async fn add_domain(
+    req: HttpRequest,
+    enabler: web::Data<LetsEncrypt>,
+    body: web::Bytes,
+) -> HttpResponse {
+    if let Some(response) = reject(&req, &enabler) {
+        return response;
+    }
+    let existing = match managed_domain(&req, &enabler) {
+        Ok(domain) => domain,
+        Err(response) => return response,
+    };
+    let body: NewDomain = match parse(&body) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    match Domain::new(&body.domain) {
+        Ok(new) => accepted(enabler.add_domain(existing, new)),
+        Err(e) => error(HttpResponse::BadRequest(), e.to_string()),
+    }
+}
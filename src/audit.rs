@@ -0,0 +1,346 @@
+//! A tamper-evident, append-only log of certificate operations, for
+//! answering "who issued this certificate, and when".
+//!
+//! Each line of `audit.log` in the ssl directory is one JSON `AuditEntry`
+//! carrying the SHA-256 of the entry before it, so editing or removing an
+//! entry breaks the chain from that line on; `verify_audit_log` finds the
+//! first such line.  Unlike the journal, the log is never trimmed.
+//! Appends hold an `flock` on the file, so the server and the CLI can
+//! write to the same log.
+
+use {
+    crate::{error::DisplayChain, Domain, Error, LetsEncrypt},
+    chrono::{DateTime, Utc},
+    log::error,
+    openssl::{sha::sha256, x509::X509},
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::Write as _,
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        os::unix::io::AsRawFd,
+        path::{Path, PathBuf},
+    },
+};
+
+const AUDIT_FILE: &str = "audit.log";
+
+// The `prev` of the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    Issued,
+    Renewed,
+    Revoked,
+    KeyGenerated,
+}
+
+/// What set the operation in motion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditActor {
+    /// The renewal schedule.
+    Auto,
+    /// A runtime API call, from the application or the admin endpoints.
+    Api,
+    /// `lets-encrypt-cli` or another user of `LetsEncrypt::offline`.
+    Cli,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub operation: AuditOperation,
+    pub actor: AuditActor,
+    pub domains: Vec<Domain>,
+    /// The certificate's serial number, in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// `hash` of the previous entry.
+    pub prev: String,
+    /// SHA-256, in hex, of this entry serialized with an empty `hash`.
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn digest(&self) -> String {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed).expect("audit entries always serialize");
+        let mut hex = String::with_capacity(64);
+        for byte in sha256(&json).iter() {
+            write!(hex, "{:02x}", byte).unwrap();
+        }
+        hex
+    }
+}
+
+pub(crate) struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub(crate) fn new(ssl_directory: &Path) -> Self {
+        AuditLog {
+            path: ssl_directory.join(AUDIT_FILE),
+        }
+    }
+
+    fn lines(&self) -> io::Result<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(log) => Ok(log.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every readable entry, oldest first.
+    pub(crate) fn entries(&self) -> Vec<AuditEntry> {
+        self.lines()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    /// How many certificates were issued for the cert whose first domain
+    /// is `domain` since its key was last generated, or None if there's no
+    /// record of that.
+    pub(crate) fn certs_since_key_generated(&self, domain: &Domain) -> Option<usize> {
+        let mut certs = 0;
+        for entry in self
+            .entries()
+            .iter()
+            .rev()
+            .filter(|e| e.domains.first() == Some(domain))
+        {
+            match entry.operation {
+                AuditOperation::KeyGenerated => return Some(certs),
+                AuditOperation::Issued | AuditOperation::Renewed => certs += 1,
+                AuditOperation::Revoked => {}
+            }
+        }
+        None
+    }
+
+    /// The number of entries, or the first line (counting from 1) that
+    /// doesn't parse or doesn't chain onto the one before it.
+    pub(crate) fn verify(&self) -> Result<usize, Error> {
+        let lines = self.lines()?;
+        let mut prev = GENESIS.to_string();
+        for (i, line) in lines.iter().enumerate() {
+            let entry: AuditEntry =
+                serde_json::from_str(line).map_err(|_| Error::AuditLog(i + 1))?;
+            if entry.prev != prev || entry.digest() != entry.hash {
+                return Err(Error::AuditLog(i + 1));
+            }
+            prev = entry.hash;
+        }
+        Ok(lines.len())
+    }
+
+    /// Appends an entry.  Failing to write it is logged rather than failing
+    /// the operation it describes, which has already happened.
+    pub(crate) fn record(
+        &self,
+        at: DateTime<Utc>,
+        operation: AuditOperation,
+        actor: AuditActor,
+        domains: &[Domain],
+        cert_path: Option<&Path>,
+    ) {
+        if let Err(e) = self.append(at, operation, actor, domains, cert_path) {
+            error!(
+                "{}: could not write audit log: {}",
+                self.path.display(),
+                DisplayChain(&e)
+            );
+        }
+    }
+
+    fn append(
+        &self,
+        at: DateTime<Utc>,
+        operation: AuditOperation,
+        actor: AuditActor,
+        domains: &[Domain],
+        cert_path: Option<&Path>,
+    ) -> io::Result<()> {
+        // Locked from reading the last hash until the entry is written, so
+        // that another process appending to the log, such as the CLI
+        // revoking a cert, can't fork the chain.  Unlocked when dropped.
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let prev = self
+            .lines()?
+            .last()
+            .and_then(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            .map_or_else(|| GENESIS.to_string(), |entry| entry.hash);
+        let mut entry = AuditEntry {
+            at,
+            operation,
+            actor,
+            domains: domains.to_vec(),
+            serial: cert_path.and_then(serial),
+            prev,
+            hash: String::new(),
+        };
+        entry.hash = entry.digest();
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    }
+}
+
+fn serial(cert_path: &Path) -> Option<String> {
+    let cert = X509::from_pem(&fs::read(cert_path).ok()?).ok()?;
+    let serial = cert.serial_number().to_bn().ok()?.to_hex_str().ok()?;
+    Some(serial.to_string())
+}
+
+impl LetsEncrypt {
+    pub(crate) fn audit_log(&self) -> AuditLog {
+        AuditLog::new(&self.config.ssl_directory)
+    }
+
+    /// Every entry in the audit log under the ssl directory, oldest first.
+    pub fn audit_entries(&self) -> Vec<AuditEntry> {
+        self.audit_log().entries()
+    }
+
+    /// When each private key of the cert covering `domain` was generated,
+    /// oldest first, as recorded in the audit log.
+    pub fn key_generations<D: AsRef<str>>(&self, domain: D) -> Vec<DateTime<Utc>> {
+        let domain = match Domain::new(domain) {
+            Ok(domain) => domain,
+            Err(_) => return Vec::new(),
+        };
+        self.audit_entries()
+            .into_iter()
+            .filter(|e| e.operation == AuditOperation::KeyGenerated && e.domains.contains(&domain))
+            .map(|e| e.at)
+            .collect()
+    }
+
+    /// Checks the audit log's hash chain, returning the number of entries,
+    /// or `Error::AuditLog` with the first line that was altered.
+    pub fn verify_audit_log(&self) -> Result<usize, Error> {
+        self.audit_log().verify()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{env, process, thread},
+    };
+
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lets-encrypt-audit-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn domain(name: &str) -> Domain {
+        Domain::new(name).unwrap()
+    }
+
+    fn append(log: &AuditLog, name: &str, operation: AuditOperation) {
+        let domains = [domain(name)];
+        log.append(Utc::now(), operation, AuditActor::Auto, &domains, None)
+            .unwrap();
+    }
+
+    fn log_of(test: &str, operations: &[(&str, AuditOperation)]) -> AuditLog {
+        let log = AuditLog::new(&scratch_dir(test));
+        for &(name, operation) in operations {
+            append(&log, name, operation);
+        }
+        log
+    }
+
+    fn rewrite(log: &AuditLog, f: impl FnOnce(&mut Vec<String>)) {
+        let mut lines = log.lines().unwrap();
+        f(&mut lines);
+        fs::write(&log.path, lines.join("\n") + "\n").unwrap();
+    }
+
+    const THREE: [(&str, AuditOperation); 3] = [
+        ("example.com", AuditOperation::KeyGenerated),
+        ("example.com", AuditOperation::Issued),
+        ("example.com", AuditOperation::Renewed),
+    ];
+
+    #[test]
+    fn verify_counts_the_entries() {
+        assert_eq!(log_of("verify", &THREE).verify().unwrap(), 3);
+        assert_eq!(log_of("verify-empty", &[]).verify().unwrap(), 0);
+    }
+
+    #[test]
+    fn verify_finds_an_edited_entry() {
+        let log = log_of("edited", &THREE);
+        rewrite(&log, |lines| {
+            lines[1] = lines[1].replace("issued", "revoked");
+        });
+        assert!(matches!(log.verify(), Err(Error::AuditLog(2))));
+    }
+
+    #[test]
+    fn verify_finds_a_removed_entry() {
+        let log = log_of("removed", &THREE);
+        rewrite(&log, |lines| {
+            lines.remove(1);
+        });
+        assert!(matches!(log.verify(), Err(Error::AuditLog(2))));
+    }
+
+    #[test]
+    fn concurrent_appends_keep_one_chain() {
+        let dir = scratch_dir("concurrent");
+        let appenders: Vec<_> = (0..4)
+            .map(|_| {
+                let dir = dir.clone();
+                thread::spawn(move || {
+                    let log = AuditLog::new(&dir);
+                    for _ in 0..25 {
+                        append(&log, "example.com", AuditOperation::Renewed);
+                    }
+                })
+            })
+            .collect();
+        for appender in appenders {
+            appender.join().unwrap();
+        }
+        assert_eq!(AuditLog::new(&dir).verify().unwrap(), 100);
+    }
+
+    #[test]
+    fn certs_since_key_generated_counts_from_the_latest_key() {
+        let log = log_of(
+            "since-key",
+            &[
+                ("example.com", AuditOperation::KeyGenerated),
+                ("example.com", AuditOperation::Issued),
+                ("example.com", AuditOperation::KeyGenerated),
+                ("example.com", AuditOperation::Renewed),
+                ("example.org", AuditOperation::Renewed),
+                ("example.com", AuditOperation::Revoked),
+                ("example.com", AuditOperation::Renewed),
+            ],
+        );
+        let since = |name| log.certs_since_key_generated(&domain(name));
+        assert_eq!(since("example.com"), Some(2));
+        assert_eq!(since("example.org"), None);
+        assert_eq!(since("example.net"), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Offline certificate management using the same JSON configuration as
+//! `LetsEncrypt::try_from_env`.
+//!
+//! ```text
+//! lets-encrypt-cli (--config FILE | --config-env VAR) [--standalone ADDR] COMMAND
+//!
+//! Commands:
+//!     status                  show each certificate and its expiry
+//!     issue [DOMAIN]          issue certificates that are missing or due
+//!     renew [DOMAIN]          re-issue certificates now
+//!     revoke DOMAIN           revoke the certificate covering DOMAIN
+//!     migrate-store DIR       copy keys and certificates into DIR
+//!     verify-audit            check the audit log's hash chain
+//!     caa                     check each domain's CAA records
+//!     healthcheck [REMAINING] check that the running server serves the
+//!                             stored certificates, with at least REMAINING
+//!                             (default 7d) left before they expire
+//!     dev-ca                  print the development CA's root certificate,
+//!                             creating the CA if needed
+//! ```
+//!
+//! `--standalone ADDR` serves the HTTP challenges from ADDR (e.g.
+//! `0.0.0.0:80`) while issuing, for use before the application is deployed.
+//!
+//! `--key-passphrase prompt` asks on the terminal for the passphrase of any
+//! encrypted key file, and `--key-passphrase env:VAR` reads it from VAR.
+//!
+//! `healthcheck` exits with status 1 when any listener is unhealthy, so
+//! that it can be used as is in a Dockerfile:
+//!
+//! ```text
+//! HEALTHCHECK CMD lets-encrypt-cli --config /etc/lets-encrypt.json healthcheck
+//! ```
+
+use {
+    actix_web::{rt::System, App, HttpServer},
+    actix_web_lets_encrypt::{CaaStatus, Domain, KeyPassphrase, LetsEncrypt},
+    std::{env, path::Path, process, thread, time::Duration},
+};
+
+const USAGE: &str = "usage: lets-encrypt-cli (--config FILE | --config-env VAR) \
+                     [--standalone ADDR] [--key-passphrase (prompt | env:VAR)] (status | issue [DOMAIN] | renew [DOMAIN] | \
+                     revoke DOMAIN | migrate-store DIR | verify-audit | caa | healthcheck [REMAINING] | dev-ca)";
+
+const DEFAULT_MIN_REMAINING: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn fail<E: std::fmt::Display>(e: E) -> ! {
+    eprintln!("lets-encrypt-cli: {}", e);
+    process::exit(1);
+}
+
+fn domain(arg: Option<&String>) -> Option<Domain> {
+    arg.map(|d| Domain::new(d).unwrap_or_else(|e| fail(e)))
+}
+
+fn key_passphrase(source: &str) -> KeyPassphrase {
+    match source {
+        "prompt" => KeyPassphrase::callback(|path| {
+            let prompt = format!("passphrase for {}: ", path.display());
+            Ok(rpassword::prompt_password_stderr(&prompt)?)
+        }),
+        _ => match source.strip_prefix("env:") {
+            Some(var) => KeyPassphrase::env(var),
+            None => fail(USAGE),
+        },
+    }
+}
+
+fn serve_challenges(enabler: &LetsEncrypt, addr: String) {
+    let enabler = enabler.clone();
+    thread::spawn(move || {
+        let system = System::new("lets-encrypt-cli");
+        let server = HttpServer::new(move || enabler.register(App::new()))
+            .bind(&addr)
+            .unwrap_or_else(|e| fail(format!("{}: {}", addr, e)))
+            .run();
+        drop(server);
+        system.run().unwrap_or_else(|e| fail(e));
+    });
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut enabler = None;
+    let mut standalone = None;
+    let mut passphrase = None;
+    while args.len() >= 2 && args[0].starts_with("--") {
+        let value = args.remove(1);
+        match args.remove(0).as_str() {
+            "--config" => enabler = Some(LetsEncrypt::try_from_file(&value)),
+            "--config-env" => enabler = Some(LetsEncrypt::try_from_env(&value)),
+            "--standalone" => standalone = Some(value),
+            "--key-passphrase" => passphrase = Some(key_passphrase(&value)),
+            _ => fail(USAGE),
+        }
+    }
+    let mut enabler = match enabler {
+        Some(enabler) => enabler.unwrap_or_else(|e| fail(e)),
+        None => fail(USAGE),
+    };
+    if let Some(passphrase) = passphrase {
+        enabler.set_key_passphrase(passphrase);
+    }
+    if let Some(addr) = standalone {
+        serve_challenges(&enabler, addr);
+    }
+
+    let offline = enabler.offline();
+    match (args.get(0).map(String::as_str), args.get(1)) {
+        (Some("status"), None) => {
+            for status in offline.status() {
+                let domains: Vec<&str> = status.domains.iter().map(Domain::as_str).collect();
+                let expiry = status
+                    .not_after
+                    .map_or_else(|| "missing".to_string(), |not_after| not_after.to_string());
+                println!("{}\t{}", domains.join(","), expiry);
+            }
+        }
+        (Some(command @ "issue"), domain_arg) | (Some(command @ "renew"), domain_arg) => {
+            let force = command == "renew";
+            let issued = offline
+                .issue(domain(domain_arg).as_ref(), force)
+                .unwrap_or_else(|e| fail(e));
+            for domain in issued {
+                println!("issued {}", domain);
+            }
+        }
+        (Some("revoke"), Some(domain_arg)) => {
+            let domain = domain(Some(domain_arg)).unwrap();
+            offline.revoke(&domain).unwrap_or_else(|e| fail(e));
+            println!("revoked {}", domain);
+        }
+        (Some("migrate-store"), Some(dir)) => {
+            for path in offline.migrate(Path::new(dir)).unwrap_or_else(|e| fail(e)) {
+                println!("wrote {}", path.display());
+            }
+        }
+        (Some("verify-audit"), None) => {
+            let entries = enabler.verify_audit_log().unwrap_or_else(|e| fail(e));
+            println!("audit log intact, {} entries", entries);
+        }
+        (Some("caa"), None) => {
+            for check in enabler.check_caa().unwrap_or_else(|e| fail(e)) {
+                match check.status {
+                    CaaStatus::Unrestricted => {
+                        println!("{}\tno CAA records, any CA may issue", check.domain);
+                        println!("\tto restrict issuance to Let's Encrypt, add: {}", check.record);
+                    }
+                    CaaStatus::Permitted { at } => {
+                        println!("{}\tpermitted by the records at {}", check.domain, at);
+                    }
+                    CaaStatus::Forbidden { at, issuers } => {
+                        println!(
+                            "{}\tthe records at {} only allow {}",
+                            check.domain,
+                            at,
+                            issuers.join(", ")
+                        );
+                        println!("\tadd: {}", check.record);
+                    }
+                }
+            }
+        }
+        (Some("healthcheck"), min_remaining) => {
+            let min_remaining = min_remaining.map_or(DEFAULT_MIN_REMAINING, |arg| {
+                humantime::parse_duration(arg).unwrap_or_else(|e| fail(format!("{}: {}", arg, e)))
+            });
+            let unhealthy = offline.healthcheck(min_remaining);
+            if !unhealthy.is_empty() {
+                for unhealthy in unhealthy {
+                    eprintln!("{}", unhealthy);
+                }
+                process::exit(1);
+            }
+            println!("healthy");
+        }
+        (Some("dev-ca"), None) => {
+            let root = enabler.dev_ca_root().unwrap_or_else(|e| fail(e));
+            let pem = root.to_pem().unwrap_or_else(|e| fail(e));
+            print!("{}", String::from_utf8_lossy(&pem));
+            eprintln!("stored in {}", enabler.dev_ca_root_path().display());
+        }
+        _ => fail(USAGE),
+    }
+}
@@ -0,0 +1,215 @@
+//! Checking the domains' CAA records before ordering.
+//!
+//! CAA records name the CAs allowed to issue for a domain and everything
+//! below it.  Names without any are open to every CA, but once some CA is
+//! listed, orders from any other fail validation; a common surprise after
+//! switching CAs.  `LetsEncrypt::check_caa` reports where each domain
+//! stands, and with `require_caa` orders are refused until the records
+//! explicitly allow Let's Encrypt.
+
+use {
+    crate::{error::DisplayChain, Domain, Error, LetsEncrypt},
+    log::warn,
+    serde::Serialize,
+    trust_dns_resolver::{
+        error::{ResolveError, ResolveErrorKind},
+        proto::rr::{
+            rdata::caa::{Property, Value, CAA},
+            RData, RecordType,
+        },
+        Resolver,
+    },
+};
+
+/// The issuer domain Let's Encrypt recognizes in CAA records.
+pub const CAA_IDENTITY: &str = "letsencrypt.org";
+
+/// Whether a domain's CAA records let Let's Encrypt issue for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CaaStatus {
+    /// Neither the name nor its parents have CAA records, so any CA may
+    /// issue.
+    Unrestricted,
+    /// The records at `at` list Let's Encrypt.
+    Permitted { at: String },
+    /// The records at `at` only list `issuers`, so orders will fail.  No
+    /// issuers means `issue ";"`, which allows no CA at all.
+    Forbidden { at: String, issuers: Vec<String> },
+}
+
+/// The CAA status of one domain, with the record that would permit Let's
+/// Encrypt.
+#[derive(Clone, Debug, Serialize)]
+pub struct CaaCheck {
+    pub domain: Domain,
+    pub status: CaaStatus,
+    pub record: String,
+}
+
+/// The zone file line that lets Let's Encrypt issue for `domain`, e.g.
+/// `example.com. IN CAA 0 issue "letsencrypt.org"`.  Wildcards get an
+/// `issuewild` record on their parent.
+pub(crate) fn record(domain: &Domain) -> String {
+    let (name, tag) = match domain.as_str().strip_prefix("*.") {
+        Some(parent) => (parent, "issuewild"),
+        None => (domain.as_str(), "issue"),
+    };
+    format!("{}. IN CAA 0 {} \"{}\"", name, tag, CAA_IDENTITY)
+}
+
+// The CAA record set at exactly `name`.
+fn lookup(resolver: &Resolver, name: &str) -> Result<Vec<CAA>, ResolveError> {
+    match resolver.lookup(name, RecordType::CAA) {
+        Ok(lookup) => Ok(lookup
+            .iter()
+            .filter_map(|rdata| match rdata {
+                RData::CAA(caa) => Some(caa.clone()),
+                _ => None,
+            })
+            .collect()),
+        Err(e) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(Vec::new()),
+            _ => Err(e),
+        },
+    }
+}
+
+// The issuer domains of the properties governing the name; None is
+// `issue ";"`, which forbids every CA.
+fn issuers(records: &[CAA], wildcard: bool) -> Vec<Option<String>> {
+    // Wildcards are governed by issuewild when present, by issue otherwise.
+    let tag = if wildcard && records.iter().any(|caa| *caa.tag() == Property::IssueWild) {
+        Property::IssueWild
+    } else {
+        Property::Issue
+    };
+    records
+        .iter()
+        .filter(|caa| *caa.tag() == tag)
+        .filter_map(|caa| match caa.value() {
+            Value::Issuer(issuer, _) => Some(
+                issuer
+                    .as_ref()
+                    .map(|name| name.to_ascii().trim_end_matches('.').to_lowercase()),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+fn status(resolver: &Resolver, domain: &Domain) -> Result<CaaStatus, ResolveError> {
+    let mut name = domain.as_str().trim_start_matches("*.");
+    // The closest name with a CAA record set decides.
+    let records = loop {
+        let records = lookup(resolver, name)?;
+        if !records.is_empty() {
+            break records;
+        }
+        match name.find('.') {
+            Some(dot) => name = &name[dot + 1..],
+            None => return Ok(CaaStatus::Unrestricted),
+        }
+    };
+    let issuers = issuers(&records, domain.is_wildcard());
+    let at = name.to_string();
+    // A record set without issue properties restricts nothing.
+    if issuers.is_empty() {
+        Ok(CaaStatus::Unrestricted)
+    } else if issuers
+        .iter()
+        .any(|issuer| issuer.as_deref() == Some(CAA_IDENTITY))
+    {
+        Ok(CaaStatus::Permitted { at })
+    } else {
+        let issuers = issuers.into_iter().flatten().collect();
+        Ok(CaaStatus::Forbidden { at, issuers })
+    }
+}
+
+fn check(resolver: &Resolver, domain: &Domain) -> Result<CaaCheck, Error> {
+    let status = status(resolver, domain).map_err(|e| Error::Caa(domain.clone(), e.to_string()))?;
+    Ok(CaaCheck {
+        domain: domain.clone(),
+        status,
+        record: record(domain),
+    })
+}
+
+fn resolver(domain: &Domain) -> Result<Resolver, Error> {
+    Resolver::from_system_conf().map_err(|e| Error::Caa(domain.clone(), e.to_string()))
+}
+
+impl LetsEncrypt {
+    /// Looks up the CAA records of every managed domain.
+    pub fn check_caa(&self) -> Result<Vec<CaaCheck>, Error> {
+        let domains: Vec<&Domain> = self
+            .config
+            .cert_builders
+            .iter()
+            .flat_map(|c| &c.domains)
+            .collect();
+        let resolver = match domains.first() {
+            Some(domain) => resolver(domain)?,
+            None => return Ok(Vec::new()),
+        };
+        domains
+            .into_iter()
+            .map(|domain| check(&resolver, domain))
+            .collect()
+    }
+
+    // Run before each order.  Domains the CA would refuse are only warned
+    // about, since the order fails with the CA's own explanation anyway,
+    // unless `require_caa` is set, in which case every domain needs a
+    // record permitting Let's Encrypt.
+    pub(crate) fn check_caa_before_order(&self, domains: &[Domain]) -> Result<(), Error> {
+        let checks = resolver(&domains[0]).and_then(|resolver| {
+            domains
+                .iter()
+                .map(|domain| check(&resolver, domain))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let checks = match checks {
+            Ok(checks) => checks,
+            Err(e) if self.config.require_caa => return Err(e),
+            Err(e) => {
+                warn!("could not check CAA records: {}", DisplayChain(&e));
+                return Ok(());
+            }
+        };
+        for check in checks {
+            let domain = &check.domain;
+            match (&check.status, self.config.require_caa) {
+                (CaaStatus::Permitted { .. }, _) | (CaaStatus::Unrestricted, false) => {}
+                (CaaStatus::Forbidden { at, issuers }, false) => warn!(
+                    "{}: CAA records at {} only allow {}, add: {}",
+                    domain,
+                    at,
+                    if issuers.is_empty() {
+                        "no CA".to_string()
+                    } else {
+                        issuers.join(", ")
+                    },
+                    check.record
+                ),
+                (CaaStatus::Unrestricted, true) => {
+                    return Err(Error::Caa(
+                        domain.clone(),
+                        format!("no CAA records, add: {}", check.record),
+                    ))
+                }
+                (CaaStatus::Forbidden { at, .. }, true) => {
+                    return Err(Error::Caa(
+                        domain.clone(),
+                        format!(
+                            "the CAA records at {} don't allow {}, add: {}",
+                            at, CAA_IDENTITY, check.record
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Certbot's directory structure, for `Layout::Certbot`.
+//!
+//! With the ssl directory pointed at `/etc/letsencrypt`, the crate takes
+//! over a host's certbot state in place: every issuance writes a new
+//! numbered version under `archive/<domain>/` and repoints the symlinks in
+//! `live/<domain>/` at it, and `renewal/<domain>.conf` is written for
+//! lineages that don't have one, so `certbot certificates` and anything
+//! configured with the `live` paths keep working.  Lineages are named after
+//! a cert's first domain.  Without an `account.key` of its own, the
+//! enabler adopts the RSA key of certbot's account with the ACME server
+//! it uses, from `accounts/`; certbot's ACME v2 accounts are left alone,
+//! since the crate speaks ACME v1.
+
+use {
+    crate::{error::DisplayChain, CertBuilder, Domain, Error, LetsEncrypt},
+    log::{info, warn},
+    openssl::{
+        base64,
+        bn::BigNum,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        x509::X509,
+    },
+    serde::Deserialize,
+    std::{
+        fs, io,
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+    },
+};
+
+// The files of a lineage, each a symlink in `live` to its latest version in
+// `archive`.
+const LINKS: [&str; 4] = ["cert", "chain", "fullchain", "privkey"];
+
+impl LetsEncrypt {
+    fn lineage_archive(&self, domain: &Domain) -> PathBuf {
+        self.config
+            .ssl_directory
+            .join("archive")
+            .join(domain.as_str())
+    }
+
+    // The highest version number in the lineage's archive, 0 if none.
+    fn latest_version(archive: &Path) -> u32 {
+        fs::read_dir(archive)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        let name = entry.file_name().into_string().ok()?;
+                        name.strip_prefix("fullchain")?
+                            .strip_suffix(".pem")?
+                            .parse()
+                            .ok()
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0)
+    }
+
+    // Writes the next version of the lineage, from the bundled `chain`
+    // (leaf first), and points `live` at it.
+    // Without `key`, when keys are only held in memory, the `privkey`
+    // link is left alone.
+    pub(crate) fn store_certbot_lineage(
+        &self,
+        cert_builder: &CertBuilder,
+        chain: &[X509],
+        key: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let domain = &cert_builder.domains[0];
+        let archive = self.lineage_archive(domain);
+        let live = cert_builder.cert_path.as_ref().unwrap().parent().unwrap();
+        fs::create_dir_all(&archive)?;
+        fs::create_dir_all(live)?;
+        let version = Self::latest_version(&archive) + 1;
+        let file = |name: &str| archive.join(format!("{}{}.pem", name, version));
+
+        let leaf = chain[0].to_pem()?;
+        let mut intermediates = Vec::new();
+        for cert in &chain[1..] {
+            intermediates.extend(cert.to_pem()?);
+        }
+        let chain = intermediates;
+        fs::write(file("cert"), &leaf)?;
+        fs::write(file("chain"), &chain)?;
+        fs::write(file("fullchain"), [&leaf[..], &chain[..]].concat())?;
+        if let Some(key) = key {
+            self.write_private_key(&file("privkey"), key, 0o600)?;
+        }
+
+        for name in LINKS
+            .iter()
+            .filter(|name| key.is_some() || **name != "privkey")
+        {
+            let link = live.join(format!("{}.pem", name));
+            let target = Path::new("../../archive")
+                .join(domain.as_str())
+                .join(format!("{}{}.pem", name, version));
+            // Renaming over the old link swaps it atomically.
+            let temporary = live.join(format!(".{}.pem.new", name));
+            let _ = fs::remove_file(&temporary);
+            symlink(&target, &temporary)?;
+            fs::rename(&temporary, &link)?;
+        }
+        self.write_renewal_conf(cert_builder, &archive, live)?;
+        info!(
+            "{}: stored version {} of the certbot lineage",
+            domain, version
+        );
+        Ok(())
+    }
+
+    // Certbot's own configuration is left alone if it's there.
+    fn write_renewal_conf(
+        &self,
+        cert_builder: &CertBuilder,
+        archive: &Path,
+        live: &Path,
+    ) -> Result<(), Error> {
+        let renewal = self.config.ssl_directory.join("renewal");
+        let conf = renewal.join(format!("{}.conf", cert_builder.domains[0]));
+        if conf.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&renewal)?;
+        let mut contents = format!(
+            "# Written by actix-web-lets-encrypt, which renews this lineage.\n\
+             archive_dir = {}\n",
+            archive.display()
+        );
+        for name in &LINKS {
+            contents.push_str(&format!(
+                "{} = {}\n",
+                name,
+                live.join(format!("{}.pem", name)).display()
+            ));
+        }
+        contents.push_str(&format!(
+            "\n[renewalparams]\nauthenticator = webroot\nwebroot_path = {},\nserver = {}\n",
+            self.config.nonce_directory.display(),
+            self.directory_url(cert_builder)
+        ));
+        fs::write(conf, contents)?;
+        Ok(())
+    }
+
+    // Certbot's account key with `cert_builder`'s ACME server, whose
+    // accounts certbot keeps under the directory URL minus its scheme.
+    pub(crate) fn certbot_account_key(&self, cert_builder: &CertBuilder) -> Option<PKey<Private>> {
+        let url = self.directory_url(cert_builder);
+        let server = url.splitn(2, "://").nth(1)?;
+        let ids = fs::read_dir(self.config.ssl_directory.join("accounts").join(server)).ok()?;
+        for id in ids.filter_map(|entry| entry.ok()) {
+            let path = id.path().join("private_key.json");
+            match fs::read(&path)
+                .map_err(Error::from)
+                .and_then(|json| jwk_to_key(&json))
+            {
+                Ok(key) => {
+                    info!("adopting certbot's account key {}", path.display());
+                    return Some(key);
+                }
+                Err(e) => warn!(
+                    "{}: not a usable account key: {}",
+                    path.display(),
+                    DisplayChain(&e)
+                ),
+            }
+        }
+        None
+    }
+}
+
+// Certbot stores the account key as an RSA JWK.
+#[derive(Deserialize)]
+struct RsaJwk {
+    n: String,
+    e: String,
+    d: String,
+    p: String,
+    q: String,
+    dp: String,
+    dq: String,
+    qi: String,
+}
+
+fn jwk_to_key(json: &[u8]) -> Result<PKey<Private>, Error> {
+    let jwk: RsaJwk =
+        serde_json::from_slice(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let number = |b64url: &str| -> Result<BigNum, Error> {
+        let mut b64 = b64url.replace('-', "+").replace('_', "/");
+        while b64.len() % 4 != 0 {
+            b64.push('=');
+        }
+        Ok(BigNum::from_slice(&base64::decode_block(&b64)?)?)
+    };
+    let rsa = Rsa::from_private_components(
+        number(&jwk.n)?,
+        number(&jwk.e)?,
+        number(&jwk.d)?,
+        number(&jwk.p)?,
+        number(&jwk.q)?,
+        number(&jwk.dp)?,
+        number(&jwk.dq)?,
+        number(&jwk.qi)?,
+    )?;
+    Ok(PKey::from_rsa(rsa)?)
+}
@@ -0,0 +1,156 @@
+//! The managed certificates as rustls `CertifiedKey`s, for QUIC stacks
+//! such as quinn, and for in-process servers built on rustls, such as a
+//! tonic gRPC endpoint, that can't use an openssl acceptor.
+//!
+//! Each certificate is converted whenever it is installed, so a
+//! `CertifiedKeyHandle`, or the resolver of a `rustls_config`, always
+//! yields the one being served over TCP, renewals included.  Enabled by
+//! the `rustls` feature.
+
+use {
+    crate::{error::DisplayChain, CertBuilder, Domain, Error, LetsEncrypt},
+    log::warn,
+    openssl::{
+        pkey::{PKey, Private},
+        x509::X509,
+    },
+    rustls::{sign, ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig},
+    std::{
+        collections::HashMap,
+        fs, io,
+        sync::{Arc, RwLock},
+    },
+};
+
+#[derive(Clone, Default)]
+pub(crate) struct CertifiedKeys(Arc<RwLock<HashMap<Domain, Arc<sign::CertifiedKey>>>>);
+
+impl CertifiedKeys {
+    fn insert(&self, domains: &[Domain], key: Arc<sign::CertifiedKey>) {
+        let mut keys = self.0.write().unwrap();
+        for domain in domains {
+            keys.insert(domain.clone(), key.clone());
+        }
+    }
+
+    pub(crate) fn remove(&self, domains: &[Domain]) {
+        let mut keys = self.0.write().unwrap();
+        for domain in domains {
+            keys.remove(domain);
+        }
+    }
+
+    // The key for `domain` itself, else for its wildcard parent.
+    pub(crate) fn lookup(&self, domain: &Domain) -> Option<Arc<sign::CertifiedKey>> {
+        let keys = self.0.read().unwrap();
+        keys.get(domain)
+            .or_else(|| keys.get(&domain.wildcard_parent()?))
+            .cloned()
+    }
+}
+
+fn certified_key(chain: &[X509], key: &PKey<Private>) -> Result<sign::CertifiedKey, Error> {
+    let mut certs = Vec::new();
+    for cert in chain {
+        certs.push(rustls::Certificate(cert.to_der()?));
+    }
+    let key = rustls::PrivateKey(key.private_key_to_pkcs8()?);
+    let key = sign::any_supported_type(&key).map_err(|()| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "key type not supported by rustls",
+        )
+    })?;
+    Ok(sign::CertifiedKey::new(certs, Arc::new(key)))
+}
+
+/// The current certificate and key for a domain, following renewals.
+#[derive(Clone)]
+pub struct CertifiedKeyHandle {
+    keys: CertifiedKeys,
+    domain: Domain,
+}
+
+impl CertifiedKeyHandle {
+    /// None until the certificate has been issued and installed.
+    pub fn current(&self) -> Option<Arc<sign::CertifiedKey>> {
+        self.keys.lookup(&self.domain)
+    }
+}
+
+/// Presents the handle's certificate to every client, whatever name it
+/// asks for, e.g. as quinn's `ServerConfig` certificate resolver.
+impl ResolvesServerCert for CertifiedKeyHandle {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        self.current().map(|key| (*key).clone())
+    }
+}
+
+// Picks the certificate by SNI, like the openssl listeners do.
+struct SniResolver {
+    keys: CertifiedKeys,
+    default: Domain,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        client_hello
+            .server_name()
+            .and_then(|name| Domain::new(<&str>::from(name)).ok())
+            .and_then(|domain| self.keys.lookup(&domain))
+            .or_else(|| self.keys.lookup(&self.default))
+            .map(|key| (*key).clone())
+    }
+}
+
+impl LetsEncrypt {
+    /// A rustls server configuration presenting the managed certificates
+    /// by SNI, and the one covering `domain` to clients without SNI.  It
+    /// offers `h2` and `http/1.1` by ALPN, as gRPC needs; clone the
+    /// configuration to change that.
+    pub fn rustls_config<D: AsRef<str>>(&self, domain: D) -> Result<Arc<ServerConfig>, Error> {
+        let domain = Domain::new(domain)?;
+        if !self
+            .config
+            .cert_builders
+            .iter()
+            .any(|c| c.domains.contains(&domain))
+        {
+            return Err(Error::NotManaged(domain));
+        }
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = Arc::new(SniResolver {
+            keys: self.shared.certified.clone(),
+            default: domain,
+        });
+        config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        Ok(Arc::new(config))
+    }
+
+    /// A handle on the certificate covering `domain`, as a rustls
+    /// `CertifiedKey`.
+    pub fn certified_key<D: AsRef<str>>(&self, domain: D) -> Result<CertifiedKeyHandle, Error> {
+        Ok(CertifiedKeyHandle {
+            keys: self.shared.certified.clone(),
+            domain: Domain::new(domain)?,
+        })
+    }
+
+    pub(crate) fn install_certified_key(&self, cert_builder: &CertBuilder) {
+        let converted = fs::read(cert_builder.cert_path.as_ref().unwrap())
+            .map_err(Error::from)
+            .and_then(|pem| Ok(X509::stack_from_pem(&pem)?))
+            .and_then(|chain| certified_key(&chain, &self.load_private_key(cert_builder)?));
+        match converted {
+            Ok(key) => self
+                .shared
+                .certified
+                .insert(&cert_builder.domains, Arc::new(key)),
+            Err(e) => warn!(
+                "{}: could not convert the certificate for rustls: {}",
+                cert_builder.domains[0],
+                DisplayChain(&e)
+            ),
+        }
+    }
+}
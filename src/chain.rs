@@ -0,0 +1,117 @@
+//! What goes into the stored, and served, certificate chain.
+//!
+//! Most clients want the certificate and its intermediates.  Some legacy
+//! ones only validate with the root appended, and some break when they
+//! see a cross-signed intermediate, i.e. a root certificate re-issued by
+//! an older root, whose issuer they don't trust or which has expired.
+//! Roots are looked up in `chain_roots`, a PEM bundle defaulting to the
+//! system's.
+
+use {
+    crate::{error::DisplayChain, CertBuilder, Error, LetsEncrypt},
+    acme_client::SignedCertificate,
+    log::warn,
+    openssl::x509::X509,
+    serde::{Deserialize, Serialize},
+    std::{fs, path::PathBuf},
+};
+
+const SYSTEM_ROOTS: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainBundle {
+    /// The certificate alone.
+    Leaf,
+    /// The certificate and its intermediates.
+    Intermediates,
+    /// The certificate and its intermediates, less any cross-signed one.
+    WithoutCrossSigned,
+    /// The certificate, its intermediates and the root, the self-signed
+    /// root taking the place of a cross-signed intermediate.
+    WithRoot,
+}
+
+impl Default for ChainBundle {
+    fn default() -> Self {
+        ChainBundle::Intermediates
+    }
+}
+
+impl LetsEncrypt {
+    fn default_chain_roots() -> PathBuf {
+        PathBuf::from(SYSTEM_ROOTS)
+    }
+
+    fn chain_roots(&self) -> Vec<X509> {
+        let path = self
+            .config
+            .chain_roots
+            .clone()
+            .unwrap_or_else(Self::default_chain_roots);
+        match fs::read(&path)
+            .map_err(Error::from)
+            .and_then(|pem| Ok(X509::stack_from_pem(&pem)?))
+        {
+            Ok(roots) => roots,
+            Err(e) => {
+                warn!(
+                    "could not read the roots in {}: {}",
+                    path.display(),
+                    DisplayChain(&e)
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// The chain to store for a newly signed certificate, leaf first.
+    pub(crate) fn bundle_chain(
+        &self,
+        cert_builder: &CertBuilder,
+        cert: &SignedCertificate,
+    ) -> Result<Vec<X509>, Error> {
+        let bundle = cert_builder.resolved_chain();
+        let mut chain = vec![cert.cert().clone()];
+        if bundle == ChainBundle::Leaf {
+            return Ok(chain);
+        }
+        chain.push(cert.get_intermediate_certificate(self.config.intermediate_url.as_deref())?);
+        if bundle == ChainBundle::Intermediates {
+            return Ok(chain);
+        }
+
+        let roots = self.chain_roots();
+        // A cross-sign has the same subject and key as a root.
+        let is_cross_signed = |cert: &X509| {
+            roots.iter().any(|root| {
+                root.subject_name().to_der().ok() == cert.subject_name().to_der().ok()
+                    && root
+                        .public_key()
+                        .ok()
+                        .map(|key| key.public_key_to_der().ok())
+                        == cert
+                            .public_key()
+                            .ok()
+                            .map(|key| key.public_key_to_der().ok())
+            })
+        };
+        chain.retain(|cert| !is_cross_signed(cert));
+        if bundle == ChainBundle::WithRoot {
+            let last = chain.last().unwrap();
+            let root = roots.iter().find(|root| {
+                root.public_key()
+                    .and_then(|key| last.verify(&key))
+                    .unwrap_or(false)
+            });
+            match root {
+                Some(root) => chain.push(root.clone()),
+                None => warn!(
+                    "{}: no root in the chain roots issued the chain, storing it without",
+                    cert_builder.domains[0]
+                ),
+            }
+        }
+        Ok(chain)
+    }
+}
@@ -0,0 +1,60 @@
+//! Slack and Discord notifications via their incoming webhooks.
+
+use {
+    crate::Event,
+    log::{debug, warn},
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    std::future::Future,
+};
+
+/// A chat channel that is told about issuance, renewal, failures and
+/// impending expiry.
+///
+/// In the JSON configuration this is written as
+/// `{"kind":"slack","webhook_url":"https://hooks.slack.com/..."}` or
+/// `{"kind":"discord","webhook_url":"https://discord.com/api/webhooks/..."}`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChatNotifier {
+    Slack { webhook_url: String },
+    Discord { webhook_url: String },
+}
+
+impl ChatNotifier {
+    pub fn slack<U: Into<String>>(webhook_url: U) -> Self {
+        ChatNotifier::Slack {
+            webhook_url: webhook_url.into(),
+        }
+    }
+
+    pub fn discord<U: Into<String>>(webhook_url: U) -> Self {
+        ChatNotifier::Discord {
+            webhook_url: webhook_url.into(),
+        }
+    }
+
+    pub(crate) fn send(&self, event: &Event) -> impl Future<Output = ()> {
+        let text = event.summary();
+        let (url, payload) = match self {
+            ChatNotifier::Slack { webhook_url } => (webhook_url.clone(), json!({ "text": text })),
+            ChatNotifier::Discord { webhook_url } => {
+                (webhook_url.clone(), json!({ "content": text }))
+            }
+        };
+        let domain = event.domain().clone();
+        async move {
+            match awc::Client::default().post(&url).send_json(&payload).await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("{}: posted chat notification", domain)
+                }
+                Ok(response) => warn!(
+                    "{}: chat webhook answered {}",
+                    domain,
+                    response.status()
+                ),
+                Err(e) => warn!("{}: chat webhook failed: {}", domain, e),
+            }
+        }
+    }
+}
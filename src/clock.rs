@@ -0,0 +1,45 @@
+//! Where the enabler gets the current time from.
+//!
+//! Whether a certificate is due, how long a failed one backs off, and when
+//! the next scheduled check falls are all worked out from `Clock::now`, so
+//! a test can move time forward instead of waiting for it.  The checks
+//! themselves still wait on real timers; after advancing the clock, call
+//! `Offline::issue` or `LetsEncrypt::renew` to see what's due then.
+
+use {
+    crate::LetsEncrypt,
+    chrono::{DateTime, Utc},
+};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's time, used unless the enabler is given another clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Any closure returning the time is a clock.
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc> + Send + Sync,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
+
+impl LetsEncrypt {
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        match &self.config.clock {
+            Some(clock) => clock.now(),
+            None => Utc::now(),
+        }
+    }
+}
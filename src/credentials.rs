@@ -0,0 +1,107 @@
+//! Secrets handed over by systemd's `LoadCredential=`.
+//!
+//! Under systemd, a service's credentials are files in
+//! `$CREDENTIALS_DIRECTORY` that only the service can read.  The ACME
+//! account key is taken from the `account.key` credential (or
+//! `<tenant>.account.key` for a tenant) when there is one, and any secret
+//! in the JSON configuration can be written as `{"credential": "name"}`
+//! instead of inline, e.g. `"admin": {"token": {"credential":
+//! "admin-token"}}`.  Such a secret is serialized back as the same
+//! reference, so `to_json` never writes out what the file held.
+
+use {
+    serde::{Deserialize, Serialize, Serializer},
+    std::{
+        convert::TryFrom,
+        env, fmt, fs,
+        path::{Path, PathBuf},
+    },
+};
+
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// The directory systemd put the service's credentials in, if any.
+pub(crate) fn directory() -> Option<PathBuf> {
+    env::var_os(CREDENTIALS_DIRECTORY).map(PathBuf::from)
+}
+
+/// The file of credential `name`, if systemd passed one.
+pub(crate) fn path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(&directory()?).join(name);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Source {
+    Inline(String),
+    Credential { credential: String },
+}
+
+/// A secret given inline or as `{"credential": "name"}`.  It remembers the
+/// credential it was read from, to serialize as, and its `Debug` never
+/// shows the secret itself.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "Source")]
+pub(crate) struct Secret {
+    value: String,
+    credential: Option<String>,
+}
+
+impl Secret {
+    pub(crate) fn expose(&self) -> &str {
+        &self.value
+    }
+}
+
+impl TryFrom<Source> for Secret {
+    type Error = String;
+
+    fn try_from(source: Source) -> Result<Self, String> {
+        let name = match source {
+            Source::Inline(value) => return Ok(Secret::from(value)),
+            Source::Credential { credential } => credential,
+        };
+        let path = path(&name)
+            .ok_or_else(|| format!("no credential {} in ${}", name, CREDENTIALS_DIRECTORY))?;
+        let secret = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Secret {
+            value: secret.trim_end_matches(&['\r', '\n'][..]).to_string(),
+            credential: Some(name),
+        })
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret {
+            value,
+            credential: None,
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.credential {
+            Some(credential) => Source::Credential {
+                credential: credential.clone(),
+            }
+            .serialize(serializer),
+            None => self.value.serialize(serializer),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.credential {
+            Some(credential) => write!(f, "Secret {{ credential: {:?} }}", credential),
+            None => f.write_str("Secret(<redacted>)"),
+        }
+    }
+}
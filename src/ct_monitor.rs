@@ -0,0 +1,221 @@
+//! Watching Certificate Transparency logs for certificates that someone
+//! else got issued for the managed domains.
+//!
+//! Every `interval`, each managed domain is looked up through a CT search
+//! service (crt.sh by default).  Certificates whose serial doesn't appear
+//! in the audit log, and isn't the one being served, raise an
+//! `Event::UnexpectedCertificate`.  The newest log entry seen per domain is
+//! kept in `ct-monitor.json` in the ssl directory; the first lookup of a
+//! domain only records it, so existing certificates don't raise alerts.
+
+use {
+    crate::{
+        serde_duration, AuditOperation, CertBuilder, Domain, Event, LetsEncrypt, LifecycleEvent,
+    },
+    actix::prelude::*,
+    log::{debug, error, warn},
+    openssl::x509::X509,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, fs, path::PathBuf, time::Duration},
+};
+
+const STATE_FILE: &str = "ct-monitor.json";
+
+// crt.sh answers can be large for popular domains.
+const MAX_RESPONSE: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CtMonitor {
+    #[serde(default = "CtMonitor::default_interval", with = "serde_duration")]
+    interval: Duration,
+    /// The domain is appended to this.
+    #[serde(default = "CtMonitor::default_search_url")]
+    search_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CtEntry {
+    id: u64,
+    issuer_name: String,
+    serial_number: String,
+}
+
+impl Default for CtMonitor {
+    fn default() -> Self {
+        CtMonitor {
+            interval: Self::default_interval(),
+            search_url: Self::default_search_url(),
+        }
+    }
+}
+
+impl CtMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(6 * 60 * 60)
+    }
+
+    fn default_search_url() -> String {
+        "https://crt.sh/?output=json&exclude=expired&q=".to_string()
+    }
+
+    pub fn interval(mut self, interval: &Duration) -> Self {
+        self.interval = *interval;
+        self
+    }
+
+    /// A crt.sh compatible JSON search endpoint, which the domain is
+    /// appended to.
+    pub fn search_url<U: Into<String>>(mut self, url: U) -> Self {
+        self.search_url = url.into();
+        self
+    }
+}
+
+async fn search(url: String) -> Result<Vec<CtEntry>, String> {
+    let mut response = awc::Client::default()
+        .get(&url)
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("answered {}", response.status()));
+    }
+    let body = response
+        .body()
+        .limit(MAX_RESPONSE)
+        .await
+        .map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+// Serial numbers as lowercase hex without leading zeros, the way they're
+// compared.
+fn normalize(serial: &str) -> String {
+    serial.replace(':', "").trim_start_matches('0').to_ascii_lowercase()
+}
+
+impl LetsEncrypt {
+    fn ct_state_path(&self) -> PathBuf {
+        self.config.ssl_directory.join(STATE_FILE)
+    }
+
+    fn ct_state(&self) -> BTreeMap<Domain, u64> {
+        fs::read(self.ct_state_path())
+            .ok()
+            .and_then(|state| serde_json::from_slice(&state).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn start_ct_monitor(&self, ctx: &mut Context<Self>) {
+        if let Some(monitor) = &self.config.ct_monitor {
+            ctx.run_interval(monitor.interval, |act, ctx| act.poll_ct(ctx));
+            self.poll_ct(ctx);
+        }
+    }
+
+    // Only the leader polls, so replicas don't alert several times over.
+    fn poll_ct(&mut self, ctx: &mut Context<Self>) {
+        let monitor = match &self.config.ct_monitor {
+            Some(monitor) if !self.is_follower() => monitor.clone(),
+            _ => return,
+        };
+        for cert_builder in self.config.cert_builders.clone() {
+            for domain in cert_builder.domains.clone() {
+                let url = format!("{}{}", monitor.search_url, domain);
+                let cert_builder = cert_builder.clone();
+                ctx.spawn(
+                    search(url)
+                        .into_actor(self)
+                        .map(move |result, act, ctx| match result {
+                            Ok(entries) => act.review_ct(&cert_builder, &domain, entries, ctx),
+                            Err(e) => warn!("{}: CT search failed: {}", domain, e),
+                        }),
+                );
+            }
+        }
+    }
+
+    // Our own serials: every certificate in the audit log, plus the one on
+    // disk, which may have been imported.
+    fn own_serials(&self, cert_builder: &CertBuilder) -> Vec<String> {
+        let mut serials: Vec<String> = self
+            .audit_entries()
+            .into_iter()
+            .filter(|e| e.operation != AuditOperation::KeyGenerated)
+            .filter_map(|e| e.serial)
+            .map(|serial| normalize(&serial))
+            .collect();
+        let current = fs::read(cert_builder.cert_path.as_ref().unwrap())
+            .ok()
+            .and_then(|pem| X509::from_pem(&pem).ok())
+            .and_then(|cert| cert.serial_number().to_bn().ok())
+            .and_then(|serial| serial.to_hex_str().ok().map(|s| normalize(&s)));
+        serials.extend(current);
+        serials
+    }
+
+    fn review_ct(
+        &mut self,
+        cert_builder: &CertBuilder,
+        domain: &Domain,
+        entries: Vec<CtEntry>,
+        ctx: &mut Context<Self>,
+    ) {
+        let mut state = self.ct_state();
+        let newest = entries.iter().map(|e| e.id).max();
+        let seen = match (state.get(domain).copied(), newest) {
+            (_, None) => return,
+            (None, Some(newest)) => {
+                debug!("{}: recorded CT baseline", domain);
+                state.insert(domain.clone(), newest);
+                return self.save_ct_state(&state);
+            }
+            (Some(seen), Some(_)) => seen,
+        };
+        let own = self.own_serials(cert_builder);
+        let mut reported = Vec::new();
+        for entry in entries.iter().filter(|e| e.id > seen) {
+            let serial = normalize(&entry.serial_number);
+            // Precertificates and certificates share a serial.
+            if own.contains(&serial) || reported.contains(&serial) {
+                continue;
+            }
+            error!(
+                "{}: certificate {} issued by {} wasn't ordered here",
+                domain, serial, entry.issuer_name
+            );
+            let event = Event::UnexpectedCertificate {
+                info: cert_builder.info(),
+                domain: domain.clone(),
+                issuer: entry.issuer_name.clone(),
+                serial: serial.clone(),
+            };
+            self.shared
+                .events
+                .send(LifecycleEvent::UnexpectedCertificate {
+                    domain: domain.clone(),
+                    issuer: entry.issuer_name.clone(),
+                    serial: serial.clone(),
+                });
+            self.notify(event, ctx);
+            reported.push(serial);
+        }
+        state.insert(domain.clone(), newest.unwrap());
+        self.save_ct_state(&state);
+    }
+
+    fn save_ct_state(&self, state: &BTreeMap<Domain, u64>) {
+        let path = self.ct_state_path();
+        let written = serde_json::to_vec(state)
+            .map_err(std::io::Error::from)
+            .and_then(|state| fs::write(&path, state));
+        if let Err(e) = written {
+            error!("{}: could not save CT monitor state: {}", path.display(), e);
+        }
+    }
+}
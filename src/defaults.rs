@@ -0,0 +1,102 @@
+//! Settings shared by every certificate added to an enabler.
+
+use {
+    crate::{serde_duration, ChainBundle, CheckSchedule, KeyRotation, KeyType},
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+/// A template applied by `LetsEncrypt::add_cert` to every `CertBuilder`,
+/// filling in whichever of these settings the builder doesn't set itself.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CertDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) email: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) production: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_type: Option<KeyType>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_rotation: Option<KeyRotation>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    pub(crate) renew_within: Option<Duration>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    pub(crate) check_every: Option<Duration>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    pub(crate) jitter: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) check_schedule: Option<CheckSchedule>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chain: Option<ChainBundle>,
+}
+
+impl CertDefaults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn email<E: AsRef<str>>(mut self, email: E) -> Self {
+        self.email = Some(email.as_ref().to_string());
+        self
+    }
+
+    pub fn test(mut self) -> Self {
+        self.production = Some(false);
+        self
+    }
+
+    pub fn key_type(mut self, key_type: KeyType) -> Self {
+        self.key_type = Some(key_type);
+        self
+    }
+
+    pub fn key_rotation(mut self, rotation: KeyRotation) -> Self {
+        self.key_rotation = Some(rotation);
+        self
+    }
+
+    pub fn renew_within(mut self, renewal: &Duration) -> Self {
+        self.renew_within = Some(*renewal);
+        self
+    }
+
+    pub fn check_every(mut self, period: &Duration) -> Self {
+        self.check_every = Some(*period);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: &Duration) -> Self {
+        self.jitter = Some(*jitter);
+        self
+    }
+
+    pub fn check_schedule(mut self, schedule: CheckSchedule) -> Self {
+        self.check_schedule = Some(schedule);
+        self
+    }
+
+    pub fn chain(mut self, bundle: ChainBundle) -> Self {
+        self.chain = Some(bundle);
+        self
+    }
+}
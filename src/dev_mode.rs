@@ -0,0 +1,227 @@
+//! Self-signed certificates for local development.
+//!
+//! With `dev_mode`, orders never reach an ACME directory.  Each cert is
+//! signed on the spot by its own key, for whatever domains it's configured
+//! with, `localhost` and IPv4 addresses included, and then stored, served
+//! and renewed exactly like one from Let's Encrypt, so the application
+//! runs the same code path as in production.  Browsers will warn about
+//! them.
+//!
+//! With `dev_ca` they are signed instead by a CA generated the first time
+//! under `<ssl_directory>/dev-ca/`, as mkcert does.  Once its root
+//! certificate (`dev_ca_root`, or `lets-encrypt-cli dev-ca`) is added to
+//! the system's or the browser's trust store, the certificates are trusted
+//! there.  Whoever has the CA's key can impersonate any site to that
+//! machine, so it is only readable by its owner and should never leave it.
+Its key is stored like the certificates' keys, wrapped with `key_wrap`
+when that is set, and like theirs it is handed to the `run_as` user.
+It is never renewed, so one generated before wrapping was turned on stays
+plain until `dev-ca/` is removed and the CA regenerated.
+//!
+//! The certificates are stored where real ones would be.  A stored
+//! certificate from another issuer than the current mode's, say a
+//! development one left behind when `dev_mode` is turned off, is replaced
+//! on the next check as if it were due.
+
+use {
+    crate::{CertBuilder, Error, LetsEncrypt},
+    log::info,
+    openssl::{
+        asn1::{Asn1Integer, Asn1Time},
+        bn::{BigNum, MsbOption},
+        ec::{EcGroup, EcKey},
+        error::ErrorStack,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        x509::{
+            extension::{
+                AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage,
+                SubjectAlternativeName, SubjectKeyIdentifier,
+            },
+            X509Name, X509NameBuilder, X509,
+        },
+    },
+    std::{
+        fs::{self, Permissions},
+        net::Ipv4Addr,
+        os::unix::fs::PermissionsExt,
+        path::PathBuf,
+    },
+    zeroize::Zeroizing,
+};
+
+// As long as Let's Encrypt's, so renewals happen on the same schedule.
+const DEV_CERT_DAYS: u32 = 90;
+
+const DEV_CA_DIRECTORY: &str = "dev-ca";
+const DEV_CA_KEY: &str = "rootCA-key.pem";
+const DEV_CA_ROOT: &str = "rootCA.pem";
+const DEV_CA_DAYS: u32 = 10 * 365;
+const DEV_CA_NAME: &str = "actix-web-lets-encrypt development CA";
+
+fn serial() -> Result<Asn1Integer, ErrorStack> {
+    let mut serial = BigNum::new()?;
+    serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+    serial.to_asn1_integer()
+}
+
+fn common_name(name: &str) -> Result<X509Name, ErrorStack> {
+    let mut builder = X509NameBuilder::new()?;
+    builder.append_entry_by_nid(Nid::COMMONNAME, name)?;
+    Ok(builder.build())
+}
+
+fn generate_dev_ca() -> Result<(PKey<Private>, X509), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+    let name = common_name(DEV_CA_NAME)?;
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(DEV_CA_DAYS)?)?;
+    builder.append_extension(BasicConstraints::new().critical().ca().pathlen(0).build()?)?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .key_cert_sign()
+            .crl_sign()
+            .build()?,
+    )?;
+    let key_id = SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(key_id)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok((key, builder.build()))
+}
+
+impl LetsEncrypt {
+    /// Where the development CA's root certificate is kept, as PEM.
+    pub fn dev_ca_root_path(&self) -> PathBuf {
+        self.config.ssl_directory.join(DEV_CA_DIRECTORY).join(DEV_CA_ROOT)
+    }
+
+    /// The development CA's root certificate, to add to a trust store,
+    /// generating the CA if it doesn't exist yet.
+    pub fn dev_ca_root(&self) -> Result<X509, Error> {
+        Ok(self.load_dev_ca()?.1)
+    }
+
+    fn load_dev_ca(&self) -> Result<(PKey<Private>, X509), Error> {
+        // Concurrent orders mustn't each generate a CA.
+        let _generating = self.shared.dev_ca.lock().unwrap();
+        let directory = self.config.ssl_directory.join(DEV_CA_DIRECTORY);
+        let key_path = directory.join(DEV_CA_KEY);
+        let root_path = directory.join(DEV_CA_ROOT);
+        if key_path.exists() && root_path.exists() {
+            let key = self.read_private_key(&key_path)?;
+            let root = X509::from_pem(&fs::read(&root_path)?)?;
+            return Ok((key, root));
+        }
+        fs::create_dir_all(&directory)?;
+        fs::set_permissions(&directory, Permissions::from_mode(0o700))?;
+        let (key, root) = generate_dev_ca()?;
+        let pem = Zeroizing::new(key.private_key_to_pem_pkcs8()?);
+        self.write_private_key(&key_path, &pem, 0o600)?;
+        fs::write(&root_path, root.to_pem()?)?;
+        info!(
+            "created the development CA, trust {} to trust its certificates",
+            root_path.display()
+        );
+        Ok((key, root))
+    }
+
+    /// Whether the stored certificate came from the issuer the current mode
+    /// orders from: the CA, the certificate's own key, or the development
+    /// CA.  A missing or unreadable certificate is left to the other checks.
+    pub(crate) fn issued_in_current_mode(&self, cert_builder: &CertBuilder) -> bool {
+        let cert = match cert_builder.cert() {
+            Some(cert) => cert,
+            None => return true,
+        };
+        let by_dev_ca = cert
+            .issuer_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .any(|entry| entry.data().as_slice() == DEV_CA_NAME.as_bytes());
+        let self_signed = cert
+            .public_key()
+            .and_then(|key| cert.verify(&key))
+            .unwrap_or(false);
+        let current = match (self.config.dev_mode, self.config.dev_ca) {
+            (false, _) => !by_dev_ca && !self_signed,
+            (true, false) => self_signed,
+            (true, true) => by_dev_ca,
+        };
+        if !current {
+            info!(
+                "{}: the stored certificate is from another issuer than the current mode's, replacing it",
+                cert_builder.domains[0]
+            );
+        }
+        current
+    }
+
+    /// A certificate for `cert_builder`'s domains, signed by `key`, or by
+    /// the development CA with `dev_ca`.
+    pub(crate) fn self_signed(
+        &self,
+        cert_builder: &CertBuilder,
+        key: &PKey<Private>,
+    ) -> Result<Vec<X509>, Error> {
+        let name = common_name(cert_builder.domains[0].as_str())?;
+        let ca = if self.config.dev_ca {
+            Some(self.load_dev_ca()?)
+        } else {
+            None
+        };
+
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&serial()?)?;
+        builder.set_subject_name(&name)?;
+        match &ca {
+            Some((_, root)) => builder.set_issuer_name(root.subject_name())?,
+            None => builder.set_issuer_name(&name)?,
+        }
+        builder.set_pubkey(key)?;
+        builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&Asn1Time::days_from_now(DEV_CERT_DAYS)?)?;
+        let mut names = SubjectAlternativeName::new();
+        for domain in &cert_builder.domains {
+            if domain.as_str().parse::<Ipv4Addr>().is_ok() {
+                names.ip(domain.as_str());
+            } else {
+                names.dns(domain.as_str());
+            }
+        }
+        let root = ca.as_ref().map(|(_, root)| &**root);
+        let names = names.build(&builder.x509v3_context(root, None))?;
+        builder.append_extension(names)?;
+        if root.is_some() {
+            let issuer_id = AuthorityKeyIdentifier::new()
+                .keyid(true)
+                .build(&builder.x509v3_context(root, None))?;
+            builder.append_extension(issuer_id)?;
+        }
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+        match &ca {
+            Some((ca_key, _)) => builder.sign(ca_key, MessageDigest::sha256())?,
+            None => builder.sign(key, MessageDigest::sha256())?,
+        }
+        info!(
+            "{}: signed a development certificate",
+            cert_builder.domains[0]
+        );
+        Ok(vec![builder.build()])
+    }
+}
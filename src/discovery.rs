@@ -0,0 +1,217 @@
+//! Finding hostnames worth a certificate from the Host headers of plain
+//! HTTP requests, for onboarding vhosts that used to be served without TLS.
+//!
+//! Requests are counted by `HostDiscovery`, a middleware wrapped around the
+//! app.  Once a hostname has been seen `threshold` times its DNS is
+//! checked, and if it points here it's proposed, or, with `auto_issue`,
+//! handed to on-demand issuance, which still applies its allow list and
+//! `allow_domain` callback.
+
+use {
+    crate::{Domain, LetsEncrypt},
+    actix_service::{Service, Transform},
+    actix_web::{
+        dev::{ServiceRequest, ServiceResponse},
+        web,
+    },
+    log::info,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        future::{ready, Ready},
+        net::{IpAddr, ToSocketAddrs},
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    },
+};
+
+// Bounds the memory a client sending random Host headers can use up.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Discovery {
+    #[serde(default = "Discovery::default_threshold")]
+    threshold: u64,
+    #[serde(default)]
+    auto_issue: bool,
+}
+
+/// A hostname seen on plain HTTP.
+#[derive(Clone, Debug, Serialize)]
+pub struct DiscoveredHost {
+    pub host: Domain,
+    pub hits: u64,
+    /// Its DNS was checked and points at this server.
+    pub verified: bool,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            threshold: Self::default_threshold(),
+            auto_issue: false,
+        }
+    }
+}
+
+impl Discovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn default_threshold() -> u64 {
+        10
+    }
+
+    /// How many requests for a hostname before it's considered.
+    pub fn threshold(mut self, threshold: u64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Queue verified hostnames for on-demand issuance rather than only
+    /// proposing them.
+    pub fn auto_issue(mut self) -> Self {
+        self.auto_issue = true;
+        self
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct DiscoveryBoard {
+    hosts: Arc<Mutex<BTreeMap<Domain, DiscoveredHost>>>,
+}
+
+impl DiscoveryBoard {
+    // The number of hits so far, or None if the host isn't being tracked.
+    fn record(&self, host: &Domain) -> Option<u64> {
+        let mut hosts = self.hosts.lock().unwrap();
+        if !hosts.contains_key(host) && hosts.len() >= MAX_TRACKED {
+            return None;
+        }
+        let entry = hosts.entry(host.clone()).or_insert_with(|| DiscoveredHost {
+            host: host.clone(),
+            hits: 0,
+            verified: false,
+        });
+        entry.hits += 1;
+        Some(entry.hits)
+    }
+
+    fn verified(&self, host: &Domain) {
+        if let Some(entry) = self.hosts.lock().unwrap().get_mut(host) {
+            entry.verified = true;
+        }
+    }
+
+    pub(crate) fn all(&self) -> Vec<DiscoveredHost> {
+        self.hosts.lock().unwrap().values().cloned().collect()
+    }
+}
+
+// Whether `host` resolves, and, if the listener is bound to a specific
+// address rather than a wildcard one, to that address.
+fn points_here(host: &Domain, local: IpAddr) -> bool {
+    match (host.as_str(), 80).to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<IpAddr> = addrs.map(|a| a.ip()).collect();
+            !addrs.is_empty() && (local.is_unspecified() || addrs.contains(&local))
+        }
+        Err(_) => false,
+    }
+}
+
+impl LetsEncrypt {
+    fn observe(&self, req: &ServiceRequest) {
+        let discovery = match &self.config.discovery {
+            Some(discovery) => discovery,
+            None => return,
+        };
+        let origin = self.origin(req.request());
+        if origin.scheme != "http" {
+            return;
+        }
+        let host = origin.host.split(':').next().unwrap_or_default();
+        let host = match Domain::new(host) {
+            Ok(host) if !host.is_wildcard() => host,
+            _ => return,
+        };
+        if self.shared.resolver.get(&host).is_some() {
+            return;
+        }
+        if self.shared.discovered.record(&host) != Some(discovery.threshold) {
+            return;
+        }
+
+        let local = req.app_config().local_addr().ip();
+        let enabler = self.clone();
+        let auto_issue = discovery.auto_issue;
+        actix_web::rt::spawn(async move {
+            let checked = host.clone();
+            let verified = web::block(move || Ok::<_, ()>(points_here(&checked, local))).await;
+            if !matches!(verified, Ok(true)) {
+                return;
+            }
+            info!("{}: discovered from Host headers", host);
+            enabler.shared.discovered.verified(&host);
+            if auto_issue {
+                enabler.issue_discovered(host);
+            }
+        });
+    }
+}
+
+/// Middleware that counts the Host headers of plain HTTP requests; see
+/// `LetsEncrypt::host_discovery`.
+pub struct HostDiscovery {
+    enabler: LetsEncrypt,
+}
+
+impl HostDiscovery {
+    pub(crate) fn new(enabler: LetsEncrypt) -> Self {
+        HostDiscovery { enabler }
+    }
+}
+
+impl<S, B> Transform<S> for HostDiscovery
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = HostDiscoveryMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HostDiscoveryMiddleware {
+            service,
+            enabler: self.enabler.clone(),
+        }))
+    }
+}
+
+pub struct HostDiscoveryMiddleware<S> {
+    service: S,
+    enabler: LetsEncrypt,
+}
+
+impl<S, B> Service for HostDiscoveryMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        self.enabler.observe(&req);
+        self.service.call(req)
+    }
+}
@@ -0,0 +1,214 @@
+//! Validated domain names.
+//!
+//! Anything that isn't a bare hostname is rejected up front, rather than
+//! being handed to the CA and failing much later with a less helpful
+//! error.
+
+use {
+    serde::{Deserialize, Serialize},
+    std::{
+        convert::TryFrom,
+        error, fmt,
+        str::FromStr,
+    },
+};
+
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+/// A lowercased hostname, optionally with a leading `*.` wildcard label.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Domain(String);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainError {
+    Empty,
+    TooLong(String),
+    Scheme(String),
+    Port(String),
+    Path(String),
+    InvalidLabel(String),
+}
+
+impl fmt::Display for DomainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DomainError::Empty => write!(f, "domain is empty"),
+            DomainError::TooLong(d) => write!(f, "{}: longer than {} characters", d, MAX_DOMAIN_LEN),
+            DomainError::Scheme(d) => write!(f, "{}: remove the scheme, only the hostname is wanted", d),
+            DomainError::Port(d) => write!(f, "{}: remove the port, only the hostname is wanted", d),
+            DomainError::Path(d) => write!(f, "{}: remove the path, only the hostname is wanted", d),
+            DomainError::InvalidLabel(d) => write!(f, "{}: not a valid hostname", d),
+        }
+    }
+}
+
+impl error::Error for DomainError {}
+
+impl Domain {
+    pub fn new<D: AsRef<str>>(domain: D) -> Result<Self, DomainError> {
+        let original = domain.as_ref().trim();
+        if original.is_empty() {
+            return Err(DomainError::Empty);
+        }
+        if original.contains("://") {
+            return Err(DomainError::Scheme(original.to_string()));
+        }
+        if original.contains('/') {
+            return Err(DomainError::Path(original.to_string()));
+        }
+        if original.contains(':') {
+            return Err(DomainError::Port(original.to_string()));
+        }
+
+        let domain = original.trim_end_matches('.').to_ascii_lowercase();
+        if domain.len() > MAX_DOMAIN_LEN {
+            return Err(DomainError::TooLong(original.to_string()));
+        }
+
+        let mut labels = domain.split('.').peekable();
+        if labels.peek() == Some(&"*") {
+            labels.next();
+            // A wildcard directly under a TLD, such as `*.com`, is never
+            // issued.
+            if labels.clone().count() < 2 {
+                return Err(DomainError::InvalidLabel(original.to_string()));
+            }
+        }
+        if !labels.all(Self::valid_label) {
+            return Err(DomainError::InvalidLabel(original.to_string()));
+        }
+
+        Ok(Domain(domain))
+    }
+
+    fn valid_label(label: &str) -> bool {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LEN
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.0.starts_with("*.")
+    }
+
+    /// The wildcard that would cover this name, `*.example.com` for
+    /// `api.example.com`.  None for wildcards and for names of fewer than
+    /// three labels, such as `example.com`, whose wildcard would sit
+    /// directly under a TLD.
+    pub fn wildcard_parent(&self) -> Option<Domain> {
+        if self.is_wildcard() {
+            return None;
+        }
+        let parent = self.0.splitn(2, '.').nth(1)?;
+        if !parent.contains('.') {
+            return None;
+        }
+        Some(Domain(format!("*.{}", parent)))
+    }
+
+    /// Whether a certificate for `self` is valid for `host`: the same name,
+    /// or one label under a wildcard.
+    pub fn matches(&self, host: &Domain) -> bool {
+        if !self.is_wildcard() {
+            return self == host;
+        }
+        let mut labels = host.0.splitn(2, '.');
+        match (labels.next(), labels.next()) {
+            (Some(label), Some(parent)) => label != "*" && parent == &self.0[2..],
+            _ => false,
+        }
+    }
+
+    /// Removes duplicates while keeping the first occurrence of each
+    /// domain in place, since the first domain names the key and cert
+    /// files.
+    pub(crate) fn dedup(domains: &mut Vec<Domain>) {
+        let mut seen = Vec::with_capacity(domains.len());
+        domains.retain(|d| {
+            if seen.contains(d) {
+                false
+            } else {
+                seen.push(d.clone());
+                true
+            }
+        });
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Domain {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Domain {
+    type Err = DomainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Domain::new(s)
+    }
+}
+
+impl TryFrom<String> for Domain {
+    type Error = DomainError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Domain::new(s)
+    }
+}
+
+impl From<Domain> for String {
+    fn from(domain: Domain) -> String {
+        domain.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent(name: &str) -> Option<String> {
+        Domain::new(name).unwrap().wildcard_parent().map(String::from)
+    }
+
+    #[test]
+    fn wildcard_parent_needs_three_labels() {
+        assert_eq!(parent("api.example.com").as_deref(), Some("*.example.com"));
+        assert_eq!(parent("a.api.example.com").as_deref(), Some("*.api.example.com"));
+        assert_eq!(parent("example.com"), None);
+        assert_eq!(parent("localhost"), None);
+        assert_eq!(parent("*.example.com"), None);
+    }
+
+    #[test]
+    fn wildcards_under_a_tld_are_rejected() {
+        assert!(Domain::new("*.example.com").is_ok());
+        assert!(Domain::new("*.com").is_err());
+        assert!(Domain::new("*").is_err());
+    }
+
+    #[test]
+    fn wildcards_match_one_label() {
+        let wildcard = Domain::new("*.example.com").unwrap();
+        let matches = |host| wildcard.matches(&Domain::new(host).unwrap());
+        assert!(matches("api.example.com"));
+        assert!(!matches("a.api.example.com"));
+        assert!(!matches("example.com"));
+    }
+}
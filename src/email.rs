@@ -0,0 +1,171 @@
+//! SMTP notifications, enabled with the `email` feature.
+//!
+//! Unlike webhooks, which get every event, email is reserved for things a
+//! person needs to act on: a certificate close to expiry, or renewal that
+//! keeps failing.
+
+use {
+    crate::{credentials::Secret, Domain, Event},
+    lettre::{
+        message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+        Transport,
+    },
+    log::{error, info},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+// Expiry warnings are repeated on every check, so mail at most this often.
+const EXPIRY_MAIL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EmailNotifier {
+    smtp_host: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smtp_port: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<Secret>,
+
+    from: String,
+    to: Vec<String>,
+
+    #[serde(default = "EmailNotifier::default_failure_threshold")]
+    failure_threshold: u32,
+
+    #[serde(skip)]
+    last_expiry_mail: Arc<Mutex<HashMap<Domain, Instant>>>,
+}
+
+impl EmailNotifier {
+    pub fn new<H, F, T>(smtp_host: H, from: F, to: &[T]) -> Self
+    where
+        H: Into<String>,
+        F: Into<String>,
+        T: AsRef<str>,
+    {
+        EmailNotifier {
+            smtp_host: smtp_host.into(),
+            smtp_port: None,
+            username: None,
+            password: None,
+            from: from.into(),
+            to: to.iter().map(|t| t.as_ref().to_string()).collect(),
+            failure_threshold: Self::default_failure_threshold(),
+            last_expiry_mail: Arc::default(),
+        }
+    }
+
+    fn default_failure_threshold() -> u32 {
+        3
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.smtp_port = Some(port);
+        self
+    }
+
+    pub fn credentials<U: Into<String>, P: Into<String>>(mut self, username: U, password: P) -> Self {
+        self.username = Some(username.into());
+        self.password = Some(Secret::from(password.into()));
+        self
+    }
+
+    /// Mail about failures once this many consecutive attempts for a cert
+    /// have failed, and again at every multiple of it.
+    pub fn failure_threshold(mut self, threshold: u32) -> Self {
+        self.failure_threshold = threshold.max(1);
+        self
+    }
+
+    pub(crate) fn notify(&self, event: &Event) {
+        let (subject, body) = match event {
+            Event::Failed { attempt, error, info } if attempt % self.failure_threshold == 0 => (
+                format!("Certificate renewal for {} keeps failing", event.domain()),
+                format!(
+                    "Renewal of the certificate for {} has failed {} times in a row.\n\
+                     Certificate expiry: {}\n\nLast error: {}\n",
+                    Self::domains(&info.domains),
+                    attempt,
+                    Self::expiry(event),
+                    error
+                ),
+            ),
+            Event::Expiring(info) if self.expiry_mail_due(event.domain()) => (
+                format!("Certificate for {} expires soon", event.domain()),
+                format!(
+                    "The certificate for {} expires {} and has not been renewed.\n",
+                    Self::domains(&info.domains),
+                    Self::expiry(event)
+                ),
+            ),
+            Event::UnexpectedCertificate { .. } => (
+                format!("Unexpected certificate for {}", event.domain()),
+                format!("{}.\n", event.summary()),
+            ),
+            _ => return,
+        };
+        let notifier = self.clone();
+        let domain = event.domain().clone();
+        thread::spawn(move || match notifier.send(&subject, body) {
+            Ok(()) => info!("{}: sent notification email", domain),
+            Err(e) => error!("{}: could not send notification email: {}", domain, e),
+        });
+    }
+
+    fn expiry_mail_due(&self, domain: &Domain) -> bool {
+        let mut last = self.last_expiry_mail.lock().unwrap();
+        let now = Instant::now();
+        match last.get(domain) {
+            Some(sent) if now.duration_since(*sent) < EXPIRY_MAIL_INTERVAL => false,
+            _ => {
+                last.insert(domain.clone(), now);
+                true
+            }
+        }
+    }
+
+    fn domains(domains: &[Domain]) -> String {
+        let domains: Vec<&str> = domains.iter().map(Domain::as_str).collect();
+        domains.join(", ")
+    }
+
+    fn expiry(event: &Event) -> String {
+        event
+            .info()
+            .not_after
+            .map_or_else(|| "unknown".to_string(), |not_after| not_after.to_string())
+    }
+
+    fn send(&self, subject: &str, body: String) -> Result<(), String> {
+        let from: Mailbox = self.from.parse().map_err(|e| format!("{}: {}", self.from, e))?;
+        let mut message = Message::builder().from(from).subject(subject);
+        for to in &self.to {
+            let to: Mailbox = to.parse().map_err(|e| format!("{}: {}", to, e))?;
+            message = message.to(to);
+        }
+        let message = message.body(body).map_err(|e| e.to_string())?;
+
+        let mut transport = SmtpTransport::relay(&self.smtp_host).map_err(|e| e.to_string())?;
+        if let Some(port) = self.smtp_port {
+            transport = transport.port(port);
+        }
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.expose().to_string()));
+        }
+        transport
+            .build()
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,276 @@
+//! Errors reported by the crate.
+
+use {
+    crate::{Domain, DomainError},
+    openssl::error::ErrorStack,
+    std::{env, error, fmt, io, path::PathBuf},
+};
+
+/// Any error the crate can report.
+///
+/// Unlike the acme-client error it wraps, this is `Send + Sync + 'static`
+/// and exposes the underlying causes through `source`, so it can be
+/// propagated with `?` into `anyhow`, `eyre` or a boxed error.
+#[derive(Debug)]
+pub enum Error {
+    Acme(AcmeError),
+    Io(io::Error),
+    Ssl(ErrorStack),
+    Config(ConfigError),
+    Domain(DomainError),
+    /// The CA didn't offer an http-01 challenge for the domain.
+    NoHttpChallenge(Domain),
+    /// No configured certificate covers the domain.
+    NotManaged(Domain),
+    /// A `RenewalLock` backend failed.
+    Lock(Box<dyn error::Error + Send + Sync>),
+    /// Another instance holds the renewal lock for the domain.
+    Locked(Domain),
+    /// The tenant has used up its `orders_per_day`.
+    RateLimited(String),
+    /// The audit log was altered at the given line.
+    AuditLog(usize),
+    /// The new certificate for the domain doesn't satisfy browsers'
+    /// Certificate Transparency policy, for the given reason.
+    CtPolicy(Domain, String),
+    /// The domain's CAA records couldn't be checked, or don't allow Let's
+    /// Encrypt while `require_caa` is set, for the given reason.
+    Caa(Domain, String),
+    /// The key file is passphrase-protected, and no passphrase is
+    /// available for it.
+    EncryptedKey(PathBuf),
+    /// A private key couldn't be wrapped or unwrapped.
+    KeyWrap(Box<dyn error::Error + Send + Sync>),
+    /// The order for the domain's certificate ran past `order_deadline`.
+    OrderDeadline(Domain),
+    /// The enabler's actor hasn't been started, or has already stopped.
+    NotRunning,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Acme(_) => write!(f, "ACME request failed"),
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::Ssl(_) => write!(f, "OpenSSL error"),
+            Error::Config(_) => write!(f, "invalid configuration"),
+            Error::Domain(_) => write!(f, "invalid domain"),
+            Error::NoHttpChallenge(domain) => write!(f, "{}: HTTP challenge not found", domain),
+            Error::NotManaged(domain) => write!(f, "{}: not a managed domain", domain),
+            Error::Lock(_) => write!(f, "renewal lock failed"),
+            Error::Locked(domain) => write!(f, "{}: being renewed by another instance", domain),
+            Error::RateLimited(tenant) => write!(f, "tenant {}: daily order limit reached", tenant),
+            Error::AuditLog(line) => write!(f, "audit log doesn't verify at line {}", line),
+            Error::CtPolicy(domain, reason) => {
+                write!(f, "{}: certificate fails the CT policy: {}", domain, reason)
+            }
+            Error::Caa(domain, reason) => write!(f, "{}: CAA check failed: {}", domain, reason),
+            Error::EncryptedKey(path) => {
+                write!(f, "{}: encrypted key and no passphrase for it", path.display())
+            }
+            Error::KeyWrap(_) => write!(f, "private key wrapping failed"),
+            Error::OrderDeadline(domain) => {
+                write!(f, "{}: order not finished by its deadline", domain)
+            }
+            Error::NotRunning => write!(f, "{}", NotRunning),
+        }
+    }
+}
+
+impl Error {
+    /// The CA's problem document, for errors the CA reported.
+    pub fn problem(&self) -> Option<&serde_json::Value> {
+        match self {
+            Error::Acme(e) => e.problem(),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Acme(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Ssl(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Domain(e) => Some(e),
+            Error::Lock(e) | Error::KeyWrap(e) => Some(e.as_ref()),
+            Error::NoHttpChallenge(_)
+            | Error::NotManaged(_)
+            | Error::Locked(_)
+            | Error::RateLimited(_)
+            | Error::AuditLog(_)
+            | Error::CtPolicy(..)
+            | Error::Caa(..)
+            | Error::EncryptedKey(_)
+            | Error::OrderDeadline(_)
+            | Error::NotRunning => None,
+        }
+    }
+}
+
+impl From<acme_client::error::Error> for Error {
+    fn from(e: acme_client::error::Error) -> Self {
+        Error::Acme(AcmeError::from(e))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ErrorStack> for Error {
+    fn from(e: ErrorStack) -> Self {
+        Error::Ssl(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<NotRunning> for Error {
+    fn from(_: NotRunning) -> Self {
+        Error::NotRunning
+    }
+}
+
+impl From<DomainError> for Error {
+    fn from(e: DomainError) -> Self {
+        Error::Domain(e)
+    }
+}
+
+#[cfg(feature = "redis-lock")]
+impl From<redis::RedisError> for Error {
+    fn from(e: redis::RedisError) -> Self {
+        Error::Lock(Box::new(e))
+    }
+}
+
+/// Displays an error followed by each of its sources, separated by `: `,
+/// for log messages.
+pub(crate) struct DisplayChain<'a>(pub(crate) &'a dyn error::Error);
+
+impl<'a> fmt::Display for DisplayChain<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+/// An acme-client error, flattened into its chain of messages.
+///
+/// acme-client's own error type is neither `Sync` nor does it expose its
+/// causes through `source`, so it is captured as text instead.
+#[derive(Debug)]
+pub struct AcmeError {
+    message: String,
+    source: Option<Box<AcmeError>>,
+    problem: Option<serde_json::Value>,
+}
+
+impl AcmeError {
+    /// The problem document the CA answered with, when the error came from
+    /// the CA rather than from reaching it.
+    pub fn problem(&self) -> Option<&serde_json::Value> {
+        self.problem.as_ref()
+    }
+}
+
+impl fmt::Display for AcmeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for AcmeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as &(dyn error::Error + 'static))
+    }
+}
+
+impl From<acme_client::error::Error> for AcmeError {
+    fn from(e: acme_client::error::Error) -> Self {
+        let problem = match e.kind() {
+            acme_client::error::ErrorKind::AcmeServerError(problem) => Some(problem.clone()),
+            _ => None,
+        };
+        let messages: Vec<String> = e.iter().map(|e| e.to_string()).collect();
+        let mut error = messages
+            .into_iter()
+            .rev()
+            .fold(None, |source, message| {
+                Some(AcmeError {
+                    message,
+                    source: source.map(Box::new),
+                    problem: None,
+                })
+            })
+            .expect("an error chain always contains the error itself");
+        error.problem = problem;
+        error
+    }
+}
+
+/// A problem reading an enabler's configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The environment variable is unset or isn't valid unicode.
+    Env { var: String, source: env::VarError },
+    /// The configuration file couldn't be read.
+    Read { path: PathBuf, source: io::Error },
+    /// The JSON couldn't be parsed; `var` is the environment variable or
+    /// file it came from and `path` names the offending field, e.g.
+    /// `cert_builders[1].domains[0]`.
+    Parse {
+        var: String,
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Env { var, source } => write!(f, "{}: {}", var, source),
+            ConfigError::Read { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse { var, path, source } => {
+                write!(f, "can't parse {} at {}: {}", var, path, source)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Env { source, .. } => Some(source),
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Returned by the runtime certificate APIs when the enabler's actor hasn't
+/// been started (or has already stopped).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotRunning;
+
+impl fmt::Display for NotRunning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the LetsEncrypt actor is not running")
+    }
+}
+
+impl error::Error for NotRunning {}
@@ -0,0 +1,74 @@
+//! Lifecycle events reported to notifiers.
+
+use {
+    crate::{CertInfo, Domain},
+    serde::Serialize,
+};
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A certificate was issued for domains that had none.
+    Issued(CertInfo),
+    /// A replacement certificate was issued and is being served.
+    Renewed(CertInfo),
+    /// An issuance attempt failed; it will be retried.
+    Failed {
+        #[serde(flatten)]
+        info: CertInfo,
+        attempt: u32,
+        error: String,
+    },
+    /// The certificate being served expires soon.
+    Expiring(CertInfo),
+    /// A CT log shows a certificate for one of the cert's domains that
+    /// wasn't ordered by this enabler.
+    UnexpectedCertificate {
+        #[serde(flatten)]
+        info: CertInfo,
+        domain: Domain,
+        issuer: String,
+        serial: String,
+    },
+}
+
+impl Event {
+    pub fn info(&self) -> &CertInfo {
+        match self {
+            Event::Issued(info) | Event::Renewed(info) | Event::Expiring(info) => info,
+            Event::Failed { info, .. } | Event::UnexpectedCertificate { info, .. } => info,
+        }
+    }
+
+    pub fn domain(&self) -> &Domain {
+        &self.info().domains[0]
+    }
+
+    /// A one line, human readable description of the event.
+    pub fn summary(&self) -> String {
+        let domains: Vec<&str> = self.info().domains.iter().map(Domain::as_str).collect();
+        let domains = domains.join(", ");
+        let expiry = self
+            .info()
+            .not_after
+            .map_or_else(|| "unknown".to_string(), |not_after| not_after.to_string());
+        match self {
+            Event::Issued(_) => format!("Issued a certificate for {}, expires {}", domains, expiry),
+            Event::Renewed(_) => format!("Renewed the certificate for {}, expires {}", domains, expiry),
+            Event::Failed { attempt, error, .. } => format!(
+                "Renewal attempt {} for {} failed: {}",
+                attempt, domains, error
+            ),
+            Event::Expiring(_) => format!("The certificate for {} expires {}", domains, expiry),
+            Event::UnexpectedCertificate {
+                domain,
+                issuer,
+                serial,
+                ..
+            } => format!(
+                "A certificate for {} (serial {}) was issued by {} without being ordered here",
+                domain, serial, issuer
+            ),
+        }
+    }
+}
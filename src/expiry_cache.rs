@@ -0,0 +1,60 @@
+//! The parsed expiry of a certificate file, kept until the file changes.
+//!
+//! Every check asks for each certificate's expiry; with this, that costs a
+//! `stat` rather than reading and parsing the PEM, unless the file's
+//! modification time or size has changed since it was last parsed.
+
+use {
+    chrono::{DateTime, Utc},
+    std::{
+        fs,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    },
+};
+
+/// Shared by the clones of a `CertBuilder`.
+#[derive(Clone, Default)]
+pub(crate) struct ExpiryCache(Arc<Mutex<Option<Cached>>>);
+
+struct Cached {
+    modified: SystemTime,
+    len: u64,
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl ExpiryCache {
+    /// The expiry of the certificate at `path`, from `parse` when the file
+    /// is new or has changed.
+    pub(crate) fn not_after<F>(&self, path: &Path, parse: F) -> Option<DateTime<Utc>>
+    where
+        F: FnOnce() -> Option<DateTime<Utc>>,
+    {
+        let mut cached = self.0.lock().unwrap();
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                *cached = None;
+                return None;
+            }
+        };
+        // Without modification times there's nothing to validate against.
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return parse(),
+        };
+        match &*cached {
+            Some(c) if c.modified == modified && c.len == metadata.len() => c.not_after,
+            _ => {
+                let not_after = parse();
+                *cached = Some(Cached {
+                    modified,
+                    len: metadata.len(),
+                    not_after,
+                });
+                not_after
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Extra copies of an issued certificate in other formats, for services on
+//! the same host that can't read PEM.
+//!
+//! They are rewritten after every issuance or renewal of the certificate
+//! (and on import), each one atomically, so a consumer never reads half a
+//! file.  A failure to write one is logged rather than failing the
+//! renewal, since the certificate itself was stored.  Export paths are
+//! relative to the certificate's directory and can't leave it.
+//!
+//! Exports that carry the private key hold it in plaintext, so they are
+//! refused with `keys_in_memory` or `wrap_keys`, which promise there is
+//! none on disk; only `DerExport::without_key` is written then.
+
+use {
+    crate::{credentials::Secret, key_wrap, CertBuilder, Error, LetsEncrypt},
+    log::info,
+    openssl::{
+        pkcs12::Pkcs12,
+        pkey::{PKey, Private},
+        stack::Stack,
+        x509::X509,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        fs, io,
+        path::{Component, Path, PathBuf},
+    },
+    zeroize::Zeroizing,
+};
+
+/// A password protected PKCS#12 (`.pfx`) bundle of the certificate, its
+/// chain and its key, for Java and Windows services.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pkcs12Export {
+    password: Secret,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+}
+
+impl Pkcs12Export {
+    /// Written to `<domain>.pfx` beside the certificate.
+    pub fn new<P: Into<String>>(password: P) -> Self {
+        Pkcs12Export {
+            password: Secret::from(password.into()),
+            path: None,
+        }
+    }
+
+    /// Written to `path` instead, relative to the certificate's directory.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        let path = path.into();
+        assert_valid_path(&path);
+        self.path = Some(path);
+        self
+    }
+
+    fn write(
+        &self,
+        cert_builder: &CertBuilder,
+        key: &PKey<Private>,
+        chain: &[X509],
+    ) -> Result<PathBuf, Error> {
+        let domain = &cert_builder.domains[0];
+        let path = beside_cert(cert_builder, self.path.clone(), "pfx");
+        let (leaf, intermediates) = chain
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no certificate"))?;
+        let mut ca = Stack::new()?;
+        for cert in intermediates {
+            ca.push(cert.clone())?;
+        }
+        let mut builder = Pkcs12::builder();
+        builder.ca(ca);
+        let pkcs12 = builder.build(self.password.expose(), domain.as_str(), key, leaf)?;
+        write_atomically(&path, &pkcs12.to_der()?, 0o600)?;
+        Ok(path)
+    }
+}
+
+/// DER copies of the certificate, the leaf alone, and of its key, as
+/// PKCS#8: `<domain>.crt.der` and `<domain>.key.der` beside the
+/// certificate, for clients and appliances that only take DER.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DerExport {
+    #[serde(default)]
+    without_key: bool,
+}
+
+impl DerExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only the certificate, for appliances given their key some other way.
+    pub fn without_key(mut self) -> Self {
+        self.without_key = true;
+        self
+    }
+
+    fn write(
+        &self,
+        cert_builder: &CertBuilder,
+        key: &PKey<Private>,
+        chain: &[X509],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let leaf = chain
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no certificate"))?;
+        let cert_path = beside_cert(cert_builder, None, "crt.der");
+        write_atomically(&cert_path, &leaf.to_der()?, 0o644)?;
+        if self.without_key {
+            return Ok(vec![cert_path]);
+        }
+        let key_path = beside_cert(cert_builder, None, "key.der");
+        write_atomically(
+            &key_path,
+            &Zeroizing::new(key.private_key_to_pkcs8()?),
+            0o600,
+        )?;
+        Ok(vec![cert_path, key_path])
+    }
+}
+
+/// The key, certificate and chain concatenated into one PEM file, as
+/// HAProxy and hitch read them.  Pair it with a `deploy_hook` that reloads
+/// the proxy; the hook is given the file as `LETS_ENCRYPT_COMBINED_PATH`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CombinedPemExport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+}
+
+impl CombinedPemExport {
+    /// Written to `<domain>.combined.pem` beside the certificate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Written to `path` instead, relative to the certificate's directory,
+    /// e.g. into a subdirectory HAProxy's `crt` loads.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        let path = path.into();
+        assert_valid_path(&path);
+        self.path = Some(path);
+        self
+    }
+
+    pub(crate) fn file(&self, cert_builder: &CertBuilder) -> PathBuf {
+        beside_cert(cert_builder, self.path.clone(), "combined.pem")
+    }
+
+    fn write(
+        &self,
+        cert_builder: &CertBuilder,
+        key: &PKey<Private>,
+        chain: &[X509],
+    ) -> Result<PathBuf, Error> {
+        let path = self.file(cert_builder);
+        let mut pem = Zeroizing::new(key.private_key_to_pem_pkcs8()?);
+        for cert in chain {
+            pem.extend(cert.to_pem()?);
+        }
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        write_atomically(&path, &pem, 0o600)?;
+        Ok(path)
+    }
+}
+
+impl CertBuilder {
+    // The export paths that were given rather than defaulted.
+    pub(crate) fn export_paths(&self) -> Vec<&Path> {
+        let pkcs12 = self.pkcs12.as_ref().and_then(|e| e.path.as_deref());
+        let combined = self.combined_pem.as_ref().and_then(|e| e.path.as_deref());
+        pkcs12.into_iter().chain(combined).collect()
+    }
+}
+
+// Absolute paths and `..` would let an export land anywhere the process
+// can write.
+fn valid_path(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+fn assert_valid_path(path: &Path) {
+    if !valid_path(path) {
+        panic!(
+            "{}: export paths must stay in the certificate's directory",
+            path.display()
+        );
+    }
+}
+
+// `path`, or `<domain>.<extension>`, in the directory of the certificate.
+fn beside_cert(cert_builder: &CertBuilder, path: Option<PathBuf>, extension: &str) -> PathBuf {
+    let directory = cert_builder.cert_path.as_ref().unwrap().parent().unwrap();
+    let path =
+        path.unwrap_or_else(|| PathBuf::from(format!("{}.{}", cert_builder.domains[0], extension)));
+    directory.join(path)
+}
+
+fn write_atomically(path: &Path, contents: &[u8], mode: u32) -> Result<(), Error> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".new");
+    let temp = PathBuf::from(temp);
+    key_wrap::write_key_file(&temp, contents, mode)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+impl LetsEncrypt {
+    // Writes every export `cert_builder` asks for from the stored
+    // certificate and its current key.
+    pub(crate) fn write_exports(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
+        if cert_builder.pkcs12.is_none()
+            && cert_builder.der.is_none()
+            && cert_builder.combined_pem.is_none()
+        {
+            return Ok(());
+        }
+        // Paths from a configuration file haven't been through the builders.
+        if let Some(path) = cert_builder
+            .export_paths()
+            .into_iter()
+            .find(|p| !valid_path(p))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: export paths must stay in the certificate's directory",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+        let key_on_disk_forbidden = self.config.keys_in_memory || self.config.key_wrap.is_some();
+        let carries_key = cert_builder.pkcs12.is_some()
+            || cert_builder.combined_pem.is_some()
+            || cert_builder
+                .der
+                .as_ref()
+                .map_or(false, |der| !der.without_key);
+        if key_on_disk_forbidden && carries_key {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "exports carrying the private key would store it in plaintext, \
+                 which keys_in_memory and wrap_keys rule out",
+            )
+            .into());
+        }
+        let chain = X509::stack_from_pem(&fs::read(cert_builder.cert_path.as_ref().unwrap())?)?;
+        let key = self.load_private_key(cert_builder)?;
+        let mut written = Vec::new();
+        if let Some(pkcs12) = &cert_builder.pkcs12 {
+            written.push(pkcs12.write(cert_builder, &key, &chain)?);
+        }
+        if let Some(der) = &cert_builder.der {
+            written.extend(der.write(cert_builder, &key, &chain)?);
+        }
+        if let Some(combined) = &cert_builder.combined_pem {
+            written.push(combined.write(cert_builder, &key, &chain)?);
+        }
+        for path in written {
+            info!("{}: exported {}", cert_builder.domains[0], path.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! What to do when certificates can't be issued soon enough at startup.
+
+use {
+    crate::{Domain, KeyType},
+    openssl::{
+        asn1::Asn1Time,
+        bn::{BigNum, MsbOption},
+        error::ErrorStack,
+        hash::MessageDigest,
+        ssl::{SslAcceptor, SslContext, SslMethod},
+        x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+    },
+    serde::{Deserialize, Serialize},
+};
+
+// Long enough to outlast any reasonable outage; it's replaced as soon as
+// the real certificate is issued.
+const SELF_SIGNED_DAYS: u32 = 30;
+
+/// Applied to each certificate still missing when the startup timeout
+/// runs out.  Issuance keeps being retried in every case but `Exit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupFallback {
+    /// Keep serving plain HTTP; HTTPS handshakes for the certificate fail.
+    HttpOnly,
+    /// Serve a self-signed certificate, held in memory only, until the
+    /// real one arrives.
+    SelfSigned,
+    /// Exit the process with the given status code, for supervisors that
+    /// should treat this as a failed start.
+    Exit(i32),
+}
+
+impl Default for StartupFallback {
+    fn default() -> Self {
+        StartupFallback::HttpOnly
+    }
+}
+
+/// A self-signed certificate for `domains`, ready to be put into the
+/// resolver.
+pub(crate) fn self_signed(domains: &[Domain], key_type: KeyType) -> Result<SslContext, ErrorStack> {
+    let key = key_type.generate()?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", domains[0].as_str())?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    cert.set_serial_number(&serial.to_asn1_integer()?)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    cert.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    cert.set_not_after(&Asn1Time::days_from_now(SELF_SIGNED_DAYS)?)?;
+    let mut san = SubjectAlternativeName::new();
+    for domain in domains {
+        san.dns(domain.as_str());
+    }
+    let san = san.build(&cert.x509v3_context(None, None))?;
+    cert.append_extension(san)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+    let cert = cert.build();
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key(&key)?;
+    builder.set_certificate(&cert)?;
+    Ok(builder.build().into_context())
+}
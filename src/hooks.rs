@@ -0,0 +1,121 @@
+//! User callbacks run around certificate issuance.
+
+use {
+    crate::{Domain, Event},
+    chrono::{DateTime, Utc},
+    log::{error, info, warn},
+    serde::Serialize,
+    std::{future::Future, path::PathBuf, pin::Pin, process::Command, sync::Arc, thread},
+};
+
+/// What a hook is told about the certificate being issued.
+#[derive(Clone, Debug, Serialize)]
+pub struct CertInfo {
+    pub domains: Vec<Domain>,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// The combined PEM file, with a `CombinedPemExport`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_path: Option<PathBuf>,
+    /// Expiry of the certificate currently on disk, if there is one.
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+type HookFuture = Pin<Box<dyn Future<Output = ()>>>;
+type Hook<T = CertInfo> = Arc<dyn Fn(T) -> HookFuture + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    before_order: Vec<Hook>,
+    renewed: Vec<Hook>,
+    critical: Vec<Hook<Event>>,
+}
+
+impl Hooks {
+    pub(crate) fn add_before_order<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(CertInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.before_order.push(Self::boxed(hook));
+    }
+
+    pub(crate) fn add_renewed<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(CertInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.renewed.push(Self::boxed(hook));
+    }
+
+    pub(crate) fn add_critical<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.critical.push(Self::boxed(hook));
+    }
+
+    fn boxed<T, F, Fut>(hook: F) -> Hook<T>
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        Arc::new(move |info| Box::pin(hook(info)) as HookFuture)
+    }
+
+    pub(crate) fn before_order(&self, info: CertInfo) -> HookFuture {
+        Self::run_all(&self.before_order, info)
+    }
+
+    pub(crate) fn renewed(&self, info: CertInfo) -> HookFuture {
+        Self::run_all(&self.renewed, info)
+    }
+
+    pub(crate) fn critical(&self, event: Event) -> HookFuture {
+        Self::run_all(&self.critical, event)
+    }
+
+    // Hooks run one after another, in the order they were added.
+    fn run_all<T: Clone>(hooks: &[Hook<T>], info: T) -> HookFuture {
+        let futures: Vec<HookFuture> = hooks.iter().map(|hook| hook(info.clone())).collect();
+        Box::pin(async move {
+            for future in futures {
+                future.await;
+            }
+        })
+    }
+}
+
+/// Runs `command` through `sh -c` on its own thread, certbot `--deploy-hook`
+/// style.  The certificate is described by environment variables:
+///
+/// * `LETS_ENCRYPT_DOMAIN` - the first domain
+/// * `LETS_ENCRYPT_DOMAINS` and `RENEWED_DOMAINS` - all domains, space separated
+/// * `LETS_ENCRYPT_CERT_PATH` - the certificate chain file
+/// * `LETS_ENCRYPT_KEY_PATH` - the private key file
+/// * `LETS_ENCRYPT_COMBINED_PATH` - the combined PEM file, if exported
+pub(crate) fn run_deploy_hook(command: &str, info: &CertInfo) {
+    let domains: Vec<&str> = info.domains.iter().map(Domain::as_str).collect();
+    let domains = domains.join(" ");
+    let mut command_line = Command::new("sh");
+    command_line
+        .arg("-c")
+        .arg(command)
+        .env("LETS_ENCRYPT_DOMAIN", info.domains[0].as_str())
+        .env("LETS_ENCRYPT_DOMAINS", &domains)
+        .env("RENEWED_DOMAINS", &domains)
+        .env("LETS_ENCRYPT_CERT_PATH", &info.cert_path)
+        .env("LETS_ENCRYPT_KEY_PATH", &info.key_path);
+    if let Some(combined_path) = &info.combined_path {
+        command_line.env("LETS_ENCRYPT_COMBINED_PATH", combined_path);
+    }
+
+    let command = command.to_string();
+    let domain = info.domains[0].clone();
+    thread::spawn(move || match command_line.status() {
+        Ok(status) if status.success() => info!("{}: deploy hook succeeded", domain),
+        Ok(status) => warn!("{}: deploy hook `{}` exited with {}", domain, command, status),
+        Err(e) => error!("{}: could not run deploy hook `{}`: {}", domain, command, e),
+    });
+}
@@ -0,0 +1,113 @@
+//! Taking over certificates issued by certbot (or lego, which can write the
+//! same layout), so that migrating doesn't mean re-issuing everything.
+
+use {
+    crate::{error::DisplayChain, key, CertBuilder, Domain, Error, LetsEncrypt},
+    chrono::{offset::TimeZone, Utc},
+    log::{info, warn},
+    openssl::x509::X509,
+    std::{fs, net::SocketAddr, path::Path},
+};
+
+impl LetsEncrypt {
+    /// Imports every lineage in a certbot `live` directory, such as
+    /// `/etc/letsencrypt/live`, copying its `fullchain.pem` and
+    /// `privkey.pem` into the ssl directory and renewing it from then on.
+    ///
+    /// A lineage is matched to an already added cert sharing its first
+    /// domain; otherwise a new cert is added for its domains, served
+    /// through SNI on the other certs' listeners.  A certificate already in
+    /// the ssl directory that expires later is left alone.  Returns the
+    /// first domain of each certificate imported.
+    pub fn import_certbot_live<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Domain>, Error> {
+        let mut imported = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let lineage = entry?.path();
+            let (chain_path, key_path) =
+                (lineage.join("fullchain.pem"), lineage.join("privkey.pem"));
+            if !chain_path.is_file() || !key_path.is_file() {
+                continue;
+            }
+            let chain = fs::read(&chain_path)?;
+            let key = key::read_key_file(&key_path)?;
+            let cert = X509::from_pem(&chain)?;
+            let domains = cert_domains(&cert)?;
+            if domains.is_empty() {
+                continue;
+            }
+
+            let cert_builder = self.imported_cert_builder(&domains);
+            let not_after = Utc
+                .datetime_from_str(&cert.not_after().to_string(), "%b %d %H:%M:%S %Y GMT")
+                .ok();
+            if cert_builder
+                .not_after()
+                .map_or(false, |current| Some(current) >= not_after)
+            {
+                info!(
+                    "{}: current certificate is newer, not importing",
+                    domains[0]
+                );
+                continue;
+            }
+            let cert_path = cert_builder.cert_path.as_ref().unwrap();
+            let dest_key_path = cert_builder.key_path.as_ref().unwrap();
+            if let Some(directory) = cert_path.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            self.write_private_key(dest_key_path, &key, 0o600)?;
+            fs::write(cert_path, &chain)?;
+            self.write_layout_files(&cert_builder)?;
+            if let Err(e) = self.write_exports(&cert_builder) {
+                warn!(
+                    "{}: could not export the certificate: {}",
+                    cert_builder.domains[0],
+                    DisplayChain(&e)
+                );
+            }
+            info!(
+                "{}: imported from {}",
+                cert_builder.domains[0],
+                lineage.display()
+            );
+            imported.push(cert_builder.domains[0].clone());
+        }
+        Ok(imported)
+    }
+
+    // The cert the lineage belongs to, added if there isn't one yet.
+    fn imported_cert_builder(&mut self, domains: &[Domain]) -> CertBuilder {
+        if let Some(existing) = self
+            .config
+            .cert_builders
+            .iter()
+            .find(|c| c.domains[0] == domains[0])
+        {
+            return existing.clone();
+        }
+        let no_addrs: &[SocketAddr] = &[];
+        self.push_cert(CertBuilder::new(no_addrs, domains));
+        self.config.cert_builders.last().unwrap().clone()
+    }
+}
+
+// The certificate's DNS names, subject common name first as certbot
+// orders them.
+fn cert_domains(cert: &X509) -> Result<Vec<Domain>, Error> {
+    let mut domains = Vec::new();
+    for entry in cert.subject_name().entries() {
+        if let Ok(name) = entry.data().as_utf8() {
+            if let Ok(domain) = Domain::new(&*name) {
+                domains.push(domain);
+                break;
+            }
+        }
+    }
+    if let Some(names) = cert.subject_alt_names() {
+        for name in names.iter().filter_map(|n| n.dnsname()) {
+            domains.push(Domain::new(name)?);
+        }
+    }
+    Domain::dedup(&mut domains);
+    Ok(domains)
+}
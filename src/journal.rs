@@ -0,0 +1,117 @@
+//! An append-only record of issuance attempts, kept next to the
+//! certificates so that it survives restarts.
+//!
+//! Each line is one JSON `JournalEntry`.  acme-client speaks ACME v1, which
+//! has no order objects, so entries name the directory that was used in
+//! place of an order URL.
+
+use {
+    crate::{error::DisplayChain, Domain},
+    chrono::{DateTime, Utc},
+    log::error,
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+const JOURNAL_FILE: &str = "renewals.journal";
+
+// Older entries are dropped once the journal grows past this.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub at: DateTime<Utc>,
+    pub domains: Vec<Domain>,
+    pub directory: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+pub(crate) struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub(crate) fn new(ssl_directory: &Path) -> Self {
+        Journal {
+            path: ssl_directory.join(JOURNAL_FILE),
+        }
+    }
+
+    /// Every readable entry, oldest first.  A missing journal is empty.
+    pub(crate) fn entries(&self) -> Vec<JournalEntry> {
+        fs::read_to_string(&self.path)
+            .map(|journal| {
+                journal
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The number of consecutive failed (non dry run) attempts for the
+    /// cert whose first domain is `domain`, and when the last one was made.
+    pub(crate) fn failures(&self, domain: &Domain) -> Option<(u32, DateTime<Utc>)> {
+        let mut failures = 0;
+        let mut last = None;
+        for entry in self
+            .entries()
+            .iter()
+            .rev()
+            .filter(|e| !e.dry_run && e.domains.first() == Some(domain))
+        {
+            if entry.success {
+                break;
+            }
+            failures += 1;
+            last = last.or(Some(entry.at));
+        }
+        last.map(|at| (failures, at))
+    }
+
+    /// The number of (non dry run) orders placed for `tenant` since `since`.
+    pub(crate) fn tenant_orders(&self, tenant: &str, since: DateTime<Utc>) -> usize {
+        self.entries()
+            .iter()
+            .filter(|e| !e.dry_run && e.at >= since && e.tenant.as_deref() == Some(tenant))
+            .count()
+    }
+
+    /// Appends `entry`.  Failing to write the journal is logged rather than
+    /// failing the renewal it describes.
+    pub(crate) fn record(&self, entry: &JournalEntry) {
+        if let Err(e) = self.append(entry) {
+            error!("{}: could not write journal: {}", self.path.display(), DisplayChain(&e));
+        }
+    }
+
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let line = serde_json::to_string(entry)?;
+        let mut entries = self.entries();
+        if entries.len() < MAX_ENTRIES {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            return writeln!(file, "{}", line);
+        }
+        entries.drain(..=entries.len() - MAX_ENTRIES);
+        let tmp = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        for entry in &entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        writeln!(file, "{}", line)?;
+        fs::rename(tmp, &self.path)
+    }
+}
@@ -0,0 +1,226 @@
+//! A `RenewalLock` kept in Kubernetes `coordination.k8s.io/v1` Leases.
+//!
+//! Given to `leader_election`, the replicas of a Deployment elect the one
+//! that renews through a Lease, the way controllers do, and the others
+//! reload what it stores in the shared ssl directory (there is no storage
+//! in Secrets yet, so that's a shared volume).  It works as a
+//! `renewal_lock` too.  Each lock is a Lease named `lets-encrypt.<domain>`,
+//! claimed with the API server's optimistic concurrency so two replicas
+//! can't both win.  The service account needs `get`, `create` and `update`
+//! on `leases` in its namespace.  Enabled by the `kubernetes` feature.
+
+use {
+    crate::{lock::holder_token, Domain, Error, RenewalLock},
+    chrono::{DateTime, Utc},
+    serde_json::{json, Value},
+    std::{
+        env, fs,
+        io::{self, BufReader},
+        sync::Arc,
+        time::Duration,
+    },
+    ureq::Agent,
+};
+
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const NAME_PREFIX: &str = "lets-encrypt.";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Locks held as Kubernetes Leases.
+pub struct KubernetesLease {
+    agent: Agent,
+    api: String,
+    namespace: String,
+    token: String,
+}
+
+fn lock_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Lock(Box::new(e))
+}
+
+fn invalid(message: &str) -> Error {
+    lock_error(io::Error::new(
+        io::ErrorKind::InvalidData,
+        message.to_string(),
+    ))
+}
+
+impl KubernetesLease {
+    /// Talks to the API server with the pod's service account, in the
+    /// pod's namespace.
+    pub fn in_cluster() -> Result<Self, Error> {
+        let namespace = fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT))?;
+        Self::in_namespace(namespace.trim())
+    }
+
+    /// Like `in_cluster`, but with the Leases in `namespace`.
+    pub fn in_namespace(namespace: &str) -> Result<Self, Error> {
+        let host = env::var("KUBERNETES_SERVICE_HOST")
+            .map_err(|_| invalid("KUBERNETES_SERVICE_HOST is not set; not in a cluster?"))?;
+        let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+        let mut tls = rustls::ClientConfig::new();
+        let ca = fs::File::open(format!("{}/ca.crt", SERVICE_ACCOUNT))?;
+        tls.root_store
+            .add_pem_file(&mut BufReader::new(ca))
+            .map_err(|()| invalid("unreadable service account ca.crt"))?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_config(Arc::new(tls))
+            .timeout(TIMEOUT)
+            .build();
+        // IPv6 service addresses need brackets in a URL.
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host
+        };
+        Ok(KubernetesLease {
+            agent,
+            api: format!("https://{}:{}", host, port),
+            namespace: namespace.to_string(),
+            token: holder_token(),
+        })
+    }
+
+    fn name(domain: &Domain) -> String {
+        format!(
+            "{}{}",
+            NAME_PREFIX,
+            domain.as_str().replace('*', "wildcard")
+        )
+    }
+
+    fn url(&self, name: Option<&str>) -> String {
+        let leases = format!(
+            "{}/apis/coordination.k8s.io/v1/namespaces/{}/leases",
+            self.api, self.namespace
+        );
+        match name {
+            Some(name) => format!("{}/{}", leases, name),
+            None => leases,
+        }
+    }
+
+    // Service account tokens are rotated, so it's read for every request.
+    fn authorization() -> Result<String, Error> {
+        let token = fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT))?;
+        Ok(format!("Bearer {}", token.trim()))
+    }
+
+    // The Lease, or None if it doesn't exist.
+    fn get(&self, name: &str) -> Result<Option<Value>, Error> {
+        match self
+            .agent
+            .get(&self.url(Some(name)))
+            .set("Authorization", &Self::authorization()?)
+            .call()
+        {
+            Ok(response) => Ok(Some(response.into_json()?)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(lock_error(e)),
+        }
+    }
+
+    // Writes the Lease, returning false if someone else changed it first.
+    fn write(&self, name: &str, lease: Option<Value>, spec: Value) -> Result<bool, Error> {
+        let authorization = Self::authorization()?;
+        let result = match lease {
+            None => self
+                .agent
+                .post(&self.url(None))
+                .set("Authorization", &authorization)
+                .send_json(json!({
+                    "apiVersion": "coordination.k8s.io/v1",
+                    "kind": "Lease",
+                    "metadata": { "name": name },
+                    "spec": spec,
+                })),
+            // The resourceVersion in the metadata makes the update fail if
+            // the Lease changed since it was read.
+            Some(mut lease) => {
+                lease["spec"] = spec;
+                self.agent
+                    .put(&self.url(Some(name)))
+                    .set("Authorization", &authorization)
+                    .send_json(lease)
+            }
+        };
+        match result {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(409, _)) => Ok(false),
+            Err(e) => Err(lock_error(e)),
+        }
+    }
+
+    fn spec(&self, ttl: Duration, transitions: i64) -> Value {
+        let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+        json!({
+            "holderIdentity": self.token,
+            "leaseDurationSeconds": ttl.as_secs().max(1),
+            "acquireTime": now,
+            "renewTime": now,
+            "leaseTransitions": transitions,
+        })
+    }
+
+    fn holder(lease: &Value) -> Option<&str> {
+        lease["spec"]["holderIdentity"].as_str()
+    }
+
+    fn expired(lease: &Value) -> bool {
+        let spec = &lease["spec"];
+        let renewed = spec["renewTime"]
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+        let duration = spec["leaseDurationSeconds"].as_i64().unwrap_or(0);
+        renewed.map_or(true, |renewed| {
+            renewed + chrono::Duration::seconds(duration) < Utc::now()
+        })
+    }
+}
+
+impl RenewalLock for KubernetesLease {
+    fn acquire(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        let name = Self::name(domain);
+        let lease = self.get(&name)?;
+        let transitions = match &lease {
+            None => 0,
+            Some(lease) => {
+                let holder = Self::holder(lease);
+                if holder.is_some() && holder != Some(&self.token) && !Self::expired(lease) {
+                    return Ok(false);
+                }
+                let transitions = lease["spec"]["leaseTransitions"].as_i64().unwrap_or(0);
+                if holder == Some(&self.token) {
+                    transitions
+                } else {
+                    transitions + 1
+                }
+            }
+        };
+        self.write(&name, lease, self.spec(ttl, transitions))
+    }
+
+    fn renew(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        let name = Self::name(domain);
+        let lease = match self.get(&name)? {
+            Some(lease) if Self::holder(&lease) == Some(&self.token) => lease,
+            _ => return Ok(false),
+        };
+        let mut spec = lease["spec"].clone();
+        spec["leaseDurationSeconds"] = json!(ttl.as_secs().max(1));
+        spec["renewTime"] = json!(Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string());
+        self.write(&name, Some(lease), spec)
+    }
+
+    fn release(&self, domain: &Domain) -> Result<(), Error> {
+        let name = Self::name(domain);
+        let lease = match self.get(&name)? {
+            Some(lease) if Self::holder(&lease) == Some(&self.token) => lease,
+            _ => return Ok(()),
+        };
+        let mut spec = lease["spec"].clone();
+        spec["holderIdentity"] = Value::Null;
+        self.write(&name, Some(lease), spec)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Private key generation for new certificates, and reading key files
+//! without leaving copies of them in memory.
+
+use {
+    openssl::{
+        ec::{EcGroup, EcKey},
+        error::ErrorStack,
+        nid::Nid,
+        pkey::{Id, PKey, Private},
+        rsa::Rsa,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::File,
+        io::{self, Read},
+        path::Path,
+    },
+    zeroize::Zeroizing,
+};
+
+/// Private key material held by the crate, wiped when dropped.
+pub(crate) type KeyBytes = Zeroizing<Vec<u8>>;
+
+/// Reads a key file into a buffer that is wiped when dropped.  The buffer
+/// is sized up front, since growing it would leave unwiped copies behind.
+pub(crate) fn read_key_file<P: AsRef<Path>>(path: P) -> io::Result<KeyBytes> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let mut key = Zeroizing::new(Vec::with_capacity(len + 1));
+    file.read_to_end(&mut key)?;
+    Ok(key)
+}
+
+/// The kind of private key generated for a certificate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    Rsa2048,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl Default for KeyType {
+    fn default() -> Self {
+        KeyType::Rsa2048
+    }
+}
+
+/// When a renewal gets a new private key rather than reusing the current
+/// one.  Every key generated is recorded in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRotation {
+    /// A fresh key for every certificate.
+    Always,
+    /// A fresh key once the current one has been used for this many
+    /// certificates.
+    Every(u32),
+    /// Keep the key for as long as it exists and matches the key type.
+    Never,
+}
+
+impl Default for KeyRotation {
+    fn default() -> Self {
+        KeyRotation::Always
+    }
+}
+
+impl KeyType {
+    /// Whether `key` is of this type, so can be reused for it.
+    pub(crate) fn matches(self, key: &PKey<Private>) -> bool {
+        match self {
+            KeyType::Rsa2048 => key.id() == Id::RSA && key.bits() == 2048,
+            KeyType::Rsa4096 => key.id() == Id::RSA && key.bits() == 4096,
+            KeyType::EcdsaP256 => key.id() == Id::EC && key.bits() == 256,
+            KeyType::EcdsaP384 => key.id() == Id::EC && key.bits() == 384,
+        }
+    }
+
+    pub(crate) fn generate(self) -> Result<PKey<Private>, ErrorStack> {
+        match self {
+            KeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?),
+            KeyType::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?),
+            KeyType::EcdsaP256 => Self::ec(Nid::X9_62_PRIME256V1),
+            KeyType::EcdsaP384 => Self::ec(Nid::SECP384R1),
+        }
+    }
+
+    fn ec(curve: Nid) -> Result<PKey<Private>, ErrorStack> {
+        let group = EcGroup::from_curve_name(curve)?;
+        PKey::from_ec_key(EcKey::generate(&group)?)
+    }
+}
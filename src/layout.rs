@@ -0,0 +1,96 @@
+//! Where a certificate's files go under the ssl directory, unless given
+//! explicit paths.
+//!
+//! `Layout::Nginx` uses the file names certbot made conventional, so that
+//! nginx, postfix and the like on the same host can be pointed at the
+//! certificates this crate manages:
+//!
+//! ```text
+//! live/example.com/fullchain.pem   the certificate and its chain, served
+//! live/example.com/privkey.pem     the private key
+//! live/example.com/cert.pem        the certificate alone
+//! live/example.com/chain.pem       the chain alone
+//! ```
+//!
+//! Those programs can't read a key wrapped by `KeyWrap`, so `wrap_keys`
+//! can't be combined with `Layout::Nginx` or `Layout::Certbot`; setting
+//! both panics.
+
+use {
+    crate::{CertBuilder, Domain, Error, LetsEncrypt},
+    openssl::x509::X509,
+    serde::{Deserialize, Serialize},
+    std::{fs, path::PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// `<domain>_cert.pem` and `<domain>_key.pem`, side by side.
+    Flat,
+    /// `live/<domain>/{fullchain,privkey,cert,chain}.pem`.
+    Nginx,
+    /// Certbot's own structure, the `live` files being symlinks into
+    /// `archive`; see the `certbot` module.
+    Certbot,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Flat
+    }
+}
+
+impl Layout {
+    pub(crate) fn key_file(self, domain: &Domain) -> PathBuf {
+        match self {
+            Layout::Flat => PathBuf::from(format!("{}_key.pem", domain)),
+            Layout::Nginx | Layout::Certbot => Self::live(domain).join("privkey.pem"),
+        }
+    }
+
+    pub(crate) fn cert_file(self, domain: &Domain) -> PathBuf {
+        match self {
+            Layout::Flat => PathBuf::from(format!("{}_cert.pem", domain)),
+            Layout::Nginx | Layout::Certbot => Self::live(domain).join("fullchain.pem"),
+        }
+    }
+
+    fn live(domain: &Domain) -> PathBuf {
+        PathBuf::from("live").join(domain.as_str())
+    }
+}
+
+impl LetsEncrypt {
+    // Called whenever the layout or key wrapping changes.
+    pub(crate) fn assert_keys_readable(&self) {
+        if self.config.key_wrap.is_some() && self.config.layout != Layout::Flat {
+            panic!(
+                "{:?} layout keys are read by other programs, so can't be wrapped",
+                self.config.layout
+            );
+        }
+    }
+
+    // Splits the stored chain into `cert.pem` and `chain.pem` beside it,
+    // for `Layout::Nginx`; nothing else needs them.
+    pub(crate) fn write_layout_files(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
+        if self.config.layout != Layout::Nginx {
+            return Ok(());
+        }
+        let cert_path = cert_builder.cert_path.as_ref().unwrap();
+        let directory = cert_path.parent().unwrap();
+        let stack = X509::stack_from_pem(&fs::read(cert_path)?)?;
+        let (leaf, chain) = match stack.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        fs::write(directory.join("cert.pem"), leaf.to_pem()?)?;
+        let mut pem = Vec::new();
+        for cert in chain {
+            pem.extend(cert.to_pem()?);
+        }
+        fs::write(directory.join("chain.pem"), pem)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Leader election between replicas sharing an ssl directory.
+//!
+//! Only the leader runs renewals.  Followers poll the shared directory and
+//! hot-reload whatever the leader writes there, and take over if the
+//! leader's lock lapses.
+
+use {
+    crate::{error::DisplayChain, Domain, Error, LetsEncrypt, RenewalLock},
+    actix::prelude::*,
+    chrono::{DateTime, Utc},
+    log::{debug, info, warn},
+    std::{collections::HashMap, io, sync::Arc, time::Duration},
+};
+
+// The `RenewalLock` key the leader holds.  `.invalid` can never be a real
+// certificate's domain.
+const LEADER_KEY: &str = "leader.lets-encrypt.invalid";
+
+#[derive(Clone)]
+pub(crate) struct Election {
+    lock: Arc<dyn RenewalLock>,
+    ttl: Duration,
+    leading: bool,
+    // Whether a campaign is waiting on the lock, so a slow one isn't
+    // overlapped by the next.
+    campaigning: bool,
+    // Expiry of each cert a follower has loaded, by first domain.
+    loaded: HashMap<Domain, Option<DateTime<Utc>>>,
+}
+
+impl Election {
+    pub(crate) fn new(lock: Arc<dyn RenewalLock>, ttl: Duration) -> Self {
+        Election {
+            lock,
+            ttl,
+            leading: false,
+            campaigning: false,
+            loaded: HashMap::new(),
+        }
+    }
+
+    fn key() -> Domain {
+        Domain::new(LEADER_KEY).unwrap()
+    }
+}
+
+impl LetsEncrypt {
+    pub(crate) fn is_follower(&self) -> bool {
+        self.election.as_ref().map_or(false, |e| !e.leading)
+    }
+
+    /// Runs for leader and keeps polling the lock.  The certs are checked
+    /// once elected, from `start_checks` the first time; until then, and
+    /// while following, they are reloaded from the store instead.
+    pub(crate) fn start_election(&mut self, ctx: &mut Context<Self>) {
+        let ttl = self.election.as_ref().unwrap().ttl;
+        self.campaign(true, ctx);
+        ctx.run_interval(ttl / 3, |act, ctx| act.campaign(false, ctx));
+    }
+
+    // Taking or renewing the lock can be a network round trip, as with
+    // `KubernetesLease` or Redis, so it runs on the blocking pool and the
+    // outcome is applied back on the actor.
+    fn campaign(&mut self, first: bool, ctx: &mut Context<Self>) {
+        let election = self.election.as_mut().unwrap();
+        if election.campaigning {
+            debug!("still waiting on the leader lock");
+            return;
+        }
+        election.campaigning = true;
+        let (lock, ttl, leading) = (election.lock.clone(), election.ttl, election.leading);
+        let result = actix_web::web::block(move || {
+            Ok::<_, ()>(if leading {
+                lock.renew(&Election::key(), ttl)
+            } else {
+                lock.acquire(&Election::key(), ttl)
+            })
+        });
+        ctx.spawn(result.into_actor(self).map(move |result, act, ctx| {
+            let result = result.unwrap_or_else(|_| {
+                Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    "campaign canceled",
+                )))
+            });
+            act.campaigned(first, leading, result, ctx)
+        }));
+    }
+
+    fn campaigned(
+        &mut self,
+        first: bool,
+        leading: bool,
+        result: Result<bool, Error>,
+        ctx: &mut Context<Self>,
+    ) {
+        let election = self.election.as_mut().unwrap();
+        election.campaigning = false;
+        match (leading, result) {
+            (true, Ok(true)) => {}
+            (false, Ok(true)) => {
+                info!("elected renewal leader");
+                election.leading = true;
+                election.loaded.clear();
+                if first {
+                    self.start_checks(ctx);
+                } else {
+                    self.lead(ctx);
+                }
+            }
+            (true, result) => {
+                if let Err(e) = result {
+                    warn!("could not renew leadership: {}", DisplayChain(&e));
+                }
+                warn!("lost renewal leadership, following");
+                election.leading = false;
+                self.follow(ctx);
+            }
+            (false, result) => {
+                if let Err(e) = result {
+                    warn!("could not run for leader: {}", DisplayChain(&e));
+                }
+                if first {
+                    info!("following the renewal leader");
+                }
+                self.reload_from_store(ctx);
+            }
+        }
+    }
+
+    // Checks every cert right away, then on its usual schedule.
+    fn lead(&mut self, ctx: &mut Context<Self>) {
+        for cert_builder in self.config.cert_builders.clone() {
+            if let Some(handle) = self.renewals.remove(&cert_builder.domains[0]) {
+                ctx.cancel_future(handle);
+            }
+            self.schedule_check(cert_builder, Duration::default(), 0, ctx);
+        }
+    }
+
+    fn follow(&mut self, ctx: &mut Context<Self>) {
+        for (_, handle) in self.renewals.drain() {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    // Installs each cert whose expiry on disk differs from the one last
+    // loaded, i.e. that the leader has renewed.  It only counts as loaded
+    // once installed, so a failed install is tried again on the next poll.
+    fn reload_from_store(&mut self, ctx: &mut Context<Self>) {
+        for cert_builder in self.config.cert_builders.clone() {
+            if !self.key_and_cert_present(&cert_builder) {
+                continue;
+            }
+            let not_after = cert_builder.not_after();
+            let loaded = &self.election.as_ref().unwrap().loaded;
+            if loaded.get(&cert_builder.domains[0]) == Some(&not_after) {
+                continue;
+            }
+            debug!(
+                "{}: loading certificate from the store",
+                cert_builder.domains[0]
+            );
+            self.install_in_background(cert_builder, ctx, move |act, cert_builder, _| {
+                if let Some(election) = &mut act.election {
+                    election
+                        .loaded
+                        .insert(cert_builder.domains[0].clone(), not_after);
+                }
+            });
+        }
+    }
+}
@@ -74,10 +74,130 @@
 
 // #![deny(missing_docs)]
 
+mod acceptor;
+mod account;
+mod admin;
+mod audit;
+mod caa;
+mod certbot;
+#[cfg(feature = "rustls")]
+mod certified_key;
+mod chain;
+mod chat;
+mod clock;
+mod credentials;
+mod ct_monitor;
+mod defaults;
+mod dev_mode;
+mod discovery;
+mod domain;
+#[cfg(feature = "email")]
+mod email;
+mod error;
+mod event;
+mod expiry_cache;
+mod export;
+mod fallback;
+mod hooks;
+mod import;
+mod journal;
+#[cfg(feature = "kubernetes")]
+mod k8s_lease;
+mod key;
+mod key_passphrase;
+mod key_wrap;
+mod layout;
+mod leader;
+mod lifecycle;
+mod lock;
+mod log_format;
+mod memory_keys;
+mod metrics;
+mod offline;
+mod on_demand;
+#[cfg(feature = "otel")]
+mod otel;
+mod privileges;
+mod probes;
+mod proxy;
+mod proxy_protocol;
+#[cfg(feature = "redis-lock")]
+mod redis_lock;
+mod reload;
+mod restart;
+mod schedule;
+mod sct;
+#[cfg(feature = "sentry")]
+mod sentry;
+mod serde_duration;
+mod shred;
+mod sni;
+mod socket_activation;
+mod status;
+mod status_page;
+mod tenant;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+mod tls_info;
+mod vhost;
+mod webhook;
+mod window;
+
+#[cfg(feature = "rustls")]
+pub use certified_key::CertifiedKeyHandle;
+#[cfg(feature = "email")]
+pub use email::EmailNotifier;
+#[cfg(feature = "kubernetes")]
+pub use k8s_lease::KubernetesLease;
+#[cfg(feature = "otel")]
+pub use otel::otel_layer;
+#[cfg(feature = "redis-lock")]
+pub use redis_lock::RedisLock;
+
+pub use {
+    admin::AdminApi,
+    audit::{AuditActor, AuditEntry, AuditOperation},
+    caa::{CaaCheck, CaaStatus, CAA_IDENTITY},
+    chain::ChainBundle,
+    chat::ChatNotifier,
+    clock::{Clock, SystemClock},
+    ct_monitor::CtMonitor,
+    defaults::CertDefaults,
+    discovery::{DiscoveredHost, Discovery, HostDiscovery},
+    domain::{Domain, DomainError},
+    error::{AcmeError, ConfigError, Error, NotRunning},
+    event::Event,
+    export::{CombinedPemExport, DerExport, Pkcs12Export},
+    fallback::StartupFallback,
+    hooks::CertInfo,
+    journal::JournalEntry,
+    layout::Layout,
+    lifecycle::LifecycleEvent,
+    key::{KeyRotation, KeyType},
+    key_passphrase::KeyPassphrase,
+    key_wrap::KeyWrap,
+    lock::{FileLock, RenewalLock},
+    log_format::LogFormat,
+    offline::{Offline, Unhealthy},
+    on_demand::OnDemand,
+    privileges::RunAs,
+    probes::{Probes, ReadinessGrace},
+    proxy::{HttpsRedirect, TrustedProxy},
+    restart::RestartStrategy,
+    schedule::{CheckSchedule, ScheduleError},
+    shred::KeyDeletion,
+    status::{CertStatus, LastError, RenewalResult},
+    status_page::StatusPage,
+    tenant::Tenant,
+    tls_info::TlsInfo,
+    vhost::VirtualHosts,
+    webhook::{Webhook, SIGNATURE_HEADER},
+    window::{RenewWindow, WindowError},
+};
+
 use {
-    acme_client::{error::Error, Directory},
+    acme_client::{Account, Directory},
     actix::prelude::*,
-    actix_files::NamedFile,
     actix_http::{
         Response, Request,
     },
@@ -86,23 +206,54 @@ use {
     },
     actix_web::{
         self,
+        dev::Server,
+        error::BlockingError,
         HttpServer,
-        App, HttpRequest,
+        App, HttpRequest, HttpResponse,
     },
-    chrono::{offset::TimeZone, Utc},
+    chrono::{offset::TimeZone, DateTime, Utc},
+    rand::Rng,
+    error::DisplayChain,
+    hooks::{run_deploy_hook, Hooks},
+    discovery::DiscoveryBoard,
+    journal::Journal,
+    leader::Election,
+    lifecycle::EventBus,
+    on_demand::{AllowDomain, Asking},
+    metrics::Metrics,
+    expiry_cache::ExpiryCache,
+    memory_keys::MemoryKeys,
+    log::{debug, error, info, warn},
     openssl::{
-        ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod},
+        error::ErrorStack,
+        pkey::{PKey, Private},
+        ssl::{SslAcceptor, SslAcceptorBuilder, SslMethod},
+        nid::Nid,
         x509::X509,
     },
+    shred::is_symlink,
+    sni::CertResolver,
+    socket_activation::Activated,
+    status::StatusBoard,
+    tracing::info_span,
+    zeroize::Zeroizing,
     std::{
+        collections::{HashMap, HashSet, VecDeque},
         env,
         ffi::OsStr,
         fmt::Display,
         fs::{self, File},
+        future::Future,
         io::{self, Read},
-        net::{SocketAddr, ToSocketAddrs},
+        net::{SocketAddr, TcpListener, ToSocketAddrs},
+        ops::Range,
         path::{Path, PathBuf},
-        time::Duration,
+        process,
+        sync::{
+            atomic::{self, AtomicUsize},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
     },
 };
 
@@ -110,22 +261,97 @@ const SECS_IN_MINUTE: u64 = 60;
 const SECS_IN_HOUR: u64 = SECS_IN_MINUTE * 60;
 const SECS_IN_DAY: u64 = SECS_IN_HOUR * 24;
 
-#[derive(Clone, Deserialize)]
+const PRODUCTION_DIRECTORY: &str = "https://acme-v01.api.letsencrypt.org/directory";
+const STAGING_DIRECTORY: &str = "https://acme-staging.api.letsencrypt.org/directory";
+// What the journal records as the directory of self-signed certificates.
+const DEV_MODE_DIRECTORY: &str = "self-signed";
+
+/// When set to `1`, `true` or `yes`, every certificate is requested from the
+/// staging directory, whatever its own `production` setting says.
+pub const STAGING_ENV_VAR: &str = "LETS_ENCRYPT_STAGING";
+
+/// Where `LetsEncrypt::health_check` serves certificate status.
+pub const DEFAULT_HEALTH_PATH: &str = "/.well-known/lets-encrypt-health";
+
+fn random_duration(max: Duration) -> Duration {
+    let max = max.as_millis() as u64;
+    if max == 0 {
+        Duration::from_secs(0)
+    } else {
+        Duration::from_millis(rand::thread_rng().gen_range(0, max))
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct CertBuilder {
-    addrs: Vec<SocketAddr>, // required
-    domains: Vec<String>,   // required
+    #[serde(default)]
+    addrs: Vec<SocketAddr>, // empty for certs only served through SNI
+    domains: Vec<Domain>,   // required
+
+    // The settings below are optional so that anything left unset can be
+    // filled in from the enabler's CertDefaults.
 
     #[serde(default)]
     email: Option<String>,
 
-    #[serde(default = "CertBuilder::default_production")]
-    production: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    production: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_type: Option<KeyType>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_rotation: Option<KeyRotation>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    renew_within: Option<Duration>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    check_every: Option<Duration>,
 
-    #[serde(default = "CertBuilder::default_renew_within")]
-    renew_within: Duration,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    jitter: Option<Duration>,
 
-    #[serde(default = "CertBuilder::default_check_every")]
-    check_every: std::time::Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    check_schedule: Option<CheckSchedule>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renew_window: Option<RenewWindow>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain: Option<ChainBundle>,
+
+    // Picked once, when the cert is added, so that a cert's renewal point
+    // doesn't wander from one check to the next.
+    #[serde(skip)]
+    renew_offset: Duration,
+
+    #[serde(skip)]
+    expiry: ExpiryCache,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pkcs12: Option<Pkcs12Export>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    der: Option<DerExport>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    combined_pem: Option<CombinedPemExport>,
 
     #[serde(default)]
     key_path: Option<PathBuf>,
@@ -135,24 +361,51 @@ pub struct CertBuilder {
 }
 
 impl CertBuilder {
+    /// Panicking version of `try_new`.
     pub fn new<S, D>(addrs: S, domains: &[D]) -> Self
+    where
+        S: ToSocketAddrs,
+        D: AsRef<str>,
+    {
+        Self::try_new(addrs, domains).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// A cert for `domains`, served on `addrs`.  The domains are
+    /// lowercased and repeats dropped; the first one that isn't a bare
+    /// hostname, such as `https://example.com`, is returned as the error.
+    ///
+    /// Panics if `addrs` can't be resolved.
+    pub fn try_new<S, D>(addrs: S, domains: &[D]) -> Result<Self, DomainError>
     where
         S: ToSocketAddrs,
         D: AsRef<str>,
     {
         let addrs = addrs.to_socket_addrs().unwrap().collect();
-        let domains = domains.iter().map(|d| d.as_ref().to_string()).collect();
+        let mut domains: Vec<Domain> = domains.iter().map(Domain::new).collect::<Result<_, _>>()?;
+        Domain::dedup(&mut domains);
 
-        CertBuilder {
+        Ok(CertBuilder {
             addrs,
             domains,
             email: None,
-            production: Self::default_production(),
-            renew_within: Self::default_renew_within(),
-            check_every: Self::default_check_every(),
+            production: None,
+            key_type: None,
+            key_rotation: None,
+            renew_within: None,
+            check_every: None,
+            jitter: None,
+            check_schedule: None,
+            renew_window: None,
+            chain: None,
+            tenant: None,
+            pkcs12: None,
+            der: None,
+            combined_pem: None,
+            renew_offset: Duration::default(),
+            expiry: ExpiryCache::default(),
             key_path: None,
             cert_path: None,
-        }
+        })
     }
 
     fn default_production() -> bool {
@@ -167,26 +420,227 @@ impl CertBuilder {
         Duration::new(12 * SECS_IN_HOUR, 0)
     }
 
+    fn is_production(&self) -> bool {
+        self.production.unwrap_or_else(Self::default_production)
+    }
+
+    fn resolved_key_type(&self) -> KeyType {
+        self.key_type.unwrap_or_default()
+    }
+
+    fn resolved_key_rotation(&self) -> KeyRotation {
+        self.key_rotation.unwrap_or_default()
+    }
+
+    fn resolved_renew_within(&self) -> Duration {
+        self.renew_within.unwrap_or_else(Self::default_renew_within)
+    }
+
+    fn resolved_check_every(&self) -> Duration {
+        self.check_every.unwrap_or_else(Self::default_check_every)
+    }
+
+    fn resolved_jitter(&self) -> Duration {
+        self.jitter.unwrap_or_default()
+    }
+
+    fn resolved_chain(&self) -> ChainBundle {
+        self.chain.unwrap_or_default()
+    }
+
+    fn apply_defaults(&mut self, defaults: &CertDefaults) {
+        if self.email.is_none() {
+            self.email = defaults.email.clone();
+        }
+        self.production = self.production.or(defaults.production);
+        self.key_type = self.key_type.or(defaults.key_type);
+        self.key_rotation = self.key_rotation.or(defaults.key_rotation);
+        self.renew_within = self.renew_within.or(defaults.renew_within);
+        self.check_every = self.check_every.or(defaults.check_every);
+        self.jitter = self.jitter.or(defaults.jitter);
+        self.chain = self.chain.or(defaults.chain);
+        if self.check_schedule.is_none() {
+            self.check_schedule = defaults.check_schedule.clone();
+        }
+    }
+
     pub fn email<E: AsRef<str>>(mut self, email: E) -> Self {
-        self.email = Some(email.as_ref().to_string());
+        self.set_email(email);
         self
     }
 
     pub fn test(mut self) -> Self {
-        self.production = false;
+        self.set_production(false);
+        self
+    }
+
+    /// Also writes a PKCS#12 bundle whenever the certificate is issued or
+    /// renewed; see `Pkcs12Export`.
+    pub fn pkcs12(mut self, export: Pkcs12Export) -> Self {
+        self.set_pkcs12(export);
+        self
+    }
+
+    /// Also writes DER copies of the certificate and key; see `DerExport`.
+    pub fn der(mut self, export: DerExport) -> Self {
+        self.set_der(export);
+        self
+    }
+
+    /// Also writes a combined key and chain PEM file for HAProxy; see
+    /// `CombinedPemExport`.
+    pub fn combined_pem(mut self, export: CombinedPemExport) -> Self {
+        self.set_combined_pem(export);
         self
     }
 
     pub fn renew_within(mut self, renewal: &Duration) -> Self {
-        self.renew_within = *renewal;
+        self.set_renew_within(renewal);
         self
     }
 
     pub fn check_every(mut self, period: &Duration) -> Self {
-        self.check_every = *period;
+        self.set_check_every(period);
+        self
+    }
+
+    pub fn key_type(mut self, key_type: KeyType) -> Self {
+        self.set_key_type(key_type);
+        self
+    }
+
+    /// Whether renewals get a fresh private key; `KeyRotation::Always` by
+    /// default.
+    pub fn key_rotation(mut self, rotation: KeyRotation) -> Self {
+        self.set_key_rotation(rotation);
+        self
+    }
+
+    /// Spreads renewals out by adding a random delay of up to `jitter` to
+    /// every check, and by renewing up to `jitter` earlier than
+    /// `renew_within` alone would.
+    pub fn jitter(mut self, jitter: &Duration) -> Self {
+        self.set_jitter(jitter);
+        self
+    }
+
+    /// Only checks (and so renews) the certificate at the times given by
+    /// `schedule`, instead of every `check_every`.  Retries after a failed
+    /// renewal also wait for the next scheduled time.
+    pub fn check_schedule(mut self, schedule: CheckSchedule) -> Self {
+        self.set_check_schedule(schedule);
+        self
+    }
+
+    /// Files the certificate under the named `Tenant` of the enabler.
+    pub fn tenant<T: Into<String>>(mut self, tenant: T) -> Self {
+        self.set_tenant(tenant);
+        self
+    }
+
+    /// Once the certificate is due, waits until the daily window between
+    /// `times` (`HH:MM` in the IANA time zone `tz`) before ordering and
+    /// installing its replacement, e.g. `renew_window("02:00".."05:00",
+    /// "Europe/Berlin")`.  `force_renew` ignores the window, and a
+    /// certificate not issued yet, or expiring within `expiry_warning`,
+    /// doesn't wait for it.
+    pub fn renew_window(mut self, times: Range<&str>, tz: &str) -> Self {
+        self.set_renew_window(times, tz);
+        self
+    }
+
+    /// What the stored and served chain holds; see `ChainBundle`.
+    pub fn chain(mut self, bundle: ChainBundle) -> Self {
+        self.set_chain(bundle);
+        self
+    }
+
+    /// Applies `f` to the builder, which is handy for conditional
+    /// configuration in the middle of a chain of consuming calls.
+    pub fn mutate<F: FnOnce(&mut Self)>(mut self, f: F) -> Self {
+        f(&mut self);
+        self
+    }
+
+    pub fn set_email<E: AsRef<str>>(&mut self, email: E) -> &mut Self {
+        self.email = Some(email.as_ref().to_string());
+        self
+    }
+
+    pub fn set_pkcs12(&mut self, export: Pkcs12Export) -> &mut Self {
+        self.pkcs12 = Some(export);
+        self
+    }
+
+    pub fn set_der(&mut self, export: DerExport) -> &mut Self {
+        self.der = Some(export);
+        self
+    }
+
+    pub fn set_combined_pem(&mut self, export: CombinedPemExport) -> &mut Self {
+        self.combined_pem = Some(export);
+        self
+    }
+
+    pub fn set_production(&mut self, production: bool) -> &mut Self {
+        self.production = Some(production);
+        self
+    }
+
+    pub fn set_renew_within(&mut self, renewal: &Duration) -> &mut Self {
+        self.renew_within = Some(*renewal);
+        self
+    }
+
+    pub fn set_check_every(&mut self, period: &Duration) -> &mut Self {
+        self.check_every = Some(*period);
+        self
+    }
+
+    pub fn set_key_type(&mut self, key_type: KeyType) -> &mut Self {
+        self.key_type = Some(key_type);
+        self
+    }
+
+    pub fn set_key_rotation(&mut self, rotation: KeyRotation) -> &mut Self {
+        self.key_rotation = Some(rotation);
         self
     }
 
+    pub fn set_jitter(&mut self, jitter: &Duration) -> &mut Self {
+        self.jitter = Some(*jitter);
+        self
+    }
+
+    pub fn set_check_schedule(&mut self, schedule: CheckSchedule) -> &mut Self {
+        self.check_schedule = Some(schedule);
+        self
+    }
+
+    pub fn set_tenant<T: Into<String>>(&mut self, tenant: T) -> &mut Self {
+        let tenant = tenant.into();
+        tenant::assert_valid_name(&tenant);
+        self.tenant = Some(tenant);
+        self
+    }
+
+    pub fn set_renew_window(&mut self, times: Range<&str>, tz: &str) -> &mut Self {
+        let window = RenewWindow::new(times, tz).unwrap_or_else(|e| panic!("{}", e));
+        self.renew_window = Some(window);
+        self
+    }
+
+    pub fn set_chain(&mut self, bundle: ChainBundle) -> &mut Self {
+        self.chain = Some(bundle);
+        self
+    }
+
+    /// Serializes the builder in the same JSON format that
+    /// `LetsEncrypt::encryption_enabler_from_env` reads.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
     fn key_and_cert_present(&self) -> bool {
         let key_path = self.key_path.as_ref().unwrap();
         let cert_path = self.cert_path.as_ref().unwrap();
@@ -194,35 +648,34 @@ impl CertBuilder {
         fs::metadata(key_path).is_ok() && fs::metadata(cert_path).is_ok()
     }
 
-    fn ssl_builder(&self) -> SslAcceptorBuilder {
-        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        builder
-            .set_private_key_file(self.key_path.clone().unwrap(), SslFiletype::PEM)
-            .unwrap();
-        builder
-            .set_certificate_chain_file(self.cert_path.clone().unwrap())
-            .unwrap();
-        builder
+    fn ssl_builder(&self, key: &PKey<Private>) -> Result<SslAcceptorBuilder, ErrorStack> {
+        let mut builder = Self::bare_ssl_builder();
+        builder.set_private_key(key)?;
+        builder.set_certificate_chain_file(self.cert_path.clone().unwrap())?;
+        Ok(builder)
     }
 
-    fn update_key_path(&mut self, ssl_directory: &PathBuf) {
-        Self::update_path(&mut self.key_path, "key", ssl_directory, &self.domains);
+    // Without a certificate of its own, for listeners whose certificate
+    // hasn't been issued yet; handshakes fail until the resolver has one.
+    fn bare_ssl_builder() -> SslAcceptorBuilder {
+        SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap()
     }
 
-    fn update_cert_path(&mut self, ssl_directory: &PathBuf) {
-        Self::update_path(&mut self.cert_path, "cert", ssl_directory, &self.domains);
+    fn update_key_path(&mut self, ssl_directory: &PathBuf, layout: Layout) {
+        let default = layout.key_file(&self.domains[0]);
+        Self::update_path(&mut self.key_path, default, ssl_directory);
     }
 
-    fn update_path(
-        pathp: &mut Option<PathBuf>,
-        stem: &str,
-        ssl_directory: &PathBuf,
-        domains: &[String],
-    ) {
+    fn update_cert_path(&mut self, ssl_directory: &PathBuf, layout: Layout) {
+        let default = layout.cert_file(&self.domains[0]);
+        Self::update_path(&mut self.cert_path, default, ssl_directory);
+    }
+
+    fn update_path(pathp: &mut Option<PathBuf>, default: PathBuf, ssl_directory: &PathBuf) {
         let file;
 
         match pathp {
-            None => file = PathBuf::from(format!("{}_{}.pem", &domains[0], stem)),
+            None => file = default,
             Some(path) => {
                 if path.is_absolute() {
                     *pathp = Some(path.to_path_buf());
@@ -235,215 +688,2954 @@ impl CertBuilder {
         *pathp = Some(ssl_directory.join(file));
     }
 
-    fn needs_building(&self) -> bool {
-        if !self.key_and_cert_present() {
-            return true;
-        }
+    // Undoes update_key_path and update_cert_path so that serialized
+    // paths don't get the ssl directory prepended a second time when
+    // they're read back in.
+    fn relativize_paths(&mut self, ssl_directory: &Path) {
+        Self::relativize_path(&mut self.key_path, ssl_directory);
+        Self::relativize_path(&mut self.cert_path, ssl_directory);
+    }
+
+    fn relativize_path(pathp: &mut Option<PathBuf>, ssl_directory: &Path) {
+        let relative = match pathp {
+            None => return,
+            Some(path) => match path.strip_prefix(ssl_directory) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => return,
+            },
+        };
+        *pathp = Some(relative);
+    }
+
+    // Whether the certificate on disk is unreadable or, at `now`, within
+    // its renewal period.
+    fn due(&self, now: DateTime<Utc>) -> bool {
+        let not_after = match self.not_after() {
+            Some(not_after) => not_after,
+            None => return true,
+        };
+        let time_remaining = not_after.signed_duration_since(now);
+        time_remaining.to_std().map_or(true, |remaining| {
+            remaining < self.resolved_renew_within() + self.renew_offset
+        })
+    }
+
+    fn cert(&self) -> Option<X509> {
         let path = self.cert_path.as_ref().unwrap();
 
-        let mut f = File::open(path).unwrap();
+        let mut f = File::open(path).ok()?;
         let mut cert = Vec::new();
-        f.read_to_end(&mut cert).unwrap();
-        let cert = X509::from_pem(&cert).ok().unwrap();
-        let not_after = cert.not_after().to_string();
-        let not_after = Utc
-            .datetime_from_str(&not_after, "%b %d %H:%M:%S %Y GMT")
-            .unwrap();
-        let time_remaining = not_after.signed_duration_since(Utc::now());
-        time_remaining.to_std().unwrap() < self.renew_within
+        f.read_to_end(&mut cert).ok()?;
+        X509::from_pem(&cert).ok()
+    }
+
+    // The issuer's common name (or organization), e.g. "R3".
+    fn issuer(&self) -> Option<String> {
+        let cert = self.cert()?;
+        let name = cert.issuer_name();
+        let entry = name
+            .entries_by_nid(Nid::COMMONNAME)
+            .chain(name.entries_by_nid(Nid::ORGANIZATIONNAME))
+            .next()?;
+        entry.data().as_utf8().ok().map(|s| s.to_string())
+    }
+
+    fn not_after(&self) -> Option<DateTime<Utc>> {
+        self.expiry.not_after(self.cert_path.as_ref().unwrap(), || {
+            let cert = self.cert()?;
+            let not_after = cert.not_after().to_string();
+            Utc.datetime_from_str(&not_after, "%b %d %H:%M:%S %Y GMT")
+                .ok()
+        })
+    }
+
+    fn info(&self) -> CertInfo {
+        CertInfo {
+            domains: self.domains.clone(),
+            cert_path: self.cert_path.clone().unwrap(),
+            key_path: self.key_path.clone().unwrap(),
+            combined_path: self.combined_pem.as_ref().map(|c| c.file(self)),
+            not_after: self.not_after(),
+        }
     }
 }
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use actix_web::dev::{MessageBody, ServiceRequest, ServiceResponse, AppConfig};
 use std::fmt;
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone)]
 pub struct LetsEncrypt {
+    // Shared by every clone, so handing the enabler to the app, the server,
+    // a worker or a blocking task doesn't copy the settings.  Changing one
+    // afterwards copies them for that clone alone.
+    config: Arc<Config>,
+
+    shared: Arc<Shared>,
+
+    // Only populated on the clone that is running as the actor.
+    renewals: HashMap<Domain, SpawnHandle>,
+
+    // First domains of certs to re-issue whether or not they're due.
+    forced: HashSet<Domain>,
+
+    // First domains of certs whose automatic renewal is paused.
+    paused: HashSet<Domain>,
+
+    election: Option<Election>,
+
+    // Only populated on the clone that is running as the actor.
+    asking: Asking,
+
+    // Hostnames issued on demand, which are subject to `max_certs`.
+    on_demand_certs: HashSet<Domain>,
+
+    // First domains of the certs being ordered, and the orders waiting for
+    // one of them to finish.
+    ordering: HashSet<Domain>,
+
+    queued: VecDeque<(CertBuilder, u32)>,
+
+    // First domains of the orders that outlived `order_deadline` and are
+    // still running, which aren't ordered again until they return.
+    overdue: HashSet<Domain>,
+}
+
+// Everything that is (de)serialized, and the hooks and extension points
+// set on the builder.
+#[derive(Clone, Deserialize, Serialize)]
+struct Config {
     #[serde(default = "LetsEncrypt::default_nonce_directory")]
     nonce_directory: PathBuf,
     #[serde(default = "LetsEncrypt::default_ssl_directory")]
     ssl_directory: PathBuf,
     cert_builders: Vec<CertBuilder>,
-}
 
-impl LetsEncrypt {
-    pub fn encryption_enabler() -> Self {
-        Self {
-            nonce_directory: Self::default_nonce_directory(),
-            ssl_directory: Self::default_ssl_directory(),
-            cert_builders: Vec::new(),
-        }
-    }
+    #[serde(default)]
+    layout: Layout,
 
-    /// Factory with configuration coming from an environment variable
-    ///
-    /// # Arguments
-    ///
-    /// * `env_var` - The name of the environment variable whose value is a
-    ///               JSON encoded CertBuilder
-    ///
-    /// # Example
-    ///
-    /// `SIMPLE_CONFIG='{"cert_builders":[]}'`
-    /// `COMPLEX_CONFIG='{"nonce_directory":"/var/nonce","ssl_directory":"ssl","cert_builders":[{"addrs":["0.0.0.0:8089"],"domains":["example.com"],"email":"ctm@example.com"},{"addrs":["0.0.0.0:8090"],"domains":["example.org","example.net"],"email":"ctm@example.org"},{"addrs":["0.0.0.0:8091"],"domains":["test.example.com"],"email":"ctm@example.com","production":false}]}'`
-    ///
-    /// ```rust
-    ///     let app_encryption_enabler = LetsEncrypt::encryption_enabler_from_env("SIMPLE_CONFIG");
-    ///
-    /// ```
-    pub fn encryption_enabler_from_env<K: AsRef<OsStr> + Display>(env_var: K) -> Self {
-        match env::var(&env_var) {
-            Err(e) => panic!("{}: {}", env_var, e),
-            Ok(config) => {
-                let mut enabler: LetsEncrypt = serde_json::from_str(&config)
-                    .unwrap_or_else(|_| panic!("Can't parse {}", env_var));
-
-                // Although we have the cert builders, we still have to
-                // add them to the enabler so that the paths will get
-                // set up properly.  This code smells bad.
-
-                for cert in enabler.cert_builders.split_off(0) {
-                    enabler = enabler.add_cert(cert);
-                }
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain_roots: Option<PathBuf>,
 
-                enabler
-            }
-        }
-    }
+    #[serde(default)]
+    force_staging: bool,
 
-    fn default_nonce_directory() -> PathBuf {
-        PathBuf::from("/var/tmp/lets_encrypt")
-    }
+    #[serde(default)]
+    dry_run: bool,
 
-    fn default_ssl_directory() -> PathBuf {
-        PathBuf::from("/ssl")
-    }
+    #[serde(default)]
+    dev_mode: bool,
 
-    pub fn nonce_directory<P>(mut self, path: P) -> Self
-    where
-        P: AsRef<Path>,
-    {
-        self.nonce_directory = PathBuf::from(path.as_ref());
-        self
-    }
+    #[serde(default)]
+    dev_ca: bool,
 
-    pub fn add_cert(mut self, mut cert: CertBuilder) -> Self {
-        cert.update_key_path(&self.ssl_directory);
-        cert.update_cert_path(&self.ssl_directory);
-        self.cert_builders.push(cert);
-        self
-    }
+    #[serde(default)]
+    log_format: LogFormat,
 
-    pub fn ssl_directory<P>(mut self, path: P) -> Self
-    where
-        P: Into<PathBuf>,
-    {
-        self.ssl_directory = path.into();
-        self
-    }
+    #[serde(default)]
+    require_scts: bool,
 
-    pub fn register<    B: MessageBody,
-        T: ServiceFactory<
-            Config = (),
-            Request = ServiceRequest,
-            Response = ServiceResponse<B>,
-            Error = actix_http::Error,
-            InitError = (),
-        >,>(&self, app: App<T, B>) -> App<T, B> {
-        struct NonceDir(PathBuf);
-        async fn handle(req: HttpRequest, nonce_dir: actix_web::web::Data<NonceDir>) -> NamedFile {
-            // TODO error on empty token
-            let token = req.match_info().query("token");
-            let mut path = nonce_dir.get_ref().0.clone();
-            path.push(".well-known");
-            path.push("acme-challenge");
-            path.push(token);
-            NamedFile::open(path.as_path()).unwrap()
-        }
+    #[serde(default)]
+    require_caa: bool,
 
-        app.data(NonceDir(self.nonce_directory.clone())).route("/.well-known/acme-challenge/{token}", actix_web::web::get().to(handle))
-    }
+    #[serde(default)]
+    cert_defaults: CertDefaults,
 
-    pub fn attach_certificates_to<F, I, S, B>(&self, mut server: HttpServer<F, I, S, B>) -> io::Result<HttpServer<F, I, S, B>>
-    where
-        F: Fn() -> I + Send + Clone + 'static,
-        I: IntoServiceFactory<S>,
-        S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
-        S::Error: Into<actix_http::Error>,
-        S::InitError: fmt::Debug,
-        S::Response: Into<Response<B>>,
-        B: MessageBody + 'static,
-    {
-        for cert_builder in &self.cert_builders {
-            if cert_builder.key_and_cert_present() {
-                server = server
-                    .bind_openssl(cert_builder.addrs[0], cert_builder.ssl_builder())?;
-            }
-        }
-        Ok(server)
-    }
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tenants: Vec<Tenant>,
 
-    fn build_cert(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
-        let directory = if cert_builder.production {
-            Directory::lets_encrypt()
-        } else {
-            Directory::from_url("https://acme-staging.api.letsencrypt.org/directory")
-        }?;
-        let mut account = directory.account_registration();
-        if let Some(email) = &cert_builder.email {
-            account = account.email(email);
-        }
-        let account = account.register()?;
+    #[serde(default = "LetsEncrypt::default_retry_initial", with = "serde_duration")]
+    retry_initial: Duration,
 
-        for domain in &cert_builder.domains {
-            let authorization = account.authorization(&domain)?;
-            let http_challenge = authorization
-                .get_http_challenge()
-                .ok_or("HTTP challenge not found")?;
-            http_challenge.save_key_authorization(self.nonce_directory.clone())?;
-            http_challenge.validate()?;
-        }
-        let domains: Vec<&str> = cert_builder.domains.iter().map(|d| &d[..]).collect();
-        let cert = account
-            .certificate_signer(&domains[..])
-            .sign_certificate()?;
-        cert.save_signed_certificate(&cert_builder.cert_path.as_ref().unwrap())?;
-        cert.save_private_key(&cert_builder.key_path.as_ref().unwrap())?;
-        Ok(())
-    }
+    #[serde(default = "LetsEncrypt::default_retry_max", with = "serde_duration")]
+    retry_max: Duration,
 
-    fn cert_built(&self, cert_builder: &CertBuilder) -> bool {
-        if cert_builder.needs_building() {
-            self
-                .build_cert(cert_builder)
-                .unwrap_or_else(|e| panic!("could not create cert: {}", e));
-            true
-        } else {
-            false
-        }
-    }
-}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deploy_hook: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    webhooks: Vec<Webhook>,
+
+    #[cfg(feature = "email")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    email_notifiers: Vec<EmailNotifier>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chat_notifiers: Vec<ChatNotifier>,
+
+    #[cfg(feature = "sentry")]
+    #[serde(default)]
+    report_to_sentry: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metrics_path: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health_path: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    admin: Option<AdminApi>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status_page: Option<StatusPage>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    probes: Option<Probes>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_demand: Option<OnDemand>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    discovery: Option<Discovery>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    trusted_proxies: Vec<TrustedProxy>,
+
+    #[serde(default)]
+    proxy_protocol: bool,
+
+    #[serde(default)]
+    tls_info: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ct_monitor: Option<CtMonitor>,
+
+    #[serde(default = "LetsEncrypt::default_expiry_warning", with = "serde_duration")]
+    expiry_warning: Duration,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escalate_after: Option<u32>,
+
+    #[serde(default = "LetsEncrypt::default_lock_ttl", with = "serde_duration")]
+    lock_ttl: Duration,
+
+    #[serde(default = "LetsEncrypt::default_max_concurrent_orders")]
+    max_concurrent_orders: usize,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    order_deadline: Option<Duration>,
+
+    #[serde(default)]
+    challenges_only_while_ordering: bool,
+
+    #[serde(default)]
+    reload_on_sighup: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account_key_path: Option<PathBuf>,
+
+    #[serde(default = "LetsEncrypt::default_account_key_mode")]
+    account_key_mode: u32,
+
+    #[serde(default)]
+    key_deletion: KeyDeletion,
+
+    #[serde(default)]
+    keys_in_memory: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_as: Option<RunAs>,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    startup_timeout: Option<Duration>,
+
+    #[serde(default)]
+    startup_fallback: StartupFallback,
+
+    #[serde(default, skip_serializing_if = "RestartStrategy::is_none")]
+    restart: RestartStrategy,
+
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_duration::option"
+    )]
+    shutdown_timeout: Option<Duration>,
+
+    #[serde(skip)]
+    hooks: Hooks,
+
+    #[serde(skip)]
+    renewal_lock: Option<Arc<dyn RenewalLock>>,
+
+    #[serde(skip)]
+    clock: Option<Arc<dyn Clock>>,
+
+    #[serde(skip)]
+    key_wrap: Option<KeyWrap>,
+
+    #[serde(skip)]
+    key_passphrase: Option<KeyPassphrase>,
+
+    #[serde(skip)]
+    allow_domain: Option<AllowDomain>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acme_directory: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    intermediate_url: Option<String>,
+}
+
+impl Serialize for LetsEncrypt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.config.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LetsEncrypt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Config::deserialize(deserializer).map(Self::with_config)
+    }
+}
+
+// State shared by every clone of an enabler, so that the clone registered
+// with the app, the one attached to the server and the running actor all
+// agree on which certificates are being served.
+#[derive(Default)]
+struct Shared {
+    resolver: CertResolver,
+    actor: Mutex<Option<Addr<LetsEncrypt>>>,
+    // The HTTPS addresses actually bound, keyed by each cert's first domain.
+    bound: Mutex<Vec<(Domain, SocketAddr)>>,
+    // The running server, if given to `manage_server`.
+    server: Mutex<Option<Server>>,
+    metrics: Metrics,
+    status: StatusBoard,
+    events: EventBus,
+    discovered: DiscoveryBoard,
+    keys: MemoryKeys,
+    // Held while writing the journal and the audit log.
+    records: Mutex<()>,
+    // How many challenges are being validated right now.
+    validating: Arc<AtomicUsize>,
+    // Sockets from systemd not yet claimed by a listener; None until
+    // first asked for.
+    activated: Mutex<Option<Vec<Activated>>>,
+    #[cfg(feature = "rustls")]
+    certified: certified_key::CertifiedKeys,
+    // When the actor first started, for the readiness grace period.
+    probes_since: Mutex<Option<Instant>>,
+    // Whether every certificate has been served, for `FirstReady`.
+    ready_once: Mutex<bool>,
+    // Held while loading or generating the development CA.
+    dev_ca: Mutex<()>,
+}
+
+// Counts a challenge as being validated until dropped.
+struct Validating(Arc<AtomicUsize>);
+
+impl Validating {
+    fn start(validating: &Arc<AtomicUsize>) -> Self {
+        validating.fetch_add(1, atomic::Ordering::SeqCst);
+        Validating(validating.clone())
+    }
+}
+
+impl Drop for Validating {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+struct AddCert(CertBuilder);
+
+impl Message for AddCert {
+    type Result = ();
+}
+
+struct RemoveCert(Domain);
+
+impl Message for RemoveCert {
+    type Result = ();
+}
+
+// None means every cert.
+struct ForceRenew(Option<Domain>);
+
+impl Message for ForceRenew {
+    type Result = ();
+}
+
+struct Revoke(Domain);
+
+impl Message for Revoke {
+    type Result = ();
+}
+
+struct Retire {
+    domain: Domain,
+    revoke: bool,
+}
+
+impl Message for Retire {
+    type Result = ();
+}
+
+// Adds the second domain to the cert covering the first.
+struct AddDomain(Domain, Domain);
+
+impl Message for AddDomain {
+    type Result = ();
+}
+
+struct PauseRenewal(Domain);
+
+// A handshake asked for this hostname, which has no certificate.
+struct IssueOnDemand(Domain);
+
+impl Message for IssueOnDemand {
+    type Result = ();
+}
+
+impl Message for PauseRenewal {
+    type Result = ();
+}
+
+struct ResumeRenewal(Domain);
+
+impl Message for ResumeRenewal {
+    type Result = ();
+}
+
+impl LetsEncrypt {
+    pub fn encryption_enabler() -> Self {
+        Self::with_config(Config {
+            nonce_directory: Self::default_nonce_directory(),
+            ssl_directory: Self::default_ssl_directory(),
+            cert_builders: Vec::new(),
+            layout: Layout::default(),
+            chain_roots: None,
+            force_staging: false,
+            dry_run: false,
+            dev_mode: false,
+            dev_ca: false,
+            log_format: LogFormat::default(),
+            require_scts: false,
+            require_caa: false,
+            cert_defaults: CertDefaults::default(),
+            tenants: Vec::new(),
+            retry_initial: Self::default_retry_initial(),
+            retry_max: Self::default_retry_max(),
+            deploy_hook: None,
+            webhooks: Vec::new(),
+            #[cfg(feature = "email")]
+            email_notifiers: Vec::new(),
+            chat_notifiers: Vec::new(),
+            #[cfg(feature = "sentry")]
+            report_to_sentry: false,
+            metrics_path: None,
+            health_path: None,
+            admin: None,
+            status_page: None,
+            probes: None,
+            on_demand: None,
+            discovery: None,
+            trusted_proxies: Vec::new(),
+            proxy_protocol: false,
+            tls_info: false,
+            ct_monitor: None,
+            expiry_warning: Self::default_expiry_warning(),
+            escalate_after: None,
+            lock_ttl: Self::default_lock_ttl(),
+            max_concurrent_orders: Self::default_max_concurrent_orders(),
+            order_deadline: None,
+            challenges_only_while_ordering: false,
+            reload_on_sighup: false,
+            account_key_path: None,
+            account_key_mode: Self::default_account_key_mode(),
+            key_deletion: KeyDeletion::default(),
+            keys_in_memory: false,
+            run_as: None,
+            startup_timeout: None,
+            startup_fallback: StartupFallback::default(),
+            restart: RestartStrategy::default(),
+            shutdown_timeout: None,
+            hooks: Hooks::default(),
+            renewal_lock: None,
+            clock: None,
+            key_wrap: None,
+            key_passphrase: None,
+            allow_domain: None,
+            acme_directory: None,
+            intermediate_url: None,
+        })
+    }
+
+    fn with_config(config: Config) -> Self {
+        Self {
+            config: Arc::new(config),
+            shared: Arc::default(),
+            renewals: HashMap::new(),
+            forced: HashSet::new(),
+            paused: HashSet::new(),
+            election: None,
+            asking: Asking::default(),
+            on_demand_certs: HashSet::new(),
+            ordering: HashSet::new(),
+            queued: VecDeque::new(),
+            overdue: HashSet::new(),
+        }
+    }
+
+    // The settings to change, first copied if another clone shares them.
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Factory with configuration coming from an environment variable
+    ///
+    /// # Arguments
+    ///
+    /// * `env_var` - The name of the environment variable whose value is a
+    ///               JSON encoded LetsEncrypt
+    ///
+    /// # Example
+    ///
+    /// `SIMPLE_CONFIG='{"cert_builders":[]}'`
+    /// `COMPLEX_CONFIG='{"nonce_directory":"/var/nonce","ssl_directory":"ssl","cert_builders":[{"addrs":["0.0.0.0:8089"],"domains":["example.com"],"email":"ctm@example.com","renew_within":"30d","check_every":"12h"},{"addrs":["0.0.0.0:8090"],"domains":["example.org","example.net"],"email":"ctm@example.org"},{"addrs":["0.0.0.0:8091"],"domains":["test.example.com"],"email":"ctm@example.com","production":false}]}'`
+    ///
+    /// ```rust
+    ///     let app_encryption_enabler = LetsEncrypt::try_from_env("SIMPLE_CONFIG")?;
+    ///
+    /// ```
+    ///
+    /// On a parse failure the error names the offending field, e.g.
+    /// `cert_builders[0].domains[1]`.
+    pub fn try_from_env<K: AsRef<OsStr> + Display>(env_var: K) -> Result<Self, ConfigError> {
+        let config = env::var(&env_var).map_err(|source| ConfigError::Env {
+            var: env_var.to_string(),
+            source,
+        })?;
+        Self::from_json(&env_var.to_string(), &config)
+    }
+
+    /// Like `try_from_env`, but the JSON is read from the file at `path`.
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_json(&path.display().to_string(), &config)
+    }
+
+    fn from_json(source_name: &str, config: &str) -> Result<Self, ConfigError> {
+        let deserializer = &mut serde_json::Deserializer::from_str(config);
+        let mut enabler: LetsEncrypt =
+            serde_path_to_error::deserialize(deserializer).map_err(|e| ConfigError::Parse {
+                var: source_name.to_string(),
+                path: e.path().to_string(),
+                source: e.into_inner(),
+            })?;
+
+        // Although we have the cert builders, we still have to
+        // add them to the enabler so that the paths will get
+        // set up properly.  This code smells bad.
+
+        for cert in enabler.config_mut().cert_builders.split_off(0) {
+            enabler.push_cert(cert);
+        }
+
+        Ok(enabler)
+    }
+
+    /// Panicking version of `try_from_env`.
+    #[deprecated(since = "0.3.0", note = "use `LetsEncrypt::try_from_env` instead")]
+    pub fn encryption_enabler_from_env<K: AsRef<OsStr> + Display>(env_var: K) -> Self {
+        Self::try_from_env(env_var).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Serializes the configuration in the format that
+    /// `encryption_enabler_from_env` reads, so a programmatically built
+    /// enabler can be dumped and later fed back in.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let mut config = Config::clone(&self.config);
+        for cert in &mut config.cert_builders {
+            cert.relativize_paths(&self.storage_directory(cert));
+        }
+        serde_json::to_string(&config)
+    }
+
+    fn default_nonce_directory() -> PathBuf {
+        PathBuf::from("/var/tmp/lets_encrypt")
+    }
+
+    fn default_ssl_directory() -> PathBuf {
+        PathBuf::from("/ssl")
+    }
+
+    fn default_retry_initial() -> Duration {
+        Duration::new(SECS_IN_MINUTE, 0)
+    }
+
+    fn default_retry_max() -> Duration {
+        Duration::new(6 * SECS_IN_HOUR, 0)
+    }
+
+    fn default_expiry_warning() -> Duration {
+        Duration::new(7 * SECS_IN_DAY, 0)
+    }
+
+    fn default_lock_ttl() -> Duration {
+        Duration::new(10 * SECS_IN_MINUTE, 0)
+    }
+
+    fn default_max_concurrent_orders() -> usize {
+        4
+    }
+
+    fn default_account_key_mode() -> u32 {
+        0o600
+    }
+
+    pub fn nonce_directory<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.set_nonce_directory(path);
+        self
+    }
+
+    /// How the files of certs added afterwards are named; see `Layout`.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.set_layout(layout);
+        self
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) -> &mut Self {
+        self.config_mut().layout = layout;
+        self.assert_keys_readable();
+        self
+    }
+
+    /// The PEM bundle of roots that `ChainBundle::WithRoot` and
+    /// `ChainBundle::WithoutCrossSigned` look roots up in, instead of the
+    /// system's `/etc/ssl/certs/ca-certificates.crt`.
+    pub fn chain_roots<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.set_chain_roots(path);
+        self
+    }
+
+    pub fn set_chain_roots<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.config_mut().chain_roots = Some(path.into());
+        self
+    }
+
+    pub fn add_cert(mut self, cert: CertBuilder) -> Self {
+        self.push_cert(cert);
+        self
+    }
+
+    pub fn ssl_directory<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.set_ssl_directory(path);
+        self
+    }
+
+    /// Requests every certificate from the staging directory, regardless of
+    /// the individual `CertBuilder`s.  See also `STAGING_ENV_VAR`.
+    pub fn force_staging(mut self) -> Self {
+        self.set_force_staging(true);
+        self
+    }
+
+    /// Orders every certificate from the staging directory at startup and
+    /// validates the challenges, but never saves or installs the result or
+    /// runs hooks.  Useful for checking that a
+    /// production host is reachable and configured correctly.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.set_dry_run(dry_run);
+        self
+    }
+
+    /// Never contacts an ACME directory, signing each certificate with its
+    /// own key instead, so `localhost` gets the same listeners and renewals
+    /// as production without touching the staging CA.  Browsers warn about
+    /// the certificates.
+    pub fn dev_mode(mut self) -> Self {
+        self.set_dev_mode(true);
+        self
+    }
+
+    /// Like `dev_mode`, but with the certificates signed by a local CA
+    /// that the machine's trust store can be told to trust, so browsers
+    /// don't warn; see `dev_ca_root`.
+    pub fn dev_ca(mut self) -> Self {
+        self.set_dev_mode(true);
+        self.set_dev_ca(true);
+        self
+    }
+
+    /// Orders every certificate from the ACME directory at `url`, such as
+    /// a step-ca or Boulder instance, instead of Let's Encrypt's; the
+    /// certificates' `production` setting and `force_staging` no longer
+    /// matter.  Its TLS is verified against the system's roots.  CAA
+    /// records aren't checked before ordering, since they name Let's
+    /// Encrypt.
+    pub fn acme_directory<S: Into<String>>(mut self, url: S) -> Self {
+        self.set_acme_directory(url);
+        self
+    }
+
+    /// Where to fetch the intermediate certificate bundled after the leaf,
+    /// for an `acme_directory` whose CA isn't Let's Encrypt's.
+    pub fn intermediate_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.set_intermediate_url(url);
+        self
+    }
+
+    /// Also logs a JSON record of every issuance step; see `LogFormat`.
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.set_log_format(log_format);
+        self
+    }
+
+    /// Refuses to install a new certificate without embedded SCTs from
+    /// enough distinct CT logs for Chrome and Safari; the order counts as
+    /// failed and the current certificate stays in place.
+    pub fn require_scts(mut self, require: bool) -> Self {
+        self.set_require_scts(require);
+        self
+    }
+
+    /// Refuses to order a certificate until every one of its domains has a
+    /// CAA record allowing Let's Encrypt; the error names the record to
+    /// add.  Without this, domains whose records only allow other CAs are
+    /// just warned about.
+    pub fn require_caa(mut self, require: bool) -> Self {
+        self.set_require_caa(require);
+        self
+    }
+
+    /// Applies `f` to the enabler, which is handy for conditional
+    /// configuration in the middle of a chain of consuming calls.
+    pub fn mutate<F: FnOnce(&mut Self)>(mut self, f: F) -> Self {
+        f(&mut self);
+        self
+    }
+
+    pub fn set_nonce_directory<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.config_mut().nonce_directory = PathBuf::from(path.as_ref());
+        self
+    }
+
+    /// Non-consuming equivalent of `add_cert`.  As with `add_cert`, the
+    /// cert's key and cert paths are resolved against the ssl directory
+    /// that is in effect at the time of the call.
+    pub fn push_cert(&mut self, cert: CertBuilder) -> &mut Self {
+        let cert = self.prepare_cert(cert);
+        self.config_mut().cert_builders.push(cert);
+        self
+    }
+
+    fn prepare_cert(&self, mut cert: CertBuilder) -> CertBuilder {
+        Domain::dedup(&mut cert.domains);
+        if let Some(tenant) = &cert.tenant {
+            tenant::assert_valid_name(tenant);
+        }
+        if cert.email.is_none() {
+            cert.email = self.tenant(&cert).and_then(Tenant::email_address).cloned();
+        }
+        cert.apply_defaults(&self.config.cert_defaults);
+        cert.renew_offset = random_duration(cert.resolved_jitter());
+        let storage_directory = self.storage_directory(&cert);
+        cert.update_key_path(&storage_directory, self.config.layout);
+        cert.update_cert_path(&storage_directory, self.config.layout);
+        cert
+    }
+
+    fn tenant(&self, cert: &CertBuilder) -> Option<&Tenant> {
+        let name = cert.tenant.as_ref()?;
+        self.config.tenants.iter().find(|t| t.name() == name)
+    }
+
+    // Where the cert's files go unless given absolute paths.
+    fn storage_directory(&self, cert: &CertBuilder) -> PathBuf {
+        match &cert.tenant {
+            Some(tenant) => self.config.ssl_directory.join("tenants").join(tenant),
+            None => self.config.ssl_directory.clone(),
+        }
+    }
+
+    /// Adds a tenant that certs added afterwards can name.
+    pub fn add_tenant(mut self, tenant: Tenant) -> Self {
+        self.push_tenant(tenant);
+        self
+    }
+
+    pub fn push_tenant(&mut self, tenant: Tenant) -> &mut Self {
+        tenant::assert_valid_name(tenant.name());
+        self.config_mut().tenants.retain(|t| t.name() != tenant.name());
+        self.config_mut().tenants.push(tenant);
+        self
+    }
+
+    // Err if the cert's tenant has placed its daily quota of orders.
+    fn check_tenant_limit(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
+        let tenant = match self.tenant(cert_builder) {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+        if let Some(limit) = tenant.order_limit() {
+            let since = self.now() - chrono::Duration::days(1);
+            if self.journal().tenant_orders(tenant.name(), since) >= limit as usize {
+                return Err(Error::RateLimited(tenant.name().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts managing `cert` on a running enabler.  The certificate is
+    /// issued if necessary and then served, via SNI, on the listeners that
+    /// were bound by `attach_certificates_to`; its `addrs` are not bound
+    /// since the server is already running.
+    pub fn add_cert_runtime(&self, cert: CertBuilder) -> Result<(), NotRunning> {
+        let cert = self.prepare_cert(cert);
+        self.actor()?.do_send(AddCert(cert));
+        Ok(())
+    }
+
+    /// Stops renewing and serving the certificate that covers `domain`.
+    /// The key and certificate files are left in place.
+    pub fn remove_cert<D: AsRef<str>>(&self, domain: D) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(RemoveCert(domain));
+        Ok(())
+    }
+
+    /// Adds a hook that runs, and is waited for, before an order for a new
+    /// certificate is placed by the renewal schedule.
+    pub fn on_before_order<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CertInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.config_mut().hooks.add_before_order(hook);
+        self
+    }
+
+    /// Adds a hook that runs after a certificate issued by the renewal
+    /// schedule has been saved and is being served, e.g. to push it to a
+    /// CDN.
+    pub fn on_renewed<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CertInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.config_mut().hooks.add_renewed(hook);
+        self
+    }
+
+    /// Once a certificate has failed to renew `failures` times in a row,
+    /// each further failure is logged as an error, runs the `on_critical`
+    /// hooks and marks the certificate critical on the health endpoint,
+    /// until a renewal succeeds.
+    pub fn escalate_after(mut self, failures: u32) -> Self {
+        self.set_escalate_after(failures);
+        self
+    }
+
+    pub fn set_escalate_after(&mut self, failures: u32) -> &mut Self {
+        self.config_mut().escalate_after = Some(failures);
+        self
+    }
+
+    /// Adds a hook that is given the `Event::Failed` of every renewal
+    /// failure past the `escalate_after` threshold, e.g. to page someone.
+    pub fn on_critical<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.config_mut().hooks.add_critical(hook);
+        self
+    }
+
+    /// Runs `command` with `sh -c` after each certificate issued by the
+    /// renewal schedule is being served, mirroring certbot's
+    /// `--deploy-hook`.  The domains and file paths are passed in
+    /// `LETS_ENCRYPT_*` environment variables, plus certbot's
+    /// `RENEWED_DOMAINS`.
+    pub fn deploy_hook<C: Into<String>>(mut self, command: C) -> Self {
+        self.set_deploy_hook(command);
+        self
+    }
+
+    pub fn set_deploy_hook<C: Into<String>>(&mut self, command: C) -> &mut Self {
+        self.config_mut().deploy_hook = Some(command.into());
+        self
+    }
+
+    /// Adds a webhook that every lifecycle `Event` is POSTed to.
+    pub fn webhook(mut self, webhook: Webhook) -> Self {
+        self.add_webhook(webhook);
+        self
+    }
+
+    pub fn add_webhook(&mut self, webhook: Webhook) -> &mut Self {
+        self.config_mut().webhooks.push(webhook);
+        self
+    }
+
+    /// Mails when renewal keeps failing or a certificate is about to
+    /// expire.
+    #[cfg(feature = "email")]
+    pub fn email_notifier(mut self, notifier: EmailNotifier) -> Self {
+        self.add_email_notifier(notifier);
+        self
+    }
+
+    #[cfg(feature = "email")]
+    pub fn add_email_notifier(&mut self, notifier: EmailNotifier) -> &mut Self {
+        self.config_mut().email_notifiers.push(notifier);
+        self
+    }
+
+    /// Sends each failed issuance attempt to Sentry, with the domain, the
+    /// CA's error and the attempt number, grouped into one issue per
+    /// certificate.  Sentry itself is initialized by the application.
+    #[cfg(feature = "sentry")]
+    pub fn report_to_sentry(mut self, report: bool) -> Self {
+        self.set_report_to_sentry(report);
+        self
+    }
+
+    #[cfg(feature = "sentry")]
+    pub fn set_report_to_sentry(&mut self, report: bool) -> &mut Self {
+        self.config_mut().report_to_sentry = report;
+        self
+    }
+
+    /// Posts lifecycle events to a Slack or Discord channel.
+    pub fn chat_notifier(mut self, notifier: ChatNotifier) -> Self {
+        self.add_chat_notifier(notifier);
+        self
+    }
+
+    pub fn add_chat_notifier(&mut self, notifier: ChatNotifier) -> &mut Self {
+        self.config_mut().chat_notifiers.push(notifier);
+        self
+    }
+
+    /// Makes `register` serve Prometheus metrics at `path`: seconds until
+    /// each certificate expires, issuance attempts, successes and failures
+    /// per certificate, and a histogram of issuance latency.
+    pub fn metrics_path<P: Into<String>>(mut self, path: P) -> Self {
+        self.set_metrics_path(path);
+        self
+    }
+
+    pub fn set_metrics_path<P: Into<String>>(&mut self, path: P) -> &mut Self {
+        self.config_mut().metrics_path = Some(path.into());
+        self
+    }
+
+    /// Makes `register` serve a JSON array of `CertStatus` at
+    /// `DEFAULT_HEALTH_PATH`, answering 503 when any certificate is missing
+    /// or expired, for load balancer health checks.
+    pub fn health_check(self) -> Self {
+        self.health_path(DEFAULT_HEALTH_PATH)
+    }
+
+    /// Like `health_check`, but served at `path`.
+    pub fn health_path<P: Into<String>>(mut self, path: P) -> Self {
+        self.set_health_path(path);
+        self
+    }
+
+    pub fn set_health_path<P: Into<String>>(&mut self, path: P) -> &mut Self {
+        self.config_mut().health_path = Some(path.into());
+        self
+    }
+
+    /// Makes `register` mount the token protected admin API; see
+    /// `AdminApi` for the endpoints.
+    pub fn admin_api(mut self, admin: AdminApi) -> Self {
+        self.set_admin_api(admin);
+        self
+    }
+
+    pub fn set_admin_api(&mut self, admin: AdminApi) -> &mut Self {
+        self.config_mut().admin = Some(admin);
+        self
+    }
+
+    /// Watches CT logs for certificates issued for the managed domains by
+    /// anyone else; see `CtMonitor`.
+    pub fn monitor_ct(mut self, monitor: CtMonitor) -> Self {
+        self.set_monitor_ct(monitor);
+        self
+    }
+
+    pub fn set_monitor_ct(&mut self, monitor: CtMonitor) -> &mut Self {
+        self.config_mut().ct_monitor = Some(monitor);
+        self
+    }
+
+    /// Makes `register` serve an HTML summary of every certificate, behind
+    /// HTTP basic auth; see `StatusPage`.
+    pub fn status_page(mut self, page: StatusPage) -> Self {
+        self.set_status_page(page);
+        self
+    }
+
+    pub fn set_status_page(&mut self, page: StatusPage) -> &mut Self {
+        self.config_mut().status_page = Some(page);
+        self
+    }
+
+    /// Makes `register` serve Kubernetes liveness and readiness probes;
+    /// readiness fails until every production certificate is served, within
+    /// the `ReadinessGrace` of `probes`.
+    pub fn kubernetes_probes(mut self, probes: Probes) -> Self {
+        self.set_kubernetes_probes(probes);
+        self
+    }
+
+    pub fn set_kubernetes_probes(&mut self, probes: Probes) -> &mut Self {
+        self.config_mut().probes = Some(probes);
+        self
+    }
+
+    /// An `Event::Expiring` is sent on every check of a certificate that
+    /// expires within `warning`, which only happens if renewals keep
+    /// failing.
+    pub fn expiry_warning(mut self, warning: &Duration) -> Self {
+        self.set_expiry_warning(warning);
+        self
+    }
+
+    pub fn set_expiry_warning(&mut self, warning: &Duration) -> &mut Self {
+        self.config_mut().expiry_warning = *warning;
+        self
+    }
+
+    /// Where the ACME account key is kept, resolved against the ssl
+    /// directory; `account.key` there by default.  Tenants keep theirs in
+    /// their own directories regardless.
+    pub fn account_key_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.set_account_key_path(path);
+        self
+    }
+
+    pub fn set_account_key_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_mut().account_key_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The permissions of the account key file, 0o600 by default.
+    pub fn account_key_mode(mut self, mode: u32) -> Self {
+        self.set_account_key_mode(mode);
+        self
+    }
+
+    pub fn set_account_key_mode(&mut self, mode: u32) -> &mut Self {
+        self.config_mut().account_key_mode = mode;
+        self
+    }
+
+    /// How private keys that are replaced, retired or evicted are
+    /// deleted; overwritten once before being unlinked by default.
+    pub fn key_deletion(mut self, deletion: KeyDeletion) -> Self {
+        self.set_key_deletion(deletion);
+        self
+    }
+
+    pub fn set_key_deletion(&mut self, deletion: KeyDeletion) -> &mut Self {
+        self.config_mut().key_deletion = deletion;
+        self
+    }
+
+    /// Keeps private keys in memory only, never writing them to disk; see
+    /// the `memory_keys` module.  Certificates are still written to the
+    /// ssl directory, but are issued again after every restart.  Exports
+    /// carrying the key, such as PKCS#12, are refused.
+    pub fn keys_in_memory(mut self) -> Self {
+        self.set_keys_in_memory(true);
+        self
+    }
+
+    pub fn set_keys_in_memory(&mut self, in_memory: bool) -> &mut Self {
+        self.config_mut().keys_in_memory = in_memory;
+        self
+    }
+
+    /// The user and group `drop_privileges` switches to, so the process
+    /// can be started as root just to bind ports 80 and 443.
+    pub fn run_as(mut self, uid: u32, gid: u32) -> Self {
+        self.set_run_as(uid, gid);
+        self
+    }
+
+    pub fn set_run_as(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.config_mut().run_as = Some(RunAs { uid, gid });
+        self
+    }
+
+    /// Encrypts private keys before they are written, and decrypts them
+    /// only to build the listeners; see `KeyWrap`.  Exports carrying the
+    /// key, such as PKCS#12, would be plaintext, so are refused.  Panics
+    /// with a `Layout` other programs read keys from.
+    pub fn wrap_keys(mut self, wrap: KeyWrap) -> Self {
+        self.set_wrap_keys(wrap);
+        self
+    }
+
+    pub fn set_wrap_keys(&mut self, wrap: KeyWrap) -> &mut Self {
+        self.config_mut().key_wrap = Some(wrap);
+        self.assert_keys_readable();
+        self
+    }
+
+    /// Decrypts passphrase-protected PEM keys, e.g. ones imported from
+    /// other tooling, with the passphrase from `passphrase`.
+    pub fn key_passphrase(mut self, passphrase: KeyPassphrase) -> Self {
+        self.set_key_passphrase(passphrase);
+        self
+    }
+
+    pub fn set_key_passphrase(&mut self, passphrase: KeyPassphrase) -> &mut Self {
+        self.config_mut().key_passphrase = Some(passphrase);
+        self
+    }
+
+    /// How many certificates are ordered at once, 4 by default; the rest
+    /// wait their turn.  Orders run on actix's blocking thread pool, so
+    /// many certs missing at startup don't take the sum of their orders'
+    /// latencies to be served.
+    pub fn max_concurrent_orders(mut self, max: usize) -> Self {
+        self.set_max_concurrent_orders(max);
+        self
+    }
+
+    pub fn set_max_concurrent_orders(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_concurrent_orders must be at least 1");
+        self.config_mut().max_concurrent_orders = max;
+        self
+    }
+
+    /// How long an order may take before it counts as failed and its turn
+    /// goes to the next queued one, so a hung connection to the ACME
+    /// directory doesn't hold up every other renewal.  The order itself
+    /// can't be interrupted: the cert isn't ordered again until it returns,
+    /// and it is still installed if it then succeeds.  Unlimited by default.
+    pub fn order_deadline(mut self, deadline: &Duration) -> Self {
+        self.set_order_deadline(deadline);
+        self
+    }
+
+    pub fn set_order_deadline(&mut self, deadline: &Duration) -> &mut Self {
+        self.config_mut().order_deadline = Some(*deadline);
+        self
+    }
+
+    /// Answers 404 on the challenge route, without looking at the nonce
+    /// directory, except while this process is validating a challenge.
+    /// Only for deployments where the CA's requests reach the instance
+    /// that ordered, i.e. not replicas behind a load balancer sharing a
+    /// nonce directory.
+    pub fn challenges_only_while_ordering(mut self, only: bool) -> Self {
+        self.set_challenges_only_while_ordering(only);
+        self
+    }
+
+    pub fn set_challenges_only_while_ordering(&mut self, only: bool) -> &mut Self {
+        self.config_mut().challenges_only_while_ordering = only;
+        self
+    }
+
+    /// Reloads every certificate from the store on SIGHUP; see
+    /// `LetsEncrypt::reload`.
+    pub fn reload_on_sighup(mut self, reload: bool) -> Self {
+        self.set_reload_on_sighup(reload);
+        self
+    }
+
+    pub fn set_reload_on_sighup(&mut self, reload: bool) -> &mut Self {
+        self.config_mut().reload_on_sighup = reload;
+        self
+    }
+
+    /// Takes `lock` before ordering a certificate, so that of several
+    /// replicas sharing an ssl directory only one orders it.  The others
+    /// check again after the initial retry delay, by which time the new
+    /// certificate is usually on disk.  `ttl` bounds how long a replica
+    /// that dies mid-order holds things up.
+    pub fn renewal_lock<L: RenewalLock + 'static>(mut self, lock: L, ttl: &Duration) -> Self {
+        self.set_renewal_lock(lock, ttl);
+        self
+    }
+
+    pub fn set_renewal_lock<L: RenewalLock + 'static>(
+        &mut self,
+        lock: L,
+        ttl: &Duration,
+    ) -> &mut Self {
+        self.config_mut().renewal_lock = Some(Arc::new(lock));
+        self.config_mut().lock_ttl = *ttl;
+        self
+    }
+
+    /// Tells the time by `clock` rather than the system's, e.g. a
+    /// `test_support::FakeClock` to see which certificates renew when.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) -> &mut Self {
+        self.config_mut().clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Applies `fallback` to every certificate that still hasn't been
+    /// issued `timeout` after the enabler starts, e.g. because the CA or
+    /// DNS is down.  Without a timeout the server just keeps serving HTTP.
+    pub fn startup_timeout(mut self, timeout: &Duration, fallback: StartupFallback) -> Self {
+        self.set_startup_timeout(timeout, fallback);
+        self
+    }
+
+    pub fn set_startup_timeout(
+        &mut self,
+        timeout: &Duration,
+        fallback: StartupFallback,
+    ) -> &mut Self {
+        self.config_mut().startup_timeout = Some(*timeout);
+        self.config_mut().startup_fallback = fallback;
+        self
+    }
+
+    /// What to do once a certificate has been issued, for setups where
+    /// something besides this server needs restarting to pick it up.
+    pub fn restart_strategy(mut self, restart: RestartStrategy) -> Self {
+        self.set_restart_strategy(restart);
+        self
+    }
+
+    pub fn set_restart_strategy(&mut self, restart: RestartStrategy) -> &mut Self {
+        self.config_mut().restart = restart;
+        self
+    }
+
+    /// How long a restart waits for in-flight requests before cutting them
+    /// off; applied to the server by `attach_certificates_to`.  Actix
+    /// defaults to 30 seconds.
+    pub fn shutdown_timeout(mut self, timeout: &Duration) -> Self {
+        self.set_shutdown_timeout(timeout);
+        self
+    }
+
+    pub fn set_shutdown_timeout(&mut self, timeout: &Duration) -> &mut Self {
+        self.config_mut().shutdown_timeout = Some(*timeout);
+        self
+    }
+
+    /// Lets a `RestartStrategy` shut `server`, the result of running the
+    /// server passed to `attach_certificates_to`, down gracefully instead
+    /// of stopping the system under it.
+    pub fn manage_server(&self, server: &Server) {
+        *self.shared.server.lock().unwrap() = Some(server.clone());
+    }
+
+    fn server(&self) -> Option<Server> {
+        self.shared.server.lock().unwrap().clone()
+    }
+
+    /// Elects one of several replicas sharing an ssl directory, by holding
+    /// `lock` for `ttl` at a time, to issue and renew every certificate.
+    /// The others only reload certificates from the directory as the
+    /// leader replaces them, and take over if it stops renewing its lock.
+    pub fn leader_election<L: RenewalLock + 'static>(mut self, lock: L, ttl: &Duration) -> Self {
+        self.set_leader_election(lock, ttl);
+        self
+    }
+
+    pub fn set_leader_election<L: RenewalLock + 'static>(
+        &mut self,
+        lock: L,
+        ttl: &Duration,
+    ) -> &mut Self {
+        self.election = Some(Election::new(Arc::new(lock), *ttl));
+        self
+    }
+
+    /// Re-issues the certificate covering `domain` right away, ignoring
+    /// `renew_within`, e.g. after a key compromise.  Failures are retried
+    /// like any other failed renewal.
+    pub fn force_renew<D: AsRef<str>>(&self, domain: D) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(ForceRenew(Some(domain)));
+        Ok(())
+    }
+
+    /// Re-issues every certificate right away, e.g. after a CA mass
+    /// revocation.
+    pub fn force_renew_all(&self) -> Result<(), NotRunning> {
+        self.actor()?.do_send(ForceRenew(None));
+        Ok(())
+    }
+
+    /// Issues certificates for hostnames allowed by `on_demand` the first
+    /// time a client asks for one, using the `cert_defaults`, instead of
+    /// requiring a `CertBuilder` for each.  They are served on every
+    /// listener bound by `attach_certificates_to`.
+    pub fn on_demand(mut self, on_demand: OnDemand) -> Self {
+        self.set_on_demand(on_demand);
+        self
+    }
+
+    pub fn set_on_demand(&mut self, on_demand: OnDemand) -> &mut Self {
+        self.config_mut().on_demand = Some(on_demand);
+        self
+    }
+
+    /// Counts the hostnames plain HTTP requests ask for, once the app is
+    /// wrapped in `host_discovery()`, and proposes those that are popular
+    /// and point here for certificates; see `discovered_hosts`.  With
+    /// `auto_issue` they go to on-demand issuance, which must be set up.
+    pub fn discover_hosts(mut self, discovery: Discovery) -> Self {
+        self.set_discover_hosts(discovery);
+        self
+    }
+
+    pub fn set_discover_hosts(&mut self, discovery: Discovery) -> &mut Self {
+        self.config_mut().discovery = Some(discovery);
+        self
+    }
+
+    /// The reverse proxies whose `Forwarded` and `X-Forwarded-*` headers
+    /// are believed, as addresses or CIDR blocks such as `10.0.0.0/8`.
+    /// Panics if one doesn't parse.
+    pub fn trusted_proxies(mut self, proxies: &[&str]) -> Self {
+        self.set_trusted_proxies(proxies);
+        self
+    }
+
+    pub fn set_trusted_proxies(&mut self, proxies: &[&str]) -> &mut Self {
+        self.config_mut().trusted_proxies = proxies
+            .iter()
+            .map(|proxy| proxy.parse().unwrap_or_else(|e| panic!("{}", e)))
+            .collect();
+        self
+    }
+
+    /// Expects a PROXY protocol header on every connection to the HTTPS
+    /// listeners, as sent by a TCP load balancer.  They are then served by
+    /// `proxy_protocol_server`, and `attach_certificates_to` fails.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.set_proxy_protocol(enabled);
+        self
+    }
+
+    pub fn set_proxy_protocol(&mut self, enabled: bool) -> &mut Self {
+        self.config_mut().proxy_protocol = enabled;
+        self
+    }
+
+    /// Has `attach_certificates_to` install `TlsInfo::capture` as the
+    /// server's `on_connect` callback, so handlers can extract `TlsInfo`.
+    /// Off by default, since it replaces any callback set before; an
+    /// application with its own should call `TlsInfo::capture` from it
+    /// instead.
+    pub fn tls_info(mut self, enabled: bool) -> Self {
+        self.set_tls_info(enabled);
+        self
+    }
+
+    pub fn set_tls_info(&mut self, enabled: bool) -> &mut Self {
+        self.config_mut().tls_info = enabled;
+        self
+    }
+
+    /// Middleware to `wrap` the app in for `discover_hosts`.
+    pub fn host_discovery(&self) -> HostDiscovery {
+        HostDiscovery::new(self.clone())
+    }
+
+    /// Middleware to `wrap` the app served on port 80 in, redirecting
+    /// everything but the ACME challenges to HTTPS, on the port the
+    /// requested host's certificate is served on.  Behind a proxy that
+    /// terminates TLS, requests the proxy forwarded from HTTPS pass
+    /// through, as long as it is one of the `trusted_proxies`.
+    pub fn https_redirect(&self) -> HttpsRedirect {
+        HttpsRedirect::new(self.clone())
+    }
+
+    /// Every hostname seen by `host_discovery`, without a certificate.
+    pub fn discovered_hosts(&self) -> Vec<DiscoveredHost> {
+        self.shared.discovered.all()
+    }
+
+    fn issue_discovered(&self, host: Domain) {
+        if let Ok(actor) = self.actor() {
+            actor.do_send(IssueOnDemand(host));
+        }
+    }
+
+    /// Decides whether a hostname that isn't on the `on_demand` allow list
+    /// may be issued a certificate, e.g. by looking it up in the customer
+    /// database.  Refused hostnames aren't asked about again for a while.
+    pub fn allow_domain<F, Fut>(mut self, allow: F) -> Self
+    where
+        F: Fn(Domain) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + 'static,
+    {
+        self.config_mut().allow_domain = Some(Arc::new(move |host| Box::pin(allow(host))));
+        self
+    }
+
+    /// Stops scheduled checks of the certificate covering `domain`, which
+    /// keeps being served as is, e.g. while the domain is moved elsewhere.
+    /// `force_renew` still works on a paused certificate.
+    pub fn pause_renewal<D: AsRef<str>>(&self, domain: D) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(PauseRenewal(domain));
+        Ok(())
+    }
+
+    /// Undoes `pause_renewal`, checking the certificate right away.
+    pub fn resume_renewal<D: AsRef<str>>(&self, domain: D) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(ResumeRenewal(domain));
+        Ok(())
+    }
+
+    /// Operations that run on the calling thread, without the actor or a
+    /// server, for command line tools and cron jobs.
+    pub fn offline(&self) -> Offline {
+        Offline::new(self)
+    }
+
+    /// Revokes the certificate covering `domain` and then issues a
+    /// replacement, so the site isn't left serving a revoked certificate.
+    pub fn revoke<D: AsRef<str>>(&self, domain: D) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(Revoke(domain));
+        Ok(())
+    }
+
+    /// Stops managing the certificate covering `domain` for good: renewals
+    /// stop, it's revoked if `revoke` is set, it's no longer served, its
+    /// certificate is moved to `retired/` in the ssl directory and its key
+    /// is deleted as set by `key_deletion`.  With `Layout::Certbot` only
+    /// the `live` links are removed, a copy of the certificate going to
+    /// `retired/`, and the lineage's `archive` is left for certbot.  Actix can't
+    /// close a single listener, so one bound for this certificate alone
+    /// stays open, failing handshakes, until the server restarts.
+    pub fn retire<D: AsRef<str>>(&self, domain: D, revoke: bool) -> Result<(), Error> {
+        let domain = Domain::new(domain)?;
+        self.actor()?.do_send(Retire { domain, revoke });
+        Ok(())
+    }
+
+    /// Adds `new` to the certificate covering `existing` and re-issues it.
+    /// The change isn't written back to any configuration.
+    pub fn add_domain<E: AsRef<str>, N: AsRef<str>>(
+        &self,
+        existing: E,
+        new: N,
+    ) -> Result<(), Error> {
+        let existing = Domain::new(existing)?;
+        let new = Domain::new(new)?;
+        self.actor()?.do_send(AddDomain(existing, new));
+        Ok(())
+    }
+
+    fn actor(&self) -> Result<Addr<LetsEncrypt>, NotRunning> {
+        self.shared
+            .actor
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|addr| addr.connected())
+            .cloned()
+            .ok_or(NotRunning)
+    }
+
+    fn acceptor_builder(&self, cert_builder: &CertBuilder) -> Result<SslAcceptorBuilder, Error> {
+        let key = self.load_private_key(cert_builder)?;
+        Ok(cert_builder.ssl_builder(&key)?)
+    }
+
+    // Fails, leaving the previous certificate served, on an unreadable key
+    // or chain.
+    fn install(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
+        let context = self.acceptor_builder(cert_builder)?.build().into_context();
+        self.shared.resolver.insert(&cert_builder.domains, context);
+        #[cfg(feature = "rustls")]
+        self.install_certified_key(cert_builder);
+        let not_after = cert_builder.not_after();
+        self.shared
+            .metrics
+            .set_expiry(&cert_builder.domains[0], not_after, self.now());
+        self.shared.status.set_expiry(&cert_builder.domains, not_after);
+        self.shared.status.set_issuer(&cert_builder.domains, cert_builder.issuer());
+        self.shared.events.send(LifecycleEvent::CertInstalled {
+            domains: cert_builder.domains.clone(),
+            not_after,
+        });
+        Ok(())
+    }
+
+    // Reading and parsing the key and chain, and building the TLS context,
+    // is done on the blocking pool rather than the actor's thread; `then`
+    // runs on the actor once the certificate is being served.
+    fn install_in_background<F>(&self, cert_builder: CertBuilder, ctx: &mut Context<Self>, then: F)
+    where
+        F: FnOnce(&mut Self, CertBuilder, &mut Context<Self>) + 'static,
+    {
+        let domain = cert_builder.domains[0].clone();
+        let enabler = self.clone();
+        let installed = actix_web::web::block(move || {
+            enabler.install(&cert_builder)?;
+            Ok::<_, Error>(cert_builder)
+        });
+        ctx.spawn(installed.into_actor(self).map(move |result, act, ctx| match result {
+            Ok(cert_builder) => then(act, cert_builder, ctx),
+            Err(BlockingError::Error(e)) => error!(
+                "{}: could not install the certificate: {}",
+                domain,
+                DisplayChain(&e)
+            ),
+            Err(BlockingError::Canceled) => {
+                error!("{}: could not install the certificate: canceled", domain)
+            }
+        }));
+    }
+
+    /// The status of the certificate covering `domain`, including the last
+    /// error and the CA's problem document when renewal is failing.  None
+    /// until the certificate has been attached or checked.
+    pub fn cert_info<D: AsRef<str>>(&self, domain: D) -> Option<CertStatus> {
+        let domain = Domain::new(domain).ok()?;
+        self.shared.status.get(&domain)
+    }
+
+    /// Receives a `LifecycleEvent` for every step of issuing and serving
+    /// certificates from now on, e.g. to feed them into your own metrics.
+    /// A receiver that falls behind misses the oldest events.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<LifecycleEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Sets the template used to fill in unset settings of certs added
+    /// afterwards; certs that were already added are not affected.
+    pub fn cert_defaults(mut self, defaults: CertDefaults) -> Self {
+        self.set_cert_defaults(defaults);
+        self
+    }
+
+    pub fn set_cert_defaults(&mut self, defaults: CertDefaults) -> &mut Self {
+        self.config_mut().cert_defaults = defaults;
+        self
+    }
+
+    /// After a failed issuance the next attempt is made after `initial`,
+    /// doubling with every further failure up to `max`.  The existing
+    /// certificate, if any, keeps being served in the meantime.
+    pub fn retry_backoff(mut self, initial: &Duration, max: &Duration) -> Self {
+        self.set_retry_backoff(initial, max);
+        self
+    }
+
+    pub fn set_retry_backoff(&mut self, initial: &Duration, max: &Duration) -> &mut Self {
+        self.config_mut().retry_initial = *initial;
+        self.config_mut().retry_max = *max;
+        self
+    }
+
+    pub fn set_force_staging(&mut self, force_staging: bool) -> &mut Self {
+        self.config_mut().force_staging = force_staging;
+        self
+    }
+
+    pub fn set_dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.config_mut().dry_run = dry_run;
+        self
+    }
+
+    pub fn set_dev_mode(&mut self, dev_mode: bool) -> &mut Self {
+        self.config_mut().dev_mode = dev_mode;
+        self
+    }
+
+    pub fn set_dev_ca(&mut self, dev_ca: bool) -> &mut Self {
+        self.config_mut().dev_ca = dev_ca;
+        self
+    }
+
+    pub fn set_acme_directory<S: Into<String>>(&mut self, url: S) -> &mut Self {
+        self.config_mut().acme_directory = Some(url.into());
+        self
+    }
+
+    pub fn set_intermediate_url<S: Into<String>>(&mut self, url: S) -> &mut Self {
+        self.config_mut().intermediate_url = Some(url.into());
+        self
+    }
+
+    pub fn set_log_format(&mut self, log_format: LogFormat) -> &mut Self {
+        self.config_mut().log_format = log_format;
+        self
+    }
+
+    pub fn set_require_scts(&mut self, require: bool) -> &mut Self {
+        self.config_mut().require_scts = require;
+        self
+    }
+
+    pub fn set_require_caa(&mut self, require: bool) -> &mut Self {
+        self.config_mut().require_caa = require;
+        self
+    }
+
+    fn staging_forced(&self) -> bool {
+        self.config.force_staging
+            || self.config.dry_run
+            || env::var(STAGING_ENV_VAR)
+                .map(|v| ["1", "true", "yes"].contains(&v.to_ascii_lowercase().as_str()))
+                .unwrap_or(false)
+    }
+
+    pub fn set_ssl_directory<P>(&mut self, path: P) -> &mut Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.config_mut().ssl_directory = path.into();
+        self
+    }
+
+    pub fn register<    B: MessageBody,
+        T: ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse<B>,
+            Error = actix_http::Error,
+            InitError = (),
+        >,>(&self, app: App<T, B>) -> App<T, B> {
+        struct NonceDir(LetsEncrypt);
+        // The file is read on the blocking pool, so that a slow or network
+        // mounted nonce directory can't stall the worker.
+        async fn handle(req: HttpRequest, nonce_dir: actix_web::web::Data<NonceDir>) -> HttpResponse {
+            let token = req.match_info().query("token");
+            // Tokens are base64url, which also keeps ".." out of the path.
+            let valid = !token.is_empty()
+                && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if !valid {
+                return HttpResponse::NotFound().finish();
+            }
+            // Scanners probing the path don't even cause a disk lookup.
+            let enabler = &nonce_dir.0;
+            if enabler.config.challenges_only_while_ordering
+                && enabler.shared.validating.load(atomic::Ordering::SeqCst) == 0
+            {
+                return HttpResponse::NotFound().finish();
+            }
+            let mut path = enabler.config.nonce_directory.clone();
+            path.push(".well-known");
+            path.push("acme-challenge");
+            path.push(token);
+            let key_authorization = match actix_web::web::block(move || fs::read(path)).await {
+                Ok(key_authorization) => key_authorization,
+                Err(e) => {
+                    debug!("no challenge file for token {}: {}", token, e);
+                    return HttpResponse::NotFound().finish();
+                }
+            };
+            Metrics::challenge_served();
+            enabler.shared.events.send(LifecycleEvent::ChallengeServed {
+                host: enabler.origin(&req).host,
+                token: token.to_string(),
+            });
+            HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .body(key_authorization)
+        }
+
+        async fn metrics(
+            metrics: actix_web::web::Data<Metrics>,
+            enabler: actix_web::web::Data<LetsEncrypt>,
+        ) -> HttpResponse {
+            HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(metrics.render(enabler.now()))
+        }
+
+        async fn health(
+            status: actix_web::web::Data<StatusBoard>,
+            enabler: actix_web::web::Data<LetsEncrypt>,
+        ) -> HttpResponse {
+            let mut response = if status.healthy(enabler.now()) {
+                HttpResponse::Ok()
+            } else {
+                HttpResponse::ServiceUnavailable()
+            };
+            response.json(status.all())
+        }
+
+        let app = app.data(NonceDir(self.clone())).route("/.well-known/acme-challenge/{token}", actix_web::web::get().to(handle));
+        let app = match &self.config.metrics_path {
+            Some(path) => app
+                .data(self.shared.metrics.clone())
+                .data(self.clone())
+                .route(path, actix_web::web::get().to(metrics)),
+            None => app,
+        };
+        let app = match &self.config.health_path {
+            Some(path) => app
+                .data(self.shared.status.clone())
+                .data(self.clone())
+                .route(path, actix_web::web::get().to(health)),
+            None => app,
+        };
+        let app = match &self.config.status_page {
+            Some(page) => app
+                .data(self.shared.status.clone())
+                .data(self.clone())
+                .data(page.clone())
+                .route(page.route_path(), actix_web::web::get().to(status_page::page)),
+            None => app,
+        };
+        let app = match &self.config.probes {
+            Some(probes) => app
+                .data(self.clone())
+                .data(probes.clone())
+                .route(probes.liveness_route(), actix_web::web::get().to(probes::liveness))
+                .route(probes.readiness_route(), actix_web::web::get().to(probes::readiness)),
+            None => app,
+        };
+        match &self.config.admin {
+            Some(admin) => app.data(self.clone()).service(admin.scope()),
+            None => app,
+        }
+    }
+
+    pub fn attach_certificates_to<F, I, S, B>(&self, mut server: HttpServer<F, I, S, B>) -> io::Result<HttpServer<F, I, S, B>>
+    where
+        F: Fn() -> I + Send + Clone + 'static,
+        I: IntoServiceFactory<S>,
+        S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+        S::Error: Into<actix_http::Error>,
+        S::InitError: fmt::Debug,
+        S::Response: Into<Response<B>>,
+        B: MessageBody + 'static,
+    {
+        if self.config.proxy_protocol {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "with proxy_protocol, serve through proxy_protocol_server",
+            ));
+        }
+        if let Some(timeout) = self.config.shutdown_timeout {
+            server = server.shutdown_timeout(timeout.as_secs());
+        }
+        if self.config.tls_info {
+            server = server.on_connect(TlsInfo::capture);
+        }
+        for (cert_builder, builder, activated) in self.https_listeners()? {
+            let already_bound = server.addrs().len();
+            server = match activated {
+                Some(listener) => server.listen_openssl(listener, builder)?,
+                None => server.bind_openssl(cert_builder.addrs[0], builder)?,
+            };
+            self.record_bound(cert_builder, server.addrs().into_iter().skip(already_bound).collect());
+        }
+        Ok(server)
+    }
+
+    // The certs with listeners of their own, with each one's acceptor and
+    // any socket systemd passed in for it.  Certs served only through SNI
+    // are installed along the way.  Fails on a stored certificate that
+    // can't be served.
+    fn https_listeners(
+        &self,
+    ) -> io::Result<Vec<(&CertBuilder, SslAcceptorBuilder, Option<TcpListener>)>> {
+        let unservable = |cert_builder: &CertBuilder, e: Error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", cert_builder.domains[0], DisplayChain(&e)),
+            )
+        };
+        let mut listeners = Vec::new();
+        for cert_builder in &self.config.cert_builders {
+            // Served through SNI on the other certs' listeners.
+            if cert_builder.addrs.is_empty() {
+                if self.key_and_cert_present(cert_builder) {
+                    self.install(cert_builder)
+                        .map_err(|e| unservable(cert_builder, e))?;
+                } else {
+                    self.shared.status.set_expiry(&cert_builder.domains, None);
+                }
+                continue;
+            }
+            if !self.key_and_cert_present(cert_builder) {
+                info!(
+                    "{}: no certificate yet, it will be served on {} once issued",
+                    cert_builder.domains[0], cert_builder.addrs[0]
+                );
+            }
+            let builder = self
+                .listener_acceptor(cert_builder)
+                .map_err(|e| unservable(cert_builder, e))?;
+            let domain = &cert_builder.domains[0];
+            let activated = self.take_activated(|name, addr| {
+                name == Some(domain.as_str()) || addr == Some(cert_builder.addrs[0])
+            });
+            if activated.is_some() {
+                info!("{}: using the socket passed in by systemd", domain);
+            }
+            listeners.push((cert_builder, builder, activated));
+        }
+        Ok(listeners)
+    }
+
+    fn record_bound(&self, cert_builder: &CertBuilder, addrs: Vec<SocketAddr>) {
+        let mut bound = self.shared.bound.lock().unwrap();
+        for addr in addrs {
+            info!("{}: bound HTTPS to {}", cert_builder.domains[0], addr);
+            bound.push((cert_builder.domains[0].clone(), addr));
+        }
+    }
+
+    // The acceptor of a listener whose default certificate is
+    // `cert_builder`'s, switching to any other managed one by SNI.
+    fn listener_acceptor(&self, cert_builder: &CertBuilder) -> Result<SslAcceptorBuilder, Error> {
+        let mut builder = if self.key_and_cert_present(cert_builder) {
+            self.install(cert_builder)?;
+            self.acceptor_builder(cert_builder)?
+        } else {
+            self.shared.status.set_expiry(&cert_builder.domains, None);
+            CertBuilder::bare_ssl_builder()
+        };
+        self.shared
+            .resolver
+            .attach_to(&mut builder, &cert_builder.domains[0]);
+        Ok(builder)
+    }
+
+    /// The addresses `attach_certificates_to` actually bound, which differ
+    /// from the configured ones when a cert asks for port 0.
+    pub fn bound_addrs(&self) -> Vec<SocketAddr> {
+        let bound = self.shared.bound.lock().unwrap();
+        bound.iter().map(|(_, addr)| *addr).collect()
+    }
+
+    /// The bound HTTPS addresses of the cert that covers `domain`.
+    pub fn bound_addrs_for<D: AsRef<str>>(&self, domain: D) -> Vec<SocketAddr> {
+        let domain = match Domain::new(domain) {
+            Ok(domain) => domain,
+            Err(_) => return Vec::new(),
+        };
+        let first_domain = self
+            .config
+            .cert_builders
+            .iter()
+            .find(|c| c.domains.contains(&domain))
+            .map(|c| c.domains[0].clone());
+        let bound = self.shared.bound.lock().unwrap();
+        bound
+            .iter()
+            .filter(|(d, _)| Some(d) == first_domain.as_ref())
+            .map(|(_, addr)| *addr)
+            .collect()
+    }
+
+    fn directory_url(&self, cert_builder: &CertBuilder) -> &str {
+        if self.config.dev_mode {
+            DEV_MODE_DIRECTORY
+        } else if let Some(directory) = &self.config.acme_directory {
+            directory
+        } else if cert_builder.is_production() && !self.staging_forced() {
+            PRODUCTION_DIRECTORY
+        } else {
+            STAGING_DIRECTORY
+        }
+    }
+
+    fn account(&self, cert_builder: &CertBuilder) -> Result<Account, Error> {
+        let _span = info_span!("register_account", otel.kind = "client").entered();
+        let directory = Directory::from_url(self.directory_url(cert_builder))?;
+        let mut account = directory
+            .account_registration()
+            .pkey(self.account_key(cert_builder)?);
+        if let Some(email) = &cert_builder.email {
+            account = account.email(email);
+        }
+        let account = account.register()?;
+        info!("registered ACME account for {}", cert_builder.domains[0]);
+        Ok(account)
+    }
+
+    fn journal(&self) -> Journal {
+        Journal::new(&self.config.ssl_directory)
+    }
+
+    /// Every issuance attempt recorded in the journal under the ssl
+    /// directory, oldest first.
+    pub fn journal_entries(&self) -> Vec<JournalEntry> {
+        self.journal().entries()
+    }
+
+    fn build_cert(&self, cert_builder: &CertBuilder, actor: AuditActor) -> Result<(), Error> {
+        let domain = &cert_builder.domains[0];
+        let renewal = self.key_and_cert_present(cert_builder);
+        self.check_tenant_limit(cert_builder)?;
+        if let Some(lock) = &self.config.renewal_lock {
+            if !lock.acquire(domain, self.config.lock_ttl)? {
+                return Err(Error::Locked(domain.clone()));
+            }
+        }
+        let result = self.step(domain, "order", || self.order_cert(cert_builder));
+        let (result, new_key) = match result {
+            Ok(new_key) => (Ok(()), new_key),
+            Err(e) => (Err(e), false),
+        };
+        if let Some(lock) = &self.config.renewal_lock {
+            if let Err(e) = lock.release(domain) {
+                warn!("{}: could not release renewal lock: {}", domain, DisplayChain(&e));
+            }
+        }
+        // Concurrent orders mustn't interleave the journal's rewrites or
+        // fork the audit log's hash chain.
+        let _records = self.shared.records.lock().unwrap();
+        let now = self.now();
+        self.journal().record(&JournalEntry {
+            at: now,
+            domains: cert_builder.domains.clone(),
+            directory: self.directory_url(cert_builder).to_string(),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| DisplayChain(e).to_string()),
+            dry_run: self.config.dry_run,
+            tenant: cert_builder.tenant.clone(),
+        });
+        if result.is_ok() && !self.config.dry_run {
+            let audit = self.audit_log();
+            if new_key {
+                audit.record(now, AuditOperation::KeyGenerated, actor, &cert_builder.domains, None);
+            }
+            let operation = if renewal {
+                AuditOperation::Renewed
+            } else {
+                AuditOperation::Issued
+            };
+            audit.record(now, operation, actor, &cert_builder.domains, cert_builder.cert_path.as_deref());
+        }
+        result
+    }
+
+    fn renew_lock(&self, domain: &Domain) -> Result<(), Error> {
+        match &self.config.renewal_lock {
+            Some(lock) if !lock.renew(domain, self.config.lock_ttl)? => Err(Error::Locked(domain.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    // The current private key, if the rotation policy says to keep it.
+    fn reusable_key(&self, cert_builder: &CertBuilder) -> Option<PKey<Private>> {
+        let domain = &cert_builder.domains[0];
+        let rotation = cert_builder.resolved_key_rotation();
+        if rotation == KeyRotation::Always || !self.key_and_cert_present(cert_builder) {
+            return None;
+        }
+        if let KeyRotation::Every(limit) = rotation {
+            // Without a record of the key's generation its age is unknown.
+            let used = self.audit_log().certs_since_key_generated(domain)?;
+            if used >= limit as usize {
+                info!("{}: key used for {} certificates, rotating", domain, used);
+                return None;
+            }
+        }
+        match self.load_private_key(cert_builder) {
+            Ok(key) if cert_builder.resolved_key_type().matches(&key) => Some(key),
+            Ok(_) => {
+                info!("{}: key type changed, rotating", domain);
+                None
+            }
+            Err(e) => {
+                warn!("{}: can't reuse the current key: {}", domain, DisplayChain(&e));
+                None
+            }
+        }
+    }
+
+    // Runs inside an `order` span, with a child span per ACME step, so a
+    // tracing subscriber can show which step a slow issuance is stuck in.
+    // Steps that call the CA are marked `otel.kind = "client"` for
+    // OpenTelemetry exporters.  True when a new private key was stored.
+    fn order_cert(&self, cert_builder: &CertBuilder) -> Result<bool, Error> {
+        let primary = &cert_builder.domains[0];
+        let _order = info_span!(
+            "order",
+            domain = %primary,
+            domains = cert_builder.domains.len(),
+            directory = self.directory_url(cert_builder),
+            dry_run = self.config.dry_run,
+        )
+        .entered();
+        self.shared.events.send(LifecycleEvent::OrderStarted {
+            domains: cert_builder.domains.clone(),
+        });
+        if self.config.dev_mode {
+            let (key, new_key) = self.order_key(cert_builder)?;
+            let chain = self.step(primary, "self_sign", || self.self_signed(cert_builder, &key))?;
+            return self.store_cert(cert_builder, key, new_key, chain);
+        }
+        // Another CA than Let's Encrypt isn't named in the CAA records
+        // `check_caa` looks for.
+        if self.config.acme_directory.is_none() {
+            self.check_caa_before_order(&cert_builder.domains)?;
+        }
+        let account = self.step(primary, "account", || self.account(cert_builder))?;
+
+        for domain in &cert_builder.domains {
+            let _authorization = info_span!("authorization", domain = %domain, otel.kind = "client").entered();
+            let authorization = self.step(domain, "authorization", || {
+                Ok(account.authorization(domain.as_str())?)
+            })?;
+            let http_challenge = authorization
+                .get_http_challenge()
+                .ok_or_else(|| Error::NoHttpChallenge(domain.clone()))?;
+            let _validating = Validating::start(&self.shared.validating);
+            http_challenge.save_key_authorization(self.config.nonce_directory.clone())?;
+            debug!("{}: saved http-01 challenge", domain);
+            self.step(domain, "challenge", || {
+                info_span!("validate_challenge", otel.kind = "client").in_scope(|| {
+                    http_challenge.validate().map_err(|e| {
+                        error!("{}: challenge validation failed: {}", domain, e);
+                        Error::from(e)
+                    })
+                })
+            })?;
+            info!("{}: challenge validated", domain);
+            self.renew_lock(primary)?;
+        }
+        let domains: Vec<&str> = cert_builder.domains.iter().map(Domain::as_str).collect();
+        let (key, new_key) = self.order_key(cert_builder)?;
+        let cert = self.step(primary, "sign", || {
+            let key_type = cert_builder.resolved_key_type();
+            info_span!("sign_csr", key_type = ?key_type, otel.kind = "client").in_scope(|| {
+                Ok(account
+                    .certificate_signer(&domains[..])
+                    .pkey(key.clone())
+                    .sign_certificate()?)
+            })
+        })?;
+        if self.config.require_scts {
+            sct::check(primary, cert.cert())?;
+        }
+        let chain = self.bundle_chain(cert_builder, &cert)?;
+        self.store_cert(cert_builder, key, new_key, chain)
+    }
+
+    // The key to order with, and whether it's a new one.
+    fn order_key(&self, cert_builder: &CertBuilder) -> Result<(PKey<Private>, bool), Error> {
+        match self.reusable_key(cert_builder) {
+            Some(key) => {
+                info!("{}: keeping the current private key", cert_builder.domains[0]);
+                Ok((key, false))
+            }
+            None => Ok((cert_builder.resolved_key_type().generate()?, true)),
+        }
+    }
+
+    // Stores a newly signed `chain`, leaf first, and its key.  True when
+    // the key was new.
+    fn store_cert(
+        &self,
+        cert_builder: &CertBuilder,
+        key: PKey<Private>,
+        new_key: bool,
+        chain: Vec<X509>,
+    ) -> Result<bool, Error> {
+        let primary = &cert_builder.domains[0];
+        if self.config.dry_run {
+            info!("{}: dry run, discarding signed certificate", primary);
+            return Ok(false);
+        }
+        let cert_path = cert_builder.cert_path.as_ref().unwrap();
+        let key_path = cert_builder.key_path.as_ref().unwrap();
+        self.step(primary, "store", || {
+            let _store = info_span!("store", cert_path = %cert_path.display()).entered();
+            if let Some(directory) = cert_path.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            if self.config.layout == Layout::Certbot {
+                let pem = Zeroizing::new(key.private_key_to_pem_pkcs8()?);
+                let pem = Some(&pem[..]).filter(|_| !self.config.keys_in_memory);
+                self.store_certbot_lineage(cert_builder, &chain, pem)?;
+                if self.config.keys_in_memory {
+                    self.shared.keys.insert(primary, key.clone());
+                }
+                return Ok(());
+            }
+            let mut pem = Vec::new();
+            for cert in &chain {
+                pem.extend(cert.to_pem()?);
+            }
+            fs::write(cert_path, pem)?;
+            self.write_layout_files(cert_builder)?;
+            if self.config.keys_in_memory {
+                self.shared.keys.insert(primary, key.clone());
+            } else if new_key {
+                let pem = Zeroizing::new(key.private_key_to_pem_pkcs8()?);
+                self.write_private_key(key_path, &pem, 0o600)?;
+            }
+            Ok(())
+        })?;
+        if self.config.keys_in_memory {
+            info!("{}: saved certificate to {}, key held in memory", primary, cert_path.display());
+        } else {
+            info!(
+                "{}: saved certificate to {} and key to {}",
+                primary,
+                cert_path.display(),
+                key_path.display()
+            );
+        }
+        if let Err(e) = self.write_exports(cert_builder) {
+            warn!("{}: could not export the certificate: {}", primary, DisplayChain(&e));
+        }
+        self.shared.events.send(LifecycleEvent::CertIssued(cert_builder.info()));
+        Ok(new_key)
+    }
+
+    // The journaled failure count and the time left before the next retry
+    // is due, if the cert's last attempts failed recently enough.
+    fn journal_backoff(&self, cert_builder: &CertBuilder) -> Option<(u32, Duration)> {
+        if self.config.dry_run || !self.needs_building(cert_builder) {
+            return None;
+        }
+        let (failures, at) = self.journal().failures(&cert_builder.domains[0])?;
+        let elapsed = self.now().signed_duration_since(at).to_std().unwrap_or_default();
+        self.retry_delay(failures)
+            .checked_sub(elapsed)
+            .filter(|remaining| *remaining > Duration::default())
+            .map(|remaining| (failures, remaining))
+    }
+
+    fn issue_on_demand(&mut self, host: Domain, ctx: &mut Context<Self>) {
+        if self.config.cert_builders.iter().any(|c| c.domains.contains(&host)) {
+            return;
+        }
+        let full = self
+            .config
+            .on_demand
+            .as_ref()
+            .map_or(false, |o| o.is_full(self.on_demand_certs.len()));
+        if full {
+            self.evict_on_demand(ctx);
+        }
+        info!("{}: issuing on demand", host);
+        self.on_demand_certs.insert(host.clone());
+        let no_addrs: &[SocketAddr] = &[];
+        let cert_builder = self.prepare_cert(CertBuilder::new(no_addrs, &[host]));
+        self.handle(AddCert(cert_builder), ctx);
+    }
+
+    // Removes the least recently used on-demand certificate and its files.
+    fn evict_on_demand(&mut self, ctx: &mut Context<Self>) {
+        let resolver = &self.shared.resolver;
+        let victim = self
+            .on_demand_certs
+            .iter()
+            .min_by_key(|host| resolver.last_used(host))
+            .cloned();
+        let victim = match victim {
+            Some(victim) => victim,
+            None => return,
+        };
+        let cert_builder = self.managed(&victim);
+        self.on_demand_certs.remove(&victim);
+        self.handle(RemoveCert(victim.clone()), ctx);
+        if let Some(cert_builder) = cert_builder {
+            let _ = self
+                .config
+                .key_deletion
+                .delete(cert_builder.key_path.as_ref().unwrap());
+            let _ = fs::remove_file(cert_builder.cert_path.as_ref().unwrap());
+        }
+        info!("{}: evicted least recently used on-demand certificate", victim);
+    }
+
+    fn revoke_cert(&self, cert_builder: &CertBuilder) -> Result<(), Error> {
+        let cert_path = cert_builder.cert_path.as_ref().unwrap();
+        self.account(cert_builder)?
+            .revoke_certificate_from_file(cert_path)?;
+        info!("{}: revoked {}", cert_builder.domains[0], cert_path.display());
+        let _records = self.shared.records.lock().unwrap();
+        self.audit_log().record(
+            self.now(),
+            AuditOperation::Revoked,
+            AuditActor::Api,
+            &cert_builder.domains,
+            Some(cert_path),
+        );
+        Ok(())
+    }
+
+    // Revoking takes requests to the CA, so is done on the blocking pool
+    // like orders; `then` runs on the actor with the outcome.
+    fn revoke_in_background<F>(&self, cert_builder: CertBuilder, ctx: &mut Context<Self>, then: F)
+    where
+        F: FnOnce(&mut Self, CertBuilder, Result<(), Error>, &mut Context<Self>) + 'static,
+    {
+        let enabler = self.clone();
+        let revoked = actix_web::web::block(move || {
+            let result = enabler.revoke_cert(&cert_builder);
+            Ok::<_, ()>((cert_builder, result))
+        });
+        ctx.spawn(revoked.into_actor(self).map(|result, act, ctx| match result {
+            Ok((cert_builder, result)) => then(act, cert_builder, result, ctx),
+            Err(_) => error!("could not revoke: the revocation panicked"),
+        }));
+    }
+
+    // Stops serving and renewing a certificate being retired, and archives
+    // its files.
+    fn finish_retiring(&mut self, cert_builder: CertBuilder, ctx: &mut Context<Self>) {
+        let domain = cert_builder.domains[0].clone();
+        self.handle(RemoveCert(domain.clone()), ctx);
+        self.shared
+            .bound
+            .lock()
+            .unwrap()
+            .retain(|(d, _)| *d != domain);
+        match self.archive(&cert_builder) {
+            Ok(archive) => info!("{}: retired, files archived in {}", domain, archive.display()),
+            Err(e) => error!("{}: could not archive files: {}", domain, DisplayChain(&e)),
+        }
+    }
+
+    // Moves the certificate into a timestamped directory under `retired` in
+    // the ssl directory, and deletes the key.  A symlinked certificate is
+    // copied and only its link removed.
+    fn archive(&self, cert_builder: &CertBuilder) -> io::Result<PathBuf> {
+        let archive = self.config.ssl_directory.join("retired").join(format!(
+            "{}-{}",
+            cert_builder.domains[0],
+            self.now().format("%Y%m%dT%H%M%SZ")
+        ));
+        fs::create_dir_all(&archive)?;
+        let cert_path = cert_builder.cert_path.as_ref().unwrap();
+        let retired = archive.join(cert_path.file_name().unwrap());
+        if is_symlink(cert_path) {
+            fs::copy(cert_path, retired)?;
+            fs::remove_file(cert_path)?;
+        } else if cert_path.exists() {
+            fs::rename(cert_path, retired)?;
+        }
+        self.config.key_deletion
+            .delete(cert_builder.key_path.as_ref().unwrap())?;
+        Ok(archive)
+    }
+
+    fn startup_timed_out(&mut self) {
+        let missing = self
+            .config
+            .cert_builders
+            .iter()
+            .filter(|c| self.shared.resolver.get(&c.domains[0]).is_none());
+        for cert_builder in missing {
+            let domain = &cert_builder.domains[0];
+            match self.config.startup_fallback {
+                StartupFallback::HttpOnly => {
+                    warn!("{}: not issued in time, HTTPS is unavailable", domain);
+                }
+                StartupFallback::SelfSigned => {
+                    let key_type = cert_builder.resolved_key_type();
+                    match fallback::self_signed(&cert_builder.domains, key_type) {
+                        Ok(context) => {
+                            warn!("{}: not issued in time, serving a self-signed certificate", domain);
+                            self.shared.resolver.insert(&cert_builder.domains, context);
+                        }
+                        Err(e) => error!(
+                            "{}: could not create a self-signed certificate: {}",
+                            domain,
+                            DisplayChain(&e)
+                        ),
+                    }
+                }
+                StartupFallback::Exit(code) => {
+                    error!("{}: not issued in time, exiting with status {}", domain, code);
+                    process::exit(code);
+                }
+            }
+        }
+    }
+
+    fn retry_delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.config.retry_initial
+            .checked_mul(factor)
+            .map_or(self.config.retry_max, |delay| delay.min(self.config.retry_max))
+    }
+
+    // The current cert whose first domain is `domain`.
+    fn managed(&self, domain: &Domain) -> Option<CertBuilder> {
+        self.config.cert_builders
+            .iter()
+            .find(|c| c.domains[0] == *domain)
+            .cloned()
+    }
+
+    fn schedule_renewal(&mut self, cert_builder: CertBuilder, ctx: &mut Context<Self>) {
+        let delay = if cert_builder.check_schedule.is_some() {
+            Duration::default()
+        } else {
+            cert_builder.resolved_check_every() + random_duration(cert_builder.resolved_jitter())
+        };
+        self.schedule_check(cert_builder, delay, 0, ctx);
+    }
+
+    fn schedule_retry(&mut self, cert_builder: CertBuilder, failures: u32, ctx: &mut Context<Self>) {
+        let delay = self.retry_delay(failures);
+        self.schedule_check(cert_builder, delay, failures, ctx);
+    }
+
+    // Checks the cert after `delay`, at the first time `check_schedule`
+    // allows.
+    fn schedule_check(
+        &mut self,
+        cert_builder: CertBuilder,
+        delay: Duration,
+        failures: u32,
+        ctx: &mut Context<Self>,
+    ) {
+        let delay = match &cert_builder.check_schedule {
+            Some(schedule) => schedule.delay_from(self.now(), delay),
+            None => delay,
+        };
+        self.schedule_check_in(cert_builder, delay, failures, ctx);
+    }
+
+    // Checks the cert after exactly `delay`.
+    fn schedule_check_in(
+        &mut self,
+        cert_builder: CertBuilder,
+        delay: Duration,
+        failures: u32,
+        ctx: &mut Context<Self>,
+    ) {
+        let domain = cert_builder.domains[0].clone();
+        info!(
+            "{}: next renewal check in {}",
+            domain,
+            humantime::format_duration(delay)
+        );
+        let next_check = chrono::Duration::from_std(delay).ok().map(|delay| self.now() + delay);
+        self.shared.status.set_next_check(&cert_builder.domains, next_check);
+        // Only the domain is kept until the check is due; the cert is looked
+        // up then, so changes made meanwhile (an added domain, a new
+        // renewal window) apply, and a removed cert isn't checked at all.
+        let first_domain = domain.clone();
+        let handle = ctx.run_later(delay, move |act, ctx| {
+            if let Some(cert_builder) = act.managed(&first_domain) {
+                act.check(cert_builder, failures, ctx);
+            }
+        });
+        // A cert has one check pending at most, whichever path scheduled it.
+        if let Some(previous) = self.renewals.insert(domain, handle) {
+            ctx.cancel_future(previous);
+        }
+    }
+
+    fn notify(&self, event: Event, ctx: &mut Context<Self>) {
+        for webhook in &self.config.webhooks {
+            ctx.spawn(webhook.send(&event).into_actor(self));
+        }
+        for notifier in &self.config.chat_notifiers {
+            ctx.spawn(notifier.send(&event).into_actor(self));
+        }
+        #[cfg(feature = "email")]
+        for notifier in &self.config.email_notifiers {
+            notifier.notify(&event);
+        }
+        #[cfg(feature = "sentry")]
+        if self.config.report_to_sentry {
+            sentry::report(&event);
+        }
+    }
+
+    // Checks every cert once the server starts, or once first elected.
+    fn start_checks(&mut self, ctx: &mut Context<Self>) {
+        // Every cert's PEM is read and parsed on the blocking pool at once,
+        // filling the expiry caches the checks below consult, so startup
+        // doesn't take a file read per cert in turn.
+        let parsed: Vec<_> = self
+            .config
+            .cert_builders
+            .iter()
+            .cloned()
+            .map(|cert_builder| {
+                actix_web::web::block(move || Ok::<_, ()>(cert_builder.not_after()))
+            })
+            .collect();
+        let parsed = async move {
+            for parse in parsed {
+                let _ = parse.await;
+            }
+        };
+        ctx.wait(parsed.into_actor(self).map(|(), act, ctx| act.check_all(ctx)));
+    }
+
+    // The server is already up, with a listener for every cert, so missing
+    // certs are issued like any renewal and served once installed.
+    fn check_all(&mut self, ctx: &mut Context<Self>) {
+        for cert_builder in self.config.cert_builders.clone() {
+            // Don't hammer the CA when a failing order crashes us in a loop.
+            if let Some((failures, delay)) = self.journal_backoff(&cert_builder) {
+                warn!(
+                    "{}: last attempt failed, not retrying for {}",
+                    cert_builder.domains[0],
+                    humantime::format_duration(delay)
+                );
+                self.schedule_check(cert_builder, delay, failures, ctx);
+                continue;
+            }
+            if self.config.dry_run {
+                info!("{}: dry run order", cert_builder.domains[0]);
+                self.forced.insert(cert_builder.domains[0].clone());
+            }
+            self.check(cert_builder, 0, ctx);
+        }
+    }
+
+    fn check(&mut self, cert_builder: CertBuilder, failures: u32, ctx: &mut Context<Self>) {
+        // The leader checks everything when elected.
+        if self.is_follower() {
+            debug!("{}: following, not checking", cert_builder.domains[0]);
+            return;
+        }
+        let warning = chrono::Duration::from_std(self.config.expiry_warning)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        // None until the certificate has been issued.
+        let expiring = cert_builder
+            .not_after()
+            .map(|not_after| (not_after, not_after.signed_duration_since(self.now()) < warning));
+        if let Some((not_after, true)) = expiring {
+            warn!("{}: certificate expires {}", cert_builder.domains[0], not_after);
+            self.shared.events.send(LifecycleEvent::Expiring(cert_builder.info()));
+            self.notify(Event::Expiring(cert_builder.info()), ctx);
+        }
+
+        let domain = &cert_builder.domains[0];
+        let forced = self.forced.contains(domain);
+        if self.paused.contains(domain) && !forced {
+            info!("{}: renewal paused, not checking", domain);
+            return;
+        }
+        if !forced && !self.needs_building(&cert_builder) {
+            debug!("{}: certificate is current", domain);
+            self.schedule_renewal(cert_builder, ctx);
+            return;
+        }
+        // Only renewals wait for the window: a certificate yet to be
+        // issued, or about to expire, is ordered right away.
+        if let (false, Some((_, false)), Some(window)) =
+            (forced, expiring, &cert_builder.renew_window)
+        {
+            if let Some(delay) = window.delay_until_open(self.now()) {
+                info!("{}: due, waiting for the renewal window {}", domain, window);
+                // When the window opens, whatever `check_schedule` says.
+                self.schedule_check_in(cert_builder, delay, failures, ctx);
+                return;
+            }
+        }
+        info!("{}: certificate needs building", domain);
+
+        // Nothing else is handled by the actor until the hooks are done.
+        let before_order = self.config.hooks.before_order(cert_builder.info());
+        ctx.wait(
+            before_order
+                .into_actor(self)
+                .map(move |(), act, ctx| act.issue(cert_builder, failures, ctx)),
+        );
+    }
+
+    // Orders the certificate on the blocking pool, or queues it while
+    // `max_concurrent_orders` orders are running.
+    fn issue(&mut self, cert_builder: CertBuilder, failures: u32, ctx: &mut Context<Self>) {
+        let domain = cert_builder.domains[0].clone();
+        if self.overdue.contains(&domain) {
+            // Its result decides what's next, but the order may never
+            // return, so the cert is checked again meanwhile.
+            debug!("{}: overdue order still running, retrying later", domain);
+            self.schedule_retry(cert_builder, failures, ctx);
+            return;
+        }
+        if self.ordering.contains(&domain)
+            || self.queued.iter().any(|(queued, _)| queued.domains[0] == domain)
+        {
+            debug!("{}: already being ordered", domain);
+            return;
+        }
+        if self.ordering.len() >= self.config.max_concurrent_orders {
+            debug!("{}: {} orders running, queued", domain, self.ordering.len());
+            self.queued.push_back((cert_builder, failures));
+            return;
+        }
+        self.ordering.insert(domain.clone());
+        let renewal = self.key_and_cert_present(&cert_builder);
+        let started = Instant::now();
+        let actor = if self.forced.contains(&domain) {
+            AuditActor::Api
+        } else {
+            AuditActor::Auto
+        };
+        let deadline = self.config.order_deadline.map(|deadline| {
+            let domain = domain.clone();
+            ctx.run_later(deadline, move |act, ctx| {
+                warn!("{}: order still running after {:?}, moving on", domain, deadline);
+                act.ordering.remove(&domain);
+                act.overdue.insert(domain.clone());
+                if let Some(cert_builder) = act.managed(&domain) {
+                    let result = Err(Error::OrderDeadline(domain));
+                    act.issued(cert_builder, failures, renewal, started, result, ctx);
+                }
+                if let Some((next, failures)) = act.queued.pop_front() {
+                    act.issue(next, failures, ctx);
+                }
+            })
+        });
+        let enabler = self.clone();
+        let ordered = cert_builder.clone();
+        let order = actix_web::web::block(move || enabler.build_cert(&ordered, actor));
+        ctx.spawn(order.into_actor(self).map(move |result, act, ctx| {
+            if act.overdue.remove(&domain) {
+                // The deadline already counted this attempt as failed and
+                // gave up its turn.  A certificate that was stored after
+                // all is served, and either way the cert's next check is
+                // scheduled from here.
+                let cert_builder = match act.managed(&domain) {
+                    Some(cert_builder) => cert_builder,
+                    None => return,
+                };
+                match result {
+                    Ok(()) if act.config.dry_run => act.schedule_renewal(cert_builder, ctx),
+                    Ok(()) => {
+                        info!("{}: overdue order finished after all, serving it", domain);
+                        act.forced.remove(&domain);
+                        act.install_in_background(cert_builder, ctx, move |act, cert_builder, ctx| {
+                            act.installed(cert_builder, renewal, ctx)
+                        });
+                    }
+                    Err(_) => act.schedule_retry(cert_builder, failures + 1, ctx),
+                }
+                return;
+            }
+            if let Some(deadline) = deadline {
+                ctx.cancel_future(deadline);
+            }
+            act.ordering.remove(&domain);
+            let result = result.map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => {
+                    Error::Io(io::Error::new(io::ErrorKind::Other, "order canceled"))
+                }
+            });
+            if act.managed(&domain).is_some() {
+                act.issued(cert_builder, failures, renewal, started, result, ctx);
+            } else {
+                info!("{}: removed while being ordered, not installing", domain);
+            }
+            if let Some((next, failures)) = act.queued.pop_front() {
+                act.issue(next, failures, ctx);
+            }
+        }));
+    }
+
+    fn installed(&mut self, cert_builder: CertBuilder, renewal: bool, ctx: &mut Context<Self>) {
+        info!("{}: renewed certificate is now being served", cert_builder.domains[0]);
+        let info = cert_builder.info();
+        if let Some(command) = &self.config.deploy_hook {
+            run_deploy_hook(command, &info);
+        }
+        if renewal {
+            self.notify(Event::Renewed(info.clone()), ctx);
+        } else {
+            self.notify(Event::Issued(info.clone()), ctx);
+        }
+        let restart = self.config.restart.clone().restart(info.clone(), self.server());
+        let renewed = self.config.hooks.renewed(info);
+        ctx.spawn(
+            async move {
+                renewed.await;
+                restart.await;
+            }
+            .into_actor(self),
+        );
+        self.schedule_renewal(cert_builder, ctx);
+    }
+
+    // Renewed certificates are swapped into the resolver, which every bound
+    // listener consults during the handshake, so nothing needs restarting.
+    fn issued(
+        &mut self,
+        cert_builder: CertBuilder,
+        failures: u32,
+        renewal: bool,
+        started: Instant,
+        result: Result<(), Error>,
+        ctx: &mut Context<Self>,
+    ) {
+        if let Err(Error::Locked(domain)) = &result {
+            info!("{}: being renewed by another instance, checking again later", domain);
+            let delay = self.config.retry_initial;
+            self.schedule_check(cert_builder, delay, failures, ctx);
+            return;
+        }
+        self.shared.metrics.record_attempt(
+            &cert_builder.domains[0],
+            started.elapsed(),
+            result.is_ok(),
+        );
+        self.shared
+            .status
+            .record_renewal(&cert_builder.domains, result.as_ref().err(), self.now());
+        match result {
+            Ok(()) if self.config.dry_run => {
+                self.forced.remove(&cert_builder.domains[0]);
+                info!("{}: dry run order succeeded", cert_builder.domains[0]);
+                self.schedule_renewal(cert_builder, ctx);
+            }
+            Ok(()) => {
+                self.forced.remove(&cert_builder.domains[0]);
+                self.install_in_background(cert_builder, ctx, move |act, cert_builder, ctx| {
+                    act.installed(cert_builder, renewal, ctx)
+                });
+            }
+            Err(e) => {
+                let failures = failures + 1;
+                let error = DisplayChain(&e).to_string();
+                let event = Event::Failed {
+                    info: cert_builder.info(),
+                    attempt: failures,
+                    error: error.clone(),
+                };
+                self.shared.events.send(LifecycleEvent::RenewalFailed {
+                    info: cert_builder.info(),
+                    attempt: failures,
+                    error: error.clone(),
+                });
+                if self.config.escalate_after.map_or(false, |n| failures >= n) {
+                    error!(
+                        "{}: CRITICAL: renewal attempt {} failed: {}",
+                        cert_builder.domains[0], failures, error
+                    );
+                    self.shared.status.set_critical(&cert_builder.domains);
+                    ctx.spawn(self.config.hooks.critical(event.clone()).into_actor(self));
+                } else {
+                    warn!(
+                        "{}: renewal attempt {} failed: {}",
+                        cert_builder.domains[0], failures, error
+                    );
+                }
+                self.notify(event, ctx);
+                self.schedule_retry(cert_builder, failures, ctx);
+            }
+        }
+    }
+}
 
 impl Actor for LetsEncrypt {
     type Context = Context<LetsEncrypt>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        let mut needs_restart = false;
-        for cert_builder in &self.cert_builders {
-            needs_restart = needs_restart || self.cert_built(cert_builder);
+        *self.shared.actor.lock().unwrap() = Some(ctx.address());
+        // Nothing is issued or installed until wrapped keys can be read.
+        if let Some(unlock) = self.unlock_keys_on_start() {
+            ctx.wait(unlock.into_actor(self));
+        }
+        if let Some(timeout) = self.config.startup_timeout {
+            ctx.run_later(timeout, |act, _| act.startup_timed_out());
+        }
+        if let Some(on_demand) = self.config.on_demand.clone() {
+            let actor = ctx.address();
+            let asks = self.config.allow_domain.is_some() || on_demand.asks();
+            self.shared.resolver.set_miss_handler(Arc::new(move |host| {
+                if on_demand.allows(host) {
+                    actor.do_send(IssueOnDemand(host.clone()));
+                    on_demand::placeholder(host)
+                } else {
+                    // Until it's been approved the listener's default
+                    // certificate is served.
+                    if asks && !host.is_wildcard() {
+                        actor.do_send(IssueOnDemand(host.clone()));
+                    }
+                    None
+                }
+            }));
+        }
+        if self.config.probes.is_some() {
+            self.start_probes();
+        }
+        self.start_sighup_reload(ctx);
+        self.start_ct_monitor(ctx);
+        if self.election.is_some() {
+            self.start_election(ctx);
+        } else {
+            self.start_checks(ctx);
+        }
+    }
+}
+
+impl Handler<AddCert> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, AddCert(cert_builder): AddCert, ctx: &mut Self::Context) {
+        if self.config.cert_builders.iter().any(|c| c.domains[0] == cert_builder.domains[0]) {
+            return;
         }
-        if needs_restart {
-            actix::System::current().stop();
+        self.config_mut().cert_builders.push(cert_builder.clone());
+        if self.needs_building(&cert_builder) {
+            self.check(cert_builder, 0, ctx);
         } else {
-            for cert_builder in &self.cert_builders {
-                let cert_builder = cert_builder.clone();
-                ctx.run_interval(cert_builder.check_every, move |act, _ctx| {
-                    if act.cert_built(&cert_builder) {
-                        actix::System::current().stop();
+            self.install_in_background(cert_builder.clone(), ctx, |_, _, _| {});
+            self.schedule_renewal(cert_builder, ctx);
+        }
+    }
+}
+
+impl Handler<IssueOnDemand> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, IssueOnDemand(host): IssueOnDemand, ctx: &mut Self::Context) {
+        if self.config.cert_builders.iter().any(|c| c.domains.contains(&host)) {
+            return;
+        }
+        let on_demand = match &self.config.on_demand {
+            Some(on_demand) => on_demand,
+            None => return,
+        };
+        if !on_demand.allows(&host) {
+            let asks = self.config.allow_domain.is_some() || on_demand.asks();
+            if !asks || !self.asking.start(&host) {
+                return;
+            }
+            let ask = on_demand::ask(on_demand, self.config.allow_domain.clone(), host.clone());
+            ctx.spawn(ask.into_actor(self).map(move |allowed, act, ctx| {
+                act.asking.finish(&host, allowed);
+                if allowed {
+                    if let Some(placeholder) = on_demand::placeholder(&host) {
+                        act.shared.resolver.insert(&[host.clone()], placeholder);
                     }
-                });
+                    act.issue_on_demand(host, ctx);
+                } else {
+                    info!("{}: not allowed on demand", host);
+                }
+            }));
+            return;
+        }
+        self.issue_on_demand(host, ctx);
+    }
+}
+
+impl Handler<ForceRenew> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, ForceRenew(domain): ForceRenew, ctx: &mut Self::Context) {
+        let cert_builders: Vec<CertBuilder> = self
+            .config
+            .cert_builders
+            .iter()
+            .filter(|c| domain.as_ref().map_or(true, |d| c.domains.contains(d)))
+            .cloned()
+            .collect();
+        for cert_builder in cert_builders {
+            info!("{}: forcing renewal", cert_builder.domains[0]);
+            if let Some(handle) = self.renewals.remove(&cert_builder.domains[0]) {
+                ctx.cancel_future(handle);
             }
+            self.forced.insert(cert_builder.domains[0].clone());
+            self.check(cert_builder, 0, ctx);
+        }
+    }
+}
+
+impl Handler<Revoke> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, Revoke(domain): Revoke, ctx: &mut Self::Context) {
+        let cert_builder = match self.config.cert_builders.iter().find(|c| c.domains.contains(&domain)) {
+            Some(cert_builder) => cert_builder.clone(),
+            None => return,
+        };
+        self.revoke_in_background(cert_builder, ctx, move |act, cert_builder, result, ctx| {
+            if let Err(e) = result {
+                error!("{}: could not revoke: {}", domain, DisplayChain(&e));
+                return;
+            }
+            if let Some(handle) = act.renewals.remove(&cert_builder.domains[0]) {
+                ctx.cancel_future(handle);
+            }
+            act.forced.insert(cert_builder.domains[0].clone());
+            act.check(cert_builder, 0, ctx);
+        });
+    }
+}
+
+impl Handler<Retire> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, Retire { domain, revoke }: Retire, ctx: &mut Self::Context) {
+        let cert_builder = match self.config.cert_builders.iter().find(|c| c.domains.contains(&domain)) {
+            Some(cert_builder) => cert_builder.clone(),
+            None => return,
+        };
+        if revoke && self.key_and_cert_present(&cert_builder) {
+            self.revoke_in_background(cert_builder, ctx, move |act, cert_builder, result, ctx| {
+                if let Err(e) = result {
+                    // Carry on: the certificate is going away either way.
+                    error!("{}: could not revoke: {}", domain, DisplayChain(&e));
+                }
+                act.finish_retiring(cert_builder, ctx);
+            });
+        } else {
+            self.finish_retiring(cert_builder, ctx);
+        }
+    }
+}
+
+impl Handler<AddDomain> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, AddDomain(existing, new): AddDomain, ctx: &mut Self::Context) {
+        let cert_builder = match self
+            .config_mut()
+            .cert_builders
+            .iter_mut()
+            .find(|c| c.domains.contains(&existing))
+        {
+            Some(cert_builder) => cert_builder,
+            None => return,
+        };
+        if cert_builder.domains.contains(&new) {
+            return;
+        }
+        cert_builder.domains.push(new);
+        let cert_builder = cert_builder.clone();
+        info!("{}: added {}, re-issuing", existing, cert_builder.domains.last().unwrap());
+
+        if let Some(handle) = self.renewals.remove(&cert_builder.domains[0]) {
+            ctx.cancel_future(handle);
+        }
+        self.forced.insert(cert_builder.domains[0].clone());
+        self.check(cert_builder, 0, ctx);
+    }
+}
+
+impl Handler<PauseRenewal> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, PauseRenewal(domain): PauseRenewal, ctx: &mut Self::Context) {
+        let first_domain = match self.config.cert_builders.iter().find(|c| c.domains.contains(&domain)) {
+            Some(cert_builder) => cert_builder.domains[0].clone(),
+            None => return,
+        };
+        if let Some(handle) = self.renewals.remove(&first_domain) {
+            ctx.cancel_future(handle);
+        }
+        info!("{}: renewal paused", first_domain);
+        self.paused.insert(first_domain);
+    }
+}
+
+impl Handler<ResumeRenewal> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, ResumeRenewal(domain): ResumeRenewal, ctx: &mut Self::Context) {
+        let cert_builder = match self.config.cert_builders.iter().find(|c| c.domains.contains(&domain)) {
+            Some(cert_builder) => cert_builder.clone(),
+            None => return,
+        };
+        if self.paused.remove(&cert_builder.domains[0]) {
+            info!("{}: renewal resumed", cert_builder.domains[0]);
+            self.check(cert_builder, 0, ctx);
+        }
+    }
+}
+
+impl Handler<RemoveCert> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, RemoveCert(domain): RemoveCert, ctx: &mut Self::Context) {
+        let index = match self
+            .config
+            .cert_builders
+            .iter()
+            .position(|c| c.domains.contains(&domain))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let cert_builder = self.config_mut().cert_builders.remove(index);
+        if let Some(handle) = self.renewals.remove(&cert_builder.domains[0]) {
+            ctx.cancel_future(handle);
         }
+        self.forced.remove(&cert_builder.domains[0]);
+        self.paused.remove(&cert_builder.domains[0]);
+        self.on_demand_certs.remove(&cert_builder.domains[0]);
+        self.queued
+            .retain(|(queued, _)| queued.domains[0] != cert_builder.domains[0]);
+        self.shared.resolver.remove(&cert_builder.domains);
+        #[cfg(feature = "rustls")]
+        self.shared.certified.remove(&cert_builder.domains);
+        self.shared.metrics.remove(&cert_builder.domains[0]);
+        self.shared.status.remove(&cert_builder.domains[0]);
+        self.shared.keys.remove(&cert_builder.domains[0]);
+        info!("{}: no longer managed", cert_builder.domains[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: &str = "127.0.0.1:8443";
+
+    fn domains(cert_builder: &CertBuilder) -> Vec<&str> {
+        cert_builder.domains.iter().map(Domain::as_str).collect()
+    }
+
+    #[test]
+    fn new_lowercases_domains() {
+        let cert_builder = CertBuilder::new(ADDR, &["Example.COM", "WWW.example.com."]);
+        assert_eq!(domains(&cert_builder), ["example.com", "www.example.com"]);
+    }
+
+    #[test]
+    fn new_drops_repeated_domains() {
+        let cert_builder = CertBuilder::new(ADDR, &["example.com", "www.example.com", "EXAMPLE.com"]);
+        assert_eq!(domains(&cert_builder), ["example.com", "www.example.com"]);
+    }
+
+    #[test]
+    fn try_new_rejects_a_url() {
+        let result = CertBuilder::try_new(ADDR, &["example.com", "https://example.com"]);
+        assert_eq!(
+            result.err(),
+            Some(DomainError::Scheme("https://example.com".to_string()))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "remove the scheme")]
+    fn new_panics_on_a_url() {
+        CertBuilder::new(ADDR, &["https://example.com"]);
     }
 }
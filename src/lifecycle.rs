@@ -0,0 +1,65 @@
+//! A broadcast of everything the enabler does, for subscribers building
+//! their own metrics, alerting or automation.
+
+use {
+    crate::{CertInfo, Domain},
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    tokio::sync::broadcast,
+};
+
+// Subscribers that fall further behind than this miss the oldest events.
+const CAPACITY: usize = 64;
+
+/// Finer grained than `Event`, which is what notifiers are sent.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// An order is about to be placed with the CA.
+    OrderStarted { domains: Vec<Domain> },
+    /// The CA (or anyone else) fetched an http-01 challenge response.
+    ChallengeServed { host: String, token: String },
+    /// A certificate was issued and saved.
+    CertIssued(CertInfo),
+    /// A certificate is now being served.
+    CertInstalled {
+        domains: Vec<Domain>,
+        not_after: Option<DateTime<Utc>>,
+    },
+    /// An issuance attempt failed.
+    RenewalFailed {
+        #[serde(flatten)]
+        info: CertInfo,
+        attempt: u32,
+        error: String,
+    },
+    /// The certificate being served expires soon.
+    Expiring(CertInfo),
+    /// A CT log shows a certificate for the domain that wasn't ordered
+    /// here.
+    UnexpectedCertificate {
+        domain: Domain,
+        issuer: String,
+        serial: String,
+    },
+}
+
+#[derive(Clone)]
+pub(crate) struct EventBus(broadcast::Sender<LifecycleEvent>);
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl EventBus {
+    pub(crate) fn send(&self, event: LifecycleEvent) {
+        // Having no subscribers is fine.
+        let _ = self.0.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.0.subscribe()
+    }
+}
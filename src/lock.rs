@@ -0,0 +1,165 @@
+//! Coordination between replicas that share an ssl directory, so that only
+//! one of them orders a given certificate at a time.
+
+use {
+    crate::{Domain, Error},
+    chrono::{DateTime, Utc},
+    rand::{distributions::Alphanumeric, Rng},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{self, File, OpenOptions},
+        io,
+        os::unix::io::AsRawFd,
+        path::PathBuf,
+        time::Duration,
+    },
+};
+
+/// A lock, keyed by a certificate's first domain, that expires after `ttl`
+/// unless renewed, so that a replica dying mid-order can't block the others
+/// forever.
+///
+/// The lock is taken before an order is placed and released once it has
+/// finished, whether it succeeded or not.  While it's held it is renewed
+/// after each challenge is validated, so `ttl` only needs to cover the
+/// longest single step of an order.
+pub trait RenewalLock: Send + Sync {
+    /// Takes the lock, returning `Ok(false)` if another holder has it.
+    fn acquire(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error>;
+
+    /// Extends a lock this holder has, returning `Ok(false)` if it has
+    /// already expired and been taken by someone else.
+    fn renew(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error>;
+
+    /// Gives up a lock this holder has.  Releasing a lock that has been
+    /// taken over by someone else leaves it alone.
+    fn release(&self, domain: &Domain) -> Result<(), Error>;
+}
+
+// Identifies this process as the holder of a lock.
+pub(crate) fn holder_token() -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .collect();
+    format!("{}-{}", std::process::id(), random)
+}
+
+/// Lock files in a directory shared by every replica, e.g. on NFS.  Each
+/// file records its holder and when it expires.  Expired locks are taken
+/// over, and locks renewed and released, under an `flock` on a guard file
+/// beside the lock, which Linux NFS clients map to a lock the server
+/// arbitrates.
+pub struct FileLock {
+    directory: PathBuf,
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LockFile {
+    holder: String,
+    expires: DateTime<Utc>,
+}
+
+impl FileLock {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        FileLock {
+            directory: directory.into(),
+            token: holder_token(),
+        }
+    }
+
+    fn path(&self, domain: &Domain) -> PathBuf {
+        self.directory.join(format!("{}.lock", domain))
+    }
+
+    fn read(&self, domain: &Domain) -> io::Result<Option<LockFile>> {
+        match fs::read_to_string(self.path(domain)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).ok()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lock_file(&self, ttl: Duration) -> LockFile {
+        LockFile {
+            holder: self.token.clone(),
+            expires: Utc::now()
+                + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::max_value()),
+        }
+    }
+
+    // Written aside and linked into place, since linking fails if the lock
+    // exists and the lock is never seen half written.
+    fn create(&self, domain: &Domain, ttl: Duration) -> io::Result<bool> {
+        let temp = self
+            .directory
+            .join(format!("{}.lock.{}", domain, self.token));
+        fs::write(&temp, serde_json::to_string(&self.lock_file(ttl))?)?;
+        let linked = fs::hard_link(&temp, self.path(domain));
+        let _ = fs::remove_file(&temp);
+        match linked {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Held while a lock is read and then replaced or removed, so that no
+    // other replica can change it in between.  Unlocked when dropped.
+    fn guard(&self, domain: &Domain) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(self.directory.join(format!("{}.guard", domain)))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+}
+
+impl RenewalLock for FileLock {
+    fn acquire(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        fs::create_dir_all(&self.directory)?;
+        if self.create(domain, ttl)? {
+            return Ok(true);
+        }
+        // An expired (or unreadable) lock is replaced while holding the
+        // guard, so two replicas that both saw it expire can't each remove
+        // the lock the other just took.
+        let _guard = self.guard(domain)?;
+        match self.read(domain)? {
+            Some(lock) if lock.expires > Utc::now() => Ok(lock.holder == self.token),
+            _ => {
+                match fs::remove_file(self.path(domain)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+                Ok(self.create(domain, ttl)?)
+            }
+        }
+    }
+
+    fn renew(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        let _guard = self.guard(domain)?;
+        match self.read(domain)? {
+            Some(lock) if lock.holder == self.token => {
+                let lock = serde_json::to_string(&self.lock_file(ttl)).map_err(io::Error::from)?;
+                fs::write(self.path(domain), lock)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn release(&self, domain: &Domain) -> Result<(), Error> {
+        let _guard = self.guard(domain)?;
+        if let Some(lock) = self.read(domain)? {
+            if lock.holder == self.token {
+                fs::remove_file(self.path(domain))?;
+            }
+        }
+        Ok(())
+    }
+}
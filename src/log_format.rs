@@ -0,0 +1,66 @@
+//! Machine readable records of each issuance step, for log pipelines such
+//! as ELK or Loki.
+//!
+//! With `LogFormat::Json`, every step of an order (`account`,
+//! `authorization`, `challenge`, `sign`, `store`, and the whole `order`)
+//! also logs one JSON object to the `actix_web_lets_encrypt::step` target:
+//!
+//! ```text
+//! {"domain":"example.com","step":"challenge","outcome":"error","duration_ms":5012,"error":"..."}
+//! ```
+//!
+//! The human readable messages are logged as before, so route or filter on
+//! the target to keep just the records.  Applications already using
+//! `tracing` can instead get the same fields from the spans around each
+//! step, e.g. with `tracing_subscriber::fmt().json()`.
+
+use {
+    crate::{error::DisplayChain, Domain, Error, LetsEncrypt},
+    log::{info, warn},
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    std::time::Instant,
+};
+
+const STEP_TARGET: &str = "actix_web_lets_encrypt::step";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Only the human readable messages.
+    Text,
+    /// The human readable messages plus a JSON record per step.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+impl LetsEncrypt {
+    // Runs one step of an order, logging its outcome and duration as a
+    // record when the log format asks for it.
+    pub(crate) fn step<T, F>(&self, domain: &Domain, step: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        let started = Instant::now();
+        let result = f();
+        if self.config.log_format == LogFormat::Json {
+            let record = json!({
+                "domain": domain,
+                "step": step,
+                "outcome": if result.is_ok() { "ok" } else { "error" },
+                "duration_ms": started.elapsed().as_millis() as u64,
+                "error": result.as_ref().err().map(|e| DisplayChain(e).to_string()),
+            });
+            match result {
+                Ok(_) => info!(target: STEP_TARGET, "{}", record),
+                Err(_) => warn!(target: STEP_TARGET, "{}", record),
+            }
+        }
+        result
+    }
+}
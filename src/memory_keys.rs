@@ -0,0 +1,57 @@
+//! Private keys that are never written to disk.
+//!
+//! With `keys_in_memory` set, each certificate's key lives only in the
+//! enabler, keyed by the certificate's first domain.  A restart loses the
+//! keys, so every certificate is issued again on start; this suits
+//! short-lived staging environments, not production against rate limits.
+
+use {
+    crate::{CertBuilder, Domain, Error, LetsEncrypt},
+    openssl::pkey::{PKey, Private},
+    std::{collections::HashMap, io, sync::Mutex},
+};
+
+#[derive(Default)]
+pub(crate) struct MemoryKeys(Mutex<HashMap<Domain, PKey<Private>>>);
+
+impl MemoryKeys {
+    pub(crate) fn get(&self, domain: &Domain) -> Option<PKey<Private>> {
+        self.0.lock().unwrap().get(domain).cloned()
+    }
+
+    pub(crate) fn insert(&self, domain: &Domain, key: PKey<Private>) {
+        self.0.lock().unwrap().insert(domain.clone(), key);
+    }
+
+    pub(crate) fn remove(&self, domain: &Domain) {
+        self.0.lock().unwrap().remove(domain);
+    }
+}
+
+impl LetsEncrypt {
+    pub(crate) fn memory_key(&self, cert_builder: &CertBuilder) -> Result<PKey<Private>, Error> {
+        let domain = &cert_builder.domains[0];
+        self.shared.keys.get(domain).ok_or_else(|| {
+            Error::from(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no private key held in memory", domain),
+            ))
+        })
+    }
+
+    /// Whether the certificate and its key, wherever keys are kept, are
+    /// both present.
+    pub(crate) fn key_and_cert_present(&self, cert_builder: &CertBuilder) -> bool {
+        if !self.config.keys_in_memory {
+            return cert_builder.key_and_cert_present();
+        }
+        self.shared.keys.get(&cert_builder.domains[0]).is_some()
+            && cert_builder.cert_path.as_ref().unwrap().exists()
+    }
+
+    pub(crate) fn needs_building(&self, cert_builder: &CertBuilder) -> bool {
+        !self.key_and_cert_present(cert_builder)
+            || cert_builder.due(self.now())
+            || !self.issued_in_current_mode(cert_builder)
+    }
+}
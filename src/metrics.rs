@@ -0,0 +1,148 @@
+//! Prometheus metrics, rendered in the text exposition format.
+//!
+//! The same measurements are reported through the `metrics` crate facade,
+//! so a recorder installed by the application (Prometheus, statsd, OTLP,
+//! ...) sees them without enabling the built-in endpoint:
+//!
+//! * `lets_encrypt_renewal_attempts_total`, `_successes_total` and
+//!   `_failures_total` counters, labelled by `domain`
+//! * `lets_encrypt_issuance_duration_seconds` histogram
+//! * `lets_encrypt_challenge_hits_total` counter, each http-01 challenge
+//!   request served
+//! * `lets_encrypt_cert_expiry_seconds` gauge, labelled by `domain`, as of
+//!   the certificate's latest check
+
+use {
+    crate::Domain,
+    chrono::{DateTime, Utc},
+    std::{
+        collections::BTreeMap,
+        fmt::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+
+const LATENCY_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    certs: BTreeMap<Domain, CertMetrics>,
+    // One count per bucket of LATENCY_BUCKETS, not cumulative.
+    latency_buckets: Vec<u64>,
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+#[derive(Default)]
+struct CertMetrics {
+    not_after: Option<DateTime<Utc>>,
+    attempts: u64,
+    successes: u64,
+    failures: u64,
+}
+
+impl Metrics {
+    fn with_cert<F: FnOnce(&mut CertMetrics)>(&self, domain: &Domain, f: F) {
+        let mut inner = self.inner.lock().unwrap();
+        f(inner.certs.entry(domain.clone()).or_default());
+    }
+
+    pub(crate) fn set_expiry(
+        &self,
+        domain: &Domain,
+        not_after: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) {
+        if let Some(not_after) = not_after {
+            let secs = not_after.signed_duration_since(now).num_seconds();
+            ::metrics::gauge!("lets_encrypt_cert_expiry_seconds", secs as f64, "domain" => domain.to_string());
+        }
+        self.with_cert(domain, |cert| cert.not_after = not_after);
+    }
+
+    pub(crate) fn remove(&self, domain: &Domain) {
+        self.inner.lock().unwrap().certs.remove(domain);
+    }
+
+    pub(crate) fn record_attempt(&self, domain: &Domain, latency: Duration, success: bool) {
+        let label = domain.to_string();
+        ::metrics::counter!("lets_encrypt_renewal_attempts_total", 1, "domain" => label.clone());
+        if success {
+            ::metrics::counter!("lets_encrypt_renewal_successes_total", 1, "domain" => label);
+        } else {
+            ::metrics::counter!("lets_encrypt_renewal_failures_total", 1, "domain" => label);
+        }
+        ::metrics::histogram!("lets_encrypt_issuance_duration_seconds", latency.as_secs_f64());
+
+        self.with_cert(domain, |cert| {
+            cert.attempts += 1;
+            if success {
+                cert.successes += 1;
+            } else {
+                cert.failures += 1;
+            }
+        });
+
+        let secs = latency.as_secs_f64();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.latency_buckets.is_empty() {
+            inner.latency_buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            inner.latency_buckets[bucket] += 1;
+        }
+        inner.latency_sum += secs;
+        inner.latency_count += 1;
+    }
+
+    // Only reported through the facade.
+    pub(crate) fn challenge_served() {
+        ::metrics::counter!("lets_encrypt_challenge_hits_total", 1);
+    }
+
+    pub(crate) fn render(&self, now: DateTime<Utc>) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP lets_encrypt_cert_expiry_seconds Seconds until the certificate expires.\n");
+        out.push_str("# TYPE lets_encrypt_cert_expiry_seconds gauge\n");
+        for (domain, cert) in &inner.certs {
+            if let Some(not_after) = cert.not_after {
+                let secs = not_after.signed_duration_since(now).num_seconds();
+                writeln!(out, "lets_encrypt_cert_expiry_seconds{{domain=\"{}\"}} {}", domain, secs).unwrap();
+            }
+        }
+
+        let counters: [(&str, &str, fn(&CertMetrics) -> u64); 3] = [
+            ("renewal_attempts", "Certificate issuance attempts.", |c| c.attempts),
+            ("renewal_successes", "Successful certificate issuances.", |c| c.successes),
+            ("renewal_failures", "Failed certificate issuances.", |c| c.failures),
+        ];
+        for (name, help, value) in &counters {
+            writeln!(out, "# HELP lets_encrypt_{}_total {}", name, help).unwrap();
+            writeln!(out, "# TYPE lets_encrypt_{}_total counter", name).unwrap();
+            for (domain, cert) in &inner.certs {
+                writeln!(out, "lets_encrypt_{}_total{{domain=\"{}\"}} {}", name, domain, value(cert)).unwrap();
+            }
+        }
+
+        out.push_str("# HELP lets_encrypt_issuance_duration_seconds Time taken to issue a certificate.\n");
+        out.push_str("# TYPE lets_encrypt_issuance_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += inner.latency_buckets.get(i).copied().unwrap_or(0);
+            writeln!(out, "lets_encrypt_issuance_duration_seconds_bucket{{le=\"{}\"}} {}", le, cumulative).unwrap();
+        }
+        writeln!(out, "lets_encrypt_issuance_duration_seconds_bucket{{le=\"+Inf\"}} {}", inner.latency_count).unwrap();
+        writeln!(out, "lets_encrypt_issuance_duration_seconds_sum {}", inner.latency_sum).unwrap();
+        writeln!(out, "lets_encrypt_issuance_duration_seconds_count {}", inner.latency_count).unwrap();
+
+        out
+    }
+}
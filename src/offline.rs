@@ -0,0 +1,208 @@
+//! Blocking certificate operations that don't need a running actor.
+//!
+//! Something must still answer the HTTP challenges from the nonce
+//! directory while issuing, either the application itself or a temporary
+//! server such as the one `lets-encrypt-cli --standalone` starts.
+
+use {
+    crate::{AuditActor, AuditOperation, CertBuilder, CertStatus, Domain, Error, LetsEncrypt},
+    log::info,
+    openssl::{
+        ssl::{SslConnector, SslMethod, SslVerifyMode},
+        x509::X509,
+    },
+    std::{
+        fmt, fs,
+        io::Write,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A listener found wrong by `Offline::healthcheck`.
+#[derive(Debug)]
+pub struct Unhealthy {
+    pub domain: Domain,
+    pub addr: SocketAddr,
+    pub problem: String,
+}
+
+impl fmt::Display for Unhealthy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on {}: {}", self.domain, self.addr, self.problem)
+    }
+}
+
+// The certificate served for `domain` on `addr`, connecting to loopback
+// when the listener is bound to every interface.  A listener expecting the
+// PROXY protocol is sent a header without an address, as a balancer's
+// health checks are.
+fn served_cert(domain: &Domain, addr: SocketAddr, proxy_protocol: bool) -> Result<X509, String> {
+    let mut addr = addr;
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        _ => (),
+    }
+    let mut stream =
+        TcpStream::connect_timeout(&addr, HEALTHCHECK_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(HEALTHCHECK_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(HEALTHCHECK_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+    if proxy_protocol {
+        stream
+            .write_all(b"PROXY UNKNOWN\r\n")
+            .map_err(|e| e.to_string())?;
+    }
+    // What's served is compared with the store, rather than verified.
+    let mut connector = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+    connector.set_verify(SslVerifyMode::NONE);
+    let stream = connector
+        .build()
+        .connect(domain.as_str(), stream)
+        .map_err(|e| e.to_string())?;
+    stream
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| "no certificate served".to_string())
+}
+
+pub struct Offline<'a> {
+    enabler: &'a LetsEncrypt,
+}
+
+impl<'a> Offline<'a> {
+    pub(crate) fn new(enabler: &'a LetsEncrypt) -> Self {
+        Offline { enabler }
+    }
+
+    // Every cert when `domain` is None, otherwise the one covering it.
+    fn matching(&self, domain: Option<&Domain>) -> Result<Vec<&'a CertBuilder>, Error> {
+        let enabler: &'a LetsEncrypt = self.enabler;
+        match domain {
+            None => Ok(enabler.config.cert_builders.iter().collect()),
+            Some(domain) => enabler
+                .config
+                .cert_builders
+                .iter()
+                .find(|c| c.domains.contains(domain))
+                .map(|c| vec![c])
+                .ok_or_else(|| Error::NotManaged(domain.clone())),
+        }
+    }
+
+    /// The certificates on disk, with their expiry.
+    pub fn status(&self) -> Vec<CertStatus> {
+        self.enabler
+            .config
+            .cert_builders
+            .iter()
+            .map(|c| CertStatus {
+                domains: c.domains.clone(),
+                not_after: c.not_after(),
+                issuer: c.issuer(),
+                next_check: None,
+                last_renewal: None,
+                last_error: None,
+                consecutive_failures: 0,
+                critical: false,
+            })
+            .collect()
+    }
+
+    /// Issues the certificates (all of them, or the one covering `domain`)
+    /// that are missing or due for renewal, or all of them when `force` is
+    /// set.  Returns the first domain of each certificate issued.
+    pub fn issue(&self, domain: Option<&Domain>, force: bool) -> Result<Vec<Domain>, Error> {
+        let mut issued = Vec::new();
+        for cert_builder in self.matching(domain)? {
+            if force || self.enabler.needs_building(cert_builder) {
+                self.enabler.build_cert(cert_builder, AuditActor::Cli)?;
+                issued.push(cert_builder.domains[0].clone());
+            } else {
+                info!("{}: certificate is current", cert_builder.domains[0]);
+            }
+        }
+        Ok(issued)
+    }
+
+    /// Revokes the certificate covering `domain`, leaving the files alone.
+    pub fn revoke(&self, domain: &Domain) -> Result<(), Error> {
+        for cert_builder in self.matching(Some(domain))? {
+            let cert_path = cert_builder.cert_path.as_ref().unwrap();
+            let account = self.enabler.account(cert_builder)?;
+            account.revoke_certificate_from_file(cert_path)?;
+            info!("{}: revoked", domain);
+            self.enabler.audit_log().record(
+                self.enabler.now(),
+                AuditOperation::Revoked,
+                AuditActor::Cli,
+                &cert_builder.domains,
+                Some(cert_path),
+            );
+        }
+        Ok(())
+    }
+
+    /// Connects to every HTTPS listener of the running server and checks
+    /// that it serves the certificate in the store, with at least
+    /// `min_remaining` left before it expires.  Returns what's wrong, if
+    /// anything, e.g. for a container `HEALTHCHECK`.
+    pub fn healthcheck(&self, min_remaining: Duration) -> Vec<Unhealthy> {
+        let mut unhealthy = Vec::new();
+        for cert_builder in &self.enabler.config.cert_builders {
+            let domain = &cert_builder.domains[0];
+            for &addr in &cert_builder.addrs {
+                let problem = |problem: String| Unhealthy {
+                    domain: domain.clone(),
+                    addr,
+                    problem,
+                };
+                let served = match served_cert(domain, addr, self.enabler.config.proxy_protocol) {
+                    Ok(served) => served,
+                    Err(e) => {
+                        unhealthy.push(problem(e));
+                        continue;
+                    }
+                };
+                let stored = cert_builder.cert().and_then(|cert| cert.to_der().ok());
+                if stored.is_none() || served.to_der().ok() != stored {
+                    unhealthy.push(problem("not serving the stored certificate".to_string()));
+                    continue;
+                }
+                let remaining = cert_builder
+                    .not_after()
+                    .and_then(|not_after| not_after.signed_duration_since(self.enabler.now()).to_std().ok());
+                if remaining.map_or(true, |remaining| remaining < min_remaining) {
+                    unhealthy.push(problem("certificate is about to expire".to_string()));
+                }
+            }
+        }
+        unhealthy
+    }
+
+    /// Copies every key and certificate into `to`, keeping their file
+    /// names, e.g. before pointing `ssl_directory` somewhere new.  Returns
+    /// the files written.
+    pub fn migrate(&self, to: &Path) -> Result<Vec<PathBuf>, Error> {
+        fs::create_dir_all(to)?;
+        let mut written = Vec::new();
+        for cert_builder in &self.enabler.config.cert_builders {
+            if !cert_builder.key_and_cert_present() {
+                continue;
+            }
+            for from in &[&cert_builder.key_path, &cert_builder.cert_path] {
+                let from = from.as_ref().unwrap();
+                let dest = to.join(from.file_name().unwrap());
+                fs::copy(from, &dest)?;
+                info!("copied {} to {}", from.display(), dest.display());
+                written.push(dest);
+            }
+        }
+        Ok(written)
+    }
+}
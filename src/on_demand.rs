@@ -0,0 +1,151 @@
+//! Issuing certificates for hostnames as clients first ask for them,
+//! Caddy's "on-demand TLS".
+//!
+//! A handshake for a hostname nobody has a certificate for yet gets a
+//! short-lived self-signed one, so that it fails quickly rather than
+//! hanging, while the real certificate is issued in the background.  Once
+//! that's installed, new connections get it.
+
+use {
+    crate::{fallback::self_signed, Domain, KeyType},
+    log::warn,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{HashMap, HashSet},
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+};
+
+// How long a hostname that was refused stays refused before asking again.
+const DENIAL_TTL: Duration = Duration::from_secs(10 * 60);
+
+type AllowFuture = Pin<Box<dyn Future<Output = bool>>>;
+pub(crate) type AllowDomain = Arc<dyn Fn(Domain) -> AllowFuture + Send + Sync>;
+
+/// Which hostnames may be issued certificates on demand.  Anyone who can
+/// point DNS at the server can make it ask the CA for a certificate, so
+/// nothing is allowed unless it's listed in `allow` or approved by `ask`
+/// or `LetsEncrypt::allow_domain`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OnDemand {
+    /// Hostnames, or wildcards covering one level of subdomains.
+    #[serde(default)]
+    allow: Vec<Domain>,
+
+    /// A URL that is sent `GET <ask>?domain=<hostname>` for any other
+    /// hostname, and answers with a 2xx status if it may be issued.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ask: Option<String>,
+
+    /// Beyond this many on-demand certificates, the least recently used is
+    /// removed, files and all, to make room.  It's issued again if its
+    /// hostname comes back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_certs: Option<usize>,
+}
+
+impl OnDemand {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow<D: AsRef<str>>(mut self, domain: D) -> Self {
+        let domain = Domain::new(domain).unwrap_or_else(|e| panic!("{}", e));
+        self.allow.push(domain);
+        self
+    }
+
+    pub fn ask<U: Into<String>>(mut self, url: U) -> Self {
+        self.ask = Some(url.into());
+        self
+    }
+
+    pub fn max_certs(mut self, max_certs: usize) -> Self {
+        self.max_certs = Some(max_certs);
+        self
+    }
+
+    pub(crate) fn is_full(&self, issued: usize) -> bool {
+        self.max_certs.map_or(false, |max| issued >= max)
+    }
+
+    pub(crate) fn asks(&self) -> bool {
+        self.ask.is_some()
+    }
+
+    pub(crate) fn allows(&self, host: &Domain) -> bool {
+        !host.is_wildcard() && self.allow.iter().any(|pattern| pattern.matches(host))
+    }
+}
+
+/// The hostnames being asked about, and recently refused, so each is only
+/// asked about once however many handshakes request it.
+#[derive(Clone, Default)]
+pub(crate) struct Asking {
+    pending: HashSet<Domain>,
+    denied: HashMap<Domain, Instant>,
+}
+
+impl Asking {
+    // False if `host` is already being asked about or was refused lately.
+    pub(crate) fn start(&mut self, host: &Domain) -> bool {
+        let now = Instant::now();
+        self.denied
+            .retain(|_, at| now.duration_since(*at) < DENIAL_TTL);
+        !self.denied.contains_key(host) && self.pending.insert(host.clone())
+    }
+
+    pub(crate) fn finish(&mut self, host: &Domain, allowed: bool) {
+        self.pending.remove(host);
+        if !allowed {
+            self.denied.insert(host.clone(), Instant::now());
+        }
+    }
+}
+
+/// Whether `host` passes the `ask` URL, if any, and then `allow_domain`,
+/// if any.  Failing to reach the URL counts as a refusal.
+pub(crate) fn ask(
+    on_demand: &OnDemand,
+    allow_domain: Option<AllowDomain>,
+    host: Domain,
+) -> AllowFuture {
+    let url = on_demand.ask.clone();
+    Box::pin(async move {
+        if let Some(url) = url {
+            let response = awc::Client::new()
+                .get(&url)
+                .query(&[("domain", host.as_str())])
+                .map(|request| request.send());
+            let allowed = match response {
+                Ok(response) => match response.await {
+                    Ok(response) => response.status().is_success(),
+                    Err(e) => {
+                        warn!("{}: could not ask {}: {}", host, url, e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    warn!("{}: could not ask {}: {}", host, url, e);
+                    false
+                }
+            };
+            if !allowed {
+                return false;
+            }
+        }
+        match allow_domain {
+            Some(allow_domain) => allow_domain(host).await,
+            None => true,
+        }
+    })
+}
+
+// The placeholder served until the real certificate arrives.  P-256 keys
+// are quick enough to generate during a handshake.
+pub(crate) fn placeholder(host: &Domain) -> Option<openssl::ssl::SslContext> {
+    self_signed(&[host.clone()], KeyType::EcdsaP256).ok()
+}
@@ -0,0 +1,33 @@
+//! Exporting issuance as OpenTelemetry traces (feature `otel`).
+//!
+//! Each order is a root `order` span, with a child span for every CA round
+//! trip (`register_account`, `authorization`, `validate_challenge`,
+//! `sign_csr`) and for writing the files (`store`).  An application that
+//! already exports its `tracing` spans through `tracing-opentelemetry` gets
+//! these too; otherwise add the layer returned here:
+//!
+//! ```text
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let tracer = /* e.g. opentelemetry_jaeger::new_pipeline().install() */;
+//! let subscriber = tracing_subscriber::registry().with(actix_web_lets_encrypt::otel_layer(tracer));
+//! tracing::subscriber::set_global_default(subscriber)?;
+//! ```
+
+use {
+    opentelemetry::trace::Tracer,
+    tracing::Subscriber,
+    tracing_opentelemetry::{OpenTelemetryLayer, PreSampledTracer},
+    tracing_subscriber::registry::LookupSpan,
+};
+
+/// A `tracing-subscriber` layer exporting spans, including the issuance
+/// spans, through `tracer`, so ACME orders show up in Jaeger or Tempo next
+/// to the application's own traces.
+pub fn otel_layer<S, T>(tracer: T) -> OpenTelemetryLayer<S, T>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    T: Tracer + PreSampledTracer + 'static,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
@@ -0,0 +1,155 @@
+//! Dropping root after the listeners are bound.
+//!
+//! Binding ports 80 and 443 needs root (or `CAP_NET_BIND_SERVICE`), but
+//! nothing after that does.  With `run_as` set, `drop_privileges` hands the
+//! ssl and nonce directories, and any key or certificate kept elsewhere,
+//! to the target user, then switches the whole process to it.  The
+//! directories of files kept elsewhere, such as `/etc/ssl/private`, aren't
+//! the enabler's to give away, so they must already be writable by that
+//! user.
+
+use {
+    crate::{credentials, LetsEncrypt},
+    log::info,
+    serde::{Deserialize, Serialize},
+    std::{
+        ffi::CString,
+        fs, io,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+        path::Path,
+        ptr,
+    },
+};
+
+/// The user and group to run as once the listeners are bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn chown(path: &Path, run_as: RunAs) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    check(unsafe { libc::lchown(path.as_ptr(), run_as.uid, run_as.gid) })
+}
+
+// Gives `path` and everything under it to `run_as`; a missing path is
+// created later by the unprivileged process, so is fine.
+fn chown_tree(path: &Path, run_as: RunAs) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    chown(path, run_as)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            chown_tree(&entry?.path(), run_as)?;
+        }
+    }
+    Ok(())
+}
+
+// New keys and certificates are renamed into their directory, so `run_as`
+// must be able to write to the directory of each one kept outside the ssl
+// directory.  A missing directory is created later, where allowed.
+fn check_writable(directory: &Path, run_as: RunAs) -> io::Result<()> {
+    let metadata = match fs::metadata(directory) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mode = metadata.mode();
+    let writable = (metadata.uid() == run_as.uid && mode & 0o200 != 0)
+        || (metadata.gid() == run_as.gid && mode & 0o020 != 0)
+        || mode & 0o002 != 0;
+    if writable {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{}: not writable by uid {} gid {}; give them a directory of their own, \
+                 or keep the file under the ssl directory",
+                directory.display(),
+                run_as.uid,
+                run_as.gid
+            ),
+        ))
+    }
+}
+
+impl RunAs {
+    fn switch(self) -> io::Result<()> {
+        // Supplementary groups first, then the group, while still root.
+        check(unsafe { libc::setgroups(0, ptr::null()) })?;
+        check(unsafe { libc::setgid(self.gid) })?;
+        check(unsafe { libc::setuid(self.uid) })?;
+        // Make sure there's no way back.
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "root privileges could be regained after dropping them",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl LetsEncrypt {
+    /// Switches the process to the `run_as` user and group, after giving
+    /// them the directories the enabler writes to.  Call it once every
+    /// listener is bound, i.e. after `attach_certificates_to` and binding
+    /// the HTTP port, and before running the server.  Does nothing without
+    /// `run_as`.  Fails, still as root and before anything is chowned, if
+    /// a key or certificate is kept in a directory they can't write to.
+    pub fn drop_privileges(&self) -> io::Result<()> {
+        let run_as = match self.config.run_as {
+            Some(run_as) => run_as,
+            None => return Ok(()),
+        };
+        let outside: Vec<_> = self
+            .config
+            .cert_builders
+            .iter()
+            .flat_map(|c| vec![c.key_path.clone().unwrap(), c.cert_path.clone().unwrap()])
+            .chain(Some(self.account_key_file(None)))
+            .filter(|path| !path.starts_with(&self.config.ssl_directory))
+            // systemd keeps credentials on a read-only mount.
+            .filter(|path| credentials::directory().map_or(true, |dir| !path.starts_with(dir)))
+            .collect();
+        // Checked before anything changes hands.
+        for path in &outside {
+            if let Some(parent) = path.parent() {
+                check_writable(parent, run_as)?;
+            }
+        }
+        chown_tree(&self.config.ssl_directory, run_as)?;
+        chown_tree(&self.config.nonce_directory, run_as)?;
+        for path in &outside {
+            chown(path, run_as).or_else(ignore_missing)?;
+        }
+        run_as.switch()?;
+        info!(
+            "dropped privileges to uid {} gid {}",
+            run_as.uid, run_as.gid
+        );
+        Ok(())
+    }
+}
+
+fn ignore_missing(e: io::Error) -> io::Result<()> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
@@ -0,0 +1,158 @@
+//! Liveness and readiness endpoints for Kubernetes probes.
+//!
+//! Liveness answers 200 as long as the server answers at all.  Readiness
+//! answers 503 until every production certificate is issued, unexpired and
+//! served on its addresses, so that a new pod gets no traffic before it can
+//! actually speak HTTPS; `ReadinessGrace` relaxes that for pods that should
+//! come up anyway when the CA is slow or unreachable.
+
+use {
+    crate::{serde_duration, LetsEncrypt},
+    actix_web::{web, HttpResponse},
+    serde::{Deserialize, Serialize},
+    std::time::{Duration, Instant},
+};
+
+/// When a pod is ready even though some certificates aren't served yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessGrace {
+    /// Never; not ready until every production certificate is served.
+    Strict,
+    /// Once this long has passed since the enabler started.
+    After(#[serde(with = "serde_duration")] Duration),
+    /// Once every certificate has been served once, even if one later
+    /// expires, so that a failing renewal doesn't take every pod out of
+    /// the service at the same moment.
+    FirstReady,
+}
+
+impl Default for ReadinessGrace {
+    fn default() -> Self {
+        ReadinessGrace::Strict
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Probes {
+    #[serde(default = "Probes::default_liveness_path")]
+    liveness_path: String,
+    #[serde(default = "Probes::default_readiness_path")]
+    readiness_path: String,
+    #[serde(default)]
+    grace: ReadinessGrace,
+}
+
+impl Default for Probes {
+    fn default() -> Self {
+        Probes {
+            liveness_path: Self::default_liveness_path(),
+            readiness_path: Self::default_readiness_path(),
+            grace: ReadinessGrace::default(),
+        }
+    }
+}
+
+impl Probes {
+    /// Probes at `/livez` and `/readyz`, without grace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn default_liveness_path() -> String {
+        "/livez".to_string()
+    }
+
+    fn default_readiness_path() -> String {
+        "/readyz".to_string()
+    }
+
+    pub fn liveness_path<P: Into<String>>(mut self, path: P) -> Self {
+        self.liveness_path = path.into();
+        self
+    }
+
+    pub fn readiness_path<P: Into<String>>(mut self, path: P) -> Self {
+        self.readiness_path = path.into();
+        self
+    }
+
+    pub fn grace(mut self, grace: ReadinessGrace) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    pub(crate) fn liveness_route(&self) -> &str {
+        &self.liveness_path
+    }
+
+    pub(crate) fn readiness_route(&self) -> &str {
+        &self.readiness_path
+    }
+}
+
+impl LetsEncrypt {
+    // Called when the actor starts, which the grace period counts from.
+    // A restarted actor keeps the original start.
+    pub(crate) fn start_probes(&self) {
+        self.shared
+            .probes_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    // Every production certificate, or every one from an `acme_directory`,
+    // is unexpired, installed, and bound to its addresses if it has any.
+    fn all_served(&self) -> bool {
+        let now = self.now();
+        let bound = self.shared.bound.lock().unwrap();
+        self.config
+            .cert_builders
+            .iter()
+            .filter(|c| {
+                self.config.acme_directory.is_some()
+                    || (c.is_production() && !self.staging_forced())
+            })
+            .all(|c| {
+                let installed = self
+                    .shared
+                    .status
+                    .get(&c.domains[0])
+                    .and_then(|status| status.not_after)
+                    .map_or(false, |not_after| not_after > now);
+                let bound = c.addrs.is_empty() || bound.iter().any(|(d, _)| *d == c.domains[0]);
+                installed && bound
+            })
+    }
+
+    fn ready(&self, probes: &Probes) -> bool {
+        match probes.grace {
+            ReadinessGrace::Strict => self.all_served(),
+            ReadinessGrace::After(grace) => {
+                let since = *self.shared.probes_since.lock().unwrap();
+                since.map_or(false, |since| since.elapsed() >= grace) || self.all_served()
+            }
+            ReadinessGrace::FirstReady => {
+                let mut once = self.shared.ready_once.lock().unwrap();
+                *once = *once || self.all_served();
+                *once
+            }
+        }
+    }
+}
+
+pub(crate) async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+pub(crate) async fn readiness(
+    enabler: web::Data<LetsEncrypt>,
+    probes: web::Data<Probes>,
+) -> HttpResponse {
+    if enabler.ready(&probes) {
+        HttpResponse::Ok().body("ok")
+    } else {
+        HttpResponse::ServiceUnavailable().body("certificates not served yet")
+    }
+}
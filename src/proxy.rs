@@ -0,0 +1,383 @@
+//! Running behind a reverse proxy or CDN that terminates TLS.
+//!
+//! Behind a proxy, every request arrives over plain HTTP from the proxy's
+//! address, and the original scheme and host are only known from the
+//! `Forwarded` or `X-Forwarded-Proto`/`X-Forwarded-Host` headers.  Those
+//! are honored only from `trusted_proxies`, since anyone else could set
+//! them; the scheme decides what `https_redirect` redirects and what host
+//! discovery counts, and the host is what challenge requests are logged
+//! under.
+
+use {
+    crate::{Domain, LetsEncrypt, TlsInfo},
+    actix_service::{Service, Transform},
+    actix_web::{
+        dev::{ServiceRequest, ServiceResponse},
+        http::{
+            header::{self, HeaderMap},
+            uri::Authority,
+        },
+        HttpRequest, HttpResponse,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        convert::TryFrom,
+        fmt,
+        future::{ready, Future, Ready},
+        net::IpAddr,
+        pin::Pin,
+        str::FromStr,
+        task::{Context, Poll},
+    },
+};
+
+/// An address, or a CIDR block such as `10.0.0.0/8`, whose forwarding
+/// headers are believed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TrustedProxy {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{}: not an IP address or CIDR block", s);
+        let (addr, prefix) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(TrustedProxy { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TrustedProxy> for String {
+    fn from(proxy: TrustedProxy) -> Self {
+        proxy.to_string()
+    }
+}
+
+impl fmt::Display for TrustedProxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// An IPv4 peer of a dual-stack listener arrives as `::ffff:a.b.c.d`.
+fn unmapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => IpAddr::V4(v6.to_ipv4().unwrap()),
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+impl TrustedProxy {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = if self.addr.is_ipv4() {
+            unmapped(ip)
+        } else {
+            ip
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The scheme and host a request was originally made with.
+pub(crate) struct Origin {
+    pub(crate) scheme: String,
+    pub(crate) host: String,
+}
+
+// The first value of a comma separated header.
+fn first(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let first = value.split(',').next()?.trim();
+    if first.is_empty() {
+        None
+    } else {
+        Some(first.to_string())
+    }
+}
+
+// The proto and host of the first element of a `Forwarded` header, i.e.
+// what the outermost proxy saw.
+fn forwarded(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let (mut proto, mut host) = (None, None);
+    if let Some(element) = first(headers, "forwarded") {
+        for pair in element.split(';') {
+            let mut pair = pair.splitn(2, '=');
+            let (name, value) = match (pair.next(), pair.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim().trim_matches('"')),
+                _ => continue,
+            };
+            if name.eq_ignore_ascii_case("proto") {
+                proto = Some(value.to_ascii_lowercase());
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value.to_string());
+            }
+        }
+    }
+    (proto, host)
+}
+
+// Where to redirect a request for `host`, a `Host` header value, and
+// `path` to, on the port `https_port` gives for the host unless it's the
+// default.  None if `host` isn't a valid authority.
+fn https_location<F>(host: &str, path: &str, https_port: F) -> Option<String>
+where
+    F: FnOnce(&str) -> u16,
+{
+    let authority: Authority = host.parse().ok()?;
+    // Keeps the brackets of an IPv6 literal.
+    let host = authority.host();
+    let port = https_port(host);
+    Some(if port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, port, path)
+    })
+}
+
+impl LetsEncrypt {
+    fn from_trusted_proxy(&self, req: &HttpRequest) -> bool {
+        req.peer_addr().map_or(false, |peer| {
+            self.config
+                .trusted_proxies
+                .iter()
+                .any(|proxy| proxy.contains(peer.ip()))
+        })
+    }
+
+    // The port HTTPS is served on for `host`: the first one bound for the
+    // cert covering it, else its configured one.  A proxy serves HTTPS on
+    // the default port, as does a cert without a listener of its own.
+    fn https_port(&self, req: &HttpRequest, host: &str) -> u16 {
+        let host = match Domain::new(host) {
+            Ok(host) if !self.from_trusted_proxy(req) => host,
+            _ => return 443,
+        };
+        let cert_builder = match self
+            .config
+            .cert_builders
+            .iter()
+            .find(|c| c.domains.iter().any(|d| d.matches(&host)))
+        {
+            Some(cert_builder) => cert_builder,
+            None => return 443,
+        };
+        let bound = self.shared.bound.lock().unwrap();
+        bound
+            .iter()
+            .find(|(d, _)| *d == cert_builder.domains[0])
+            .map(|(_, addr)| *addr)
+            .or_else(|| cert_builder.addrs.first().copied())
+            .map(|addr| addr.port())
+            .filter(|port| *port != 0)
+            .unwrap_or(443)
+    }
+
+    pub(crate) fn origin(&self, req: &HttpRequest) -> Origin {
+        // Connections served by `proxy_protocol_server` have the default
+        // app config, but carry their TLS details.
+        let secure = req.app_config().secure() || req.extensions().get::<TlsInfo>().is_some();
+        let direct_scheme = if secure { "https" } else { "http" };
+        let direct_host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| req.uri().authority().map(|a| a.to_string()))
+            .unwrap_or_else(|| req.app_config().host().to_string());
+        if !self.from_trusted_proxy(req) {
+            return Origin {
+                scheme: direct_scheme.to_string(),
+                host: direct_host,
+            };
+        }
+        let headers = req.headers();
+        let (proto, host) = forwarded(headers);
+        Origin {
+            scheme: proto
+                .or_else(|| first(headers, "x-forwarded-proto").map(|p| p.to_ascii_lowercase()))
+                .unwrap_or_else(|| direct_scheme.to_string()),
+            host: host
+                .or_else(|| first(headers, "x-forwarded-host"))
+                .unwrap_or(direct_host),
+        }
+    }
+}
+
+/// Middleware that redirects plain HTTP requests to HTTPS, except for the
+/// ACME challenges; see `LetsEncrypt::https_redirect`.
+pub struct HttpsRedirect {
+    enabler: LetsEncrypt,
+}
+
+impl HttpsRedirect {
+    pub(crate) fn new(enabler: LetsEncrypt) -> Self {
+        HttpsRedirect { enabler }
+    }
+}
+
+impl<S, B> Transform<S> for HttpsRedirect
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = HttpsRedirectMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HttpsRedirectMiddleware {
+            service,
+            enabler: self.enabler.clone(),
+        }))
+    }
+}
+
+pub struct HttpsRedirectMiddleware<S> {
+    service: S,
+    enabler: LetsEncrypt,
+}
+
+impl<S, B> Service for HttpsRedirectMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let origin = self.enabler.origin(req.request());
+        if origin.scheme == "https" || req.path().starts_with("/.well-known/acme-challenge/") {
+            return Box::pin(self.service.call(req));
+        }
+        let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+        let enabler = &self.enabler;
+        let location = https_location(&origin.host, path, |host| {
+            enabler.https_port(req.request(), host)
+        });
+        let response = match location {
+            Some(location) => HttpResponse::MovedPermanently()
+                .header(header::LOCATION, location)
+                .finish(),
+            None => HttpResponse::BadRequest().finish(),
+        }
+        .into_body();
+        Box::pin(ready(Ok(req.into_response(response))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(s: &str) -> TrustedProxy {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_addresses_and_blocks() {
+        assert_eq!(proxy("10.0.0.1").to_string(), "10.0.0.1/32");
+        assert_eq!(proxy("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert_eq!(proxy("::1").to_string(), "::1/128");
+        assert_eq!(proxy("fd00::/8").to_string(), "fd00::/8");
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("fd00::/129".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/".parse::<TrustedProxy>().is_err());
+        assert!("proxy.example.com".parse::<TrustedProxy>().is_err());
+    }
+
+    #[test]
+    fn contains_addresses_in_the_block() {
+        assert!(proxy("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!proxy("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(proxy("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!proxy("10.0.0.1").contains(ip("10.0.0.2")));
+        assert!(proxy("0.0.0.0/0").contains(ip("192.0.2.1")));
+        assert!(proxy("fd00::/8").contains(ip("fd12::1")));
+        assert!(!proxy("fd00::/8").contains(ip("fe80::1")));
+        assert!(proxy("::/0").contains(ip("2001:db8::1")));
+        // IPv4 peers of a dual-stack listener are mapped into IPv6.
+        assert!(proxy("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(!proxy("10.0.0.0/8").contains(ip("::ffff:11.0.0.1")));
+        // An IPv4 block doesn't contain other IPv6 addresses, or the reverse.
+        assert!(!proxy("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(!proxy("::/0").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn redirects_to_the_https_port() {
+        let location = |host, port| https_location(host, "/a?b", |_| port);
+        assert_eq!(
+            location("example.com:8080", 443).as_deref(),
+            Some("https://example.com/a?b")
+        );
+        assert_eq!(
+            location("example.com", 8443).as_deref(),
+            Some("https://example.com:8443/a?b")
+        );
+        assert_eq!(
+            location("[2001:db8::1]:8080", 8443).as_deref(),
+            Some("https://[2001:db8::1]:8443/a?b")
+        );
+        assert_eq!(
+            location("[2001:db8::1]", 443).as_deref(),
+            Some("https://[2001:db8::1]/a?b")
+        );
+        assert_eq!(location("exa mple.com", 443), None);
+    }
+}
@@ -0,0 +1,485 @@
+//! The PROXY protocol on the managed listeners.
+//!
+//! Behind a load balancer that forwards TCP, such as an AWS NLB or HAProxy
+//! in `mode tcp`, every connection comes from the balancer, and the
+//! client's address is only known from the PROXY protocol header (v1 or
+//! v2) it prepends.  `HttpServer` has no hook that runs before its TLS
+//! handshake, so with `proxy_protocol` the HTTPS listeners are served by
+//! `proxy_protocol_server` instead of `attach_certificates_to`: an actix
+//! server whose accept pipeline reads the header off each connection,
+//! then does the TLS handshake and hands the request to the app with the
+//! client's address as its peer address.  `proxy_protocol_http` adds a
+//! plain HTTP listener to it, e.g. the one answering HTTP challenges.  A
+//! connection without a valid header is dropped, so the balancer must send
+//! one on every connection, the CA's and its own health checks included.
+//!
+//! The app is built with actix-web's default `AppConfig`, which only
+//! matters to `connection_info`: its scheme reads `http` on HTTPS
+//! connections and its host falls back to `localhost:8080` when a request
+//! has no `Host` header.  `TlsInfo` is captured on every HTTPS connection.
+
+use {
+    crate::{LetsEncrypt, TlsInfo},
+    actix_http::{
+        body::MessageBody, Error as HttpError, Extensions, HttpServiceBuilder, Protocol, Request,
+        Response,
+    },
+    actix_server::{Server, ServerBuilder},
+    actix_service::{
+        fn_service, map_config, pipeline_factory, IntoServiceFactory, Service, ServiceFactory,
+    },
+    actix_tls::openssl::{Acceptor, SslStream},
+    actix_web::{
+        dev::AppConfig,
+        rt::{net::TcpStream, time::timeout},
+    },
+    log::{debug, info},
+    openssl::ssl::{AlpnError, SslAcceptor, SslAcceptorBuilder},
+    std::{
+        convert::TryInto,
+        fmt,
+        future::ready,
+        io,
+        net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        pin::Pin,
+        str,
+        task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+
+const SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX: usize = 107;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Parsed {
+    Incomplete,
+    // The client's address, unless the balancer sent none, and the length
+    // of the header.
+    Header {
+        client: Option<SocketAddr>,
+        len: usize,
+    },
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse(buf: &[u8]) -> io::Result<Parsed> {
+    if SIGNATURE.starts_with(&buf[..buf.len().min(SIGNATURE.len())]) {
+        parse_v2(buf)
+    } else if V1_PREFIX.starts_with(&buf[..buf.len().min(V1_PREFIX.len())]) {
+        parse_v1(buf)
+    } else {
+        Err(invalid("no PROXY protocol header"))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> io::Result<Parsed> {
+    let end = match buf.windows(2).take(V1_MAX - 1).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() < V1_MAX => return Ok(Parsed::Incomplete),
+        None => return Err(invalid("PROXY protocol header too long")),
+    };
+    let line =
+        str::from_utf8(&buf[..end]).map_err(|_| invalid("malformed PROXY protocol header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let client = match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4", source, _, port, _] | ["PROXY", "TCP6", source, _, port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("malformed PROXY protocol address"))?;
+            let port: u16 = port
+                .parse()
+                .map_err(|_| invalid("malformed PROXY protocol port"))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("malformed PROXY protocol header")),
+    };
+    Ok(Parsed::Header {
+        client,
+        len: end + 2,
+    })
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Parsed> {
+    if buf.len() < 16 {
+        return Ok(Parsed::Incomplete);
+    }
+    if buf[12] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let len = 16 + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if buf.len() < len {
+        return Ok(Parsed::Incomplete);
+    }
+    let addresses = &buf[16..len];
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    let client = match (buf[12] & 0x0f, buf[13] >> 4) {
+        // LOCAL: the balancer's own connection, e.g. a health check.
+        (0, _) => None,
+        (1, 1) if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8)))
+        }
+        (1, 2) if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32)))
+        }
+        // Unspecified, or a Unix socket: no address to go by.
+        (1, 0) | (1, 3) => None,
+        _ => return Err(invalid("malformed PROXY protocol header")),
+    };
+    Ok(Parsed::Header { client, len })
+}
+
+// The client's address, and whatever was read past the header.
+async fn read_header(stream: &mut TcpStream) -> io::Result<(Option<SocketAddr>, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 512];
+    loop {
+        if let Parsed::Header { client, len } = parse(&buf)? {
+            return Ok((client, buf.split_off(len)));
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// A connection whose PROXY protocol header has been read: what was read
+/// past the header is served before the rest of the stream.
+pub(crate) struct Proxied {
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    client: SocketAddr,
+}
+
+impl Proxied {
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+}
+
+impl AsyncRead for Proxied {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.buffered.is_empty() {
+            return Pin::new(&mut self.stream).poll_read(cx, buf);
+        }
+        let len = buf.len().min(self.buffered.len());
+        buf[..len].copy_from_slice(&self.buffered[..len]);
+        self.buffered.drain(..len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for Proxied {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+async fn accept(mut stream: TcpStream) -> io::Result<Proxied> {
+    let peer = stream.peer_addr()?;
+    let header = match timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+        Ok(header) => header,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no PROXY protocol header",
+        )),
+    };
+    match header {
+        Ok((client, buffered)) => Ok(Proxied {
+            stream,
+            buffered,
+            client: client.unwrap_or(peer),
+        }),
+        Err(e) => {
+            debug!("dropped the connection from {}: {}", peer, e);
+            Err(e)
+        }
+    }
+}
+
+fn protocol<T>(stream: &SslStream<T>) -> Protocol {
+    match stream.ssl().selected_alpn_protocol() {
+        Some(b"h2") => Protocol::Http2,
+        _ => Protocol::Http1,
+    }
+}
+
+// Negotiates HTTP/2 the way `HttpServer` does.
+fn alpn_acceptor(mut builder: SslAcceptorBuilder) -> io::Result<SslAcceptor> {
+    builder.set_alpn_select_callback(|_, protocols| {
+        if protocols.windows(3).any(|window| window == b"\x02h2") {
+            Ok(b"h2")
+        } else if protocols.windows(9).any(|window| window == b"\x08http/1.1") {
+            Ok(b"http/1.1")
+        } else {
+            Err(AlpnError::NOACK)
+        }
+    });
+    builder.set_alpn_protos(b"\x08http/1.1\x02h2")?;
+    Ok(builder.build())
+}
+
+fn https_service<I, S, B>(
+    service: I,
+    acceptor: SslAcceptor,
+) -> impl ServiceFactory<Config = (), Request = TcpStream, Response = (), Error = (), InitError = ()>
+where
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+    S::Error: Into<HttpError> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    pipeline_factory(fn_service(accept))
+        .map_err(|_| ())
+        .and_then(Acceptor::new(acceptor).map_err(|_| ()))
+        .and_then(fn_service(|io: SslStream<Proxied>| {
+            let (proto, client) = (protocol(&io), io.get_ref().client);
+            ready(Ok::<_, ()>((io, proto, Some(client))))
+        }))
+        .and_then(
+            HttpServiceBuilder::new()
+                .on_connect_ext(|io: &SslStream<Proxied>, ext: &mut Extensions| {
+                    TlsInfo::capture(io, ext)
+                })
+                .finish(map_config(service, |_| AppConfig::default()))
+                .map_err(|_| ()),
+        )
+}
+
+fn http_service<I, S, B>(
+    service: I,
+) -> impl ServiceFactory<Config = (), Request = TcpStream, Response = (), Error = (), InitError = ()>
+where
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+    S::Error: Into<HttpError> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    pipeline_factory(fn_service(accept))
+        .map_err(|_| ())
+        .and_then(fn_service(|io: Proxied| {
+            let client = io.client;
+            ready(Ok::<_, ()>((io, Protocol::Http1, Some(client))))
+        }))
+        .and_then(
+            HttpServiceBuilder::<Proxied, _>::new()
+                .finish(map_config(service, |_| AppConfig::default()))
+                .map_err(|_| ()),
+        )
+}
+
+impl LetsEncrypt {
+    /// Serves the app `factory` builds on the HTTPS listeners of the
+    /// managed certificates, reading the PROXY protocol header off every
+    /// connection; what `attach_certificates_to` does without
+    /// `proxy_protocol`.  Add the HTTP listener with `proxy_protocol_http`
+    /// and `run` the returned builder.
+    pub fn proxy_protocol_server<F, I, S, B>(&self, factory: F) -> io::Result<ServerBuilder>
+    where
+        F: Fn() -> I + Send + Clone + 'static,
+        I: IntoServiceFactory<S>,
+        S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+        S::Error: Into<HttpError> + 'static,
+        S::InitError: fmt::Debug,
+        S::Response: Into<Response<B>> + 'static,
+        <S::Service as Service>::Future: 'static,
+        B: MessageBody + 'static,
+    {
+        let mut server = Server::build();
+        if let Some(timeout) = self.config.shutdown_timeout {
+            server = server.shutdown_timeout(timeout.as_secs());
+        }
+        for (cert_builder, builder, activated) in self.https_listeners()? {
+            let listener = match activated {
+                Some(listener) => listener,
+                None => net::TcpListener::bind(cert_builder.addrs[0])?,
+            };
+            let addr = listener.local_addr()?;
+            let acceptor = alpn_acceptor(builder)?;
+            let factory = factory.clone();
+            server = server.listen(format!("proxied-https-{}", addr), listener, move || {
+                https_service(factory(), acceptor.clone())
+            })?;
+            info!(
+                "{}: reading PROXY protocol headers on {}",
+                cert_builder.domains[0], addr
+            );
+            self.record_bound(cert_builder, vec![addr]);
+        }
+        Ok(server)
+    }
+
+    /// Adds a plain HTTP `listener` to `server`, reading the PROXY protocol
+    /// header off every connection, e.g. for the app answering HTTP
+    /// challenges.
+    pub fn proxy_protocol_http<F, I, S, B>(
+        &self,
+        server: ServerBuilder,
+        listener: net::TcpListener,
+        factory: F,
+    ) -> io::Result<ServerBuilder>
+    where
+        F: Fn() -> I + Send + Clone + 'static,
+        I: IntoServiceFactory<S>,
+        S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+        S::Error: Into<HttpError> + 'static,
+        S::InitError: fmt::Debug,
+        S::Response: Into<Response<B>> + 'static,
+        <S::Service as Service>::Future: 'static,
+        B: MessageBody + 'static,
+    {
+        let addr = listener.local_addr()?;
+        info!("reading PROXY protocol headers on {}", addr);
+        server.listen(format!("proxied-http-{}", addr), listener, move || {
+            http_service(factory())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(buf: &[u8]) -> (Option<SocketAddr>, usize) {
+        match parse(buf).unwrap() {
+            Parsed::Header { client, len } => (client, len),
+            Parsed::Incomplete => panic!("incomplete"),
+        }
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut buf = SIGNATURE.to_vec();
+        buf.push(0x20 | command);
+        buf.push((family << 4) | 1);
+        buf.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        buf.extend_from_slice(addresses);
+        buf
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let buf = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+        assert_eq!(
+            header(buf),
+            (Some("192.0.2.1:56324".parse().unwrap()), buf.len() - 5)
+        );
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let buf = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        assert_eq!(
+            header(buf),
+            (Some("[2001:db8::1]:56324".parse().unwrap()), buf.len())
+        );
+    }
+
+    #[test]
+    fn v1_unknown() {
+        assert_eq!(header(b"PROXY UNKNOWN\r\n"), (None, 15));
+    }
+
+    #[test]
+    fn v1_incomplete() {
+        assert!(matches!(parse(b"PROX"), Ok(Parsed::Incomplete)));
+        assert!(matches!(
+            parse(b"PROXY TCP4 192.0.2.1"),
+            Ok(Parsed::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn v1_too_long() {
+        let mut buf = b"PROXY UNKNOWN ".to_vec();
+        buf.resize(V1_MAX + 10, b'x');
+        assert!(parse(&buf).is_err());
+    }
+
+    #[test]
+    fn v1_malformed() {
+        assert!(parse(b"PROXY TCP4 192.0.2.1 nonsense\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 not-an-ip 198.51.100.1 1 2\r\n").is_err());
+    }
+
+    #[test]
+    fn v2_inet() {
+        let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let mut buf = v2(1, 1, &addresses);
+        let len = buf.len();
+        buf.extend_from_slice(b"\x16\x03\x01");
+        assert_eq!(
+            header(&buf),
+            (Some("192.0.2.1:56324".parse().unwrap()), len)
+        );
+    }
+
+    #[test]
+    fn v2_inet6() {
+        let mut addresses = Ipv6Addr::LOCALHOST.octets().to_vec();
+        addresses.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets());
+        addresses.extend_from_slice(&8443u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        let buf = v2(1, 2, &addresses);
+        assert_eq!(header(&buf), (Some("[::1]:8443".parse().unwrap()), 52));
+    }
+
+    #[test]
+    fn v2_local() {
+        let buf = v2(0, 0, &[]);
+        assert_eq!(header(&buf), (None, 16));
+    }
+
+    #[test]
+    fn v2_incomplete() {
+        let buf = v2(1, 1, &[0; 12]);
+        assert!(matches!(parse(&buf[..10]), Ok(Parsed::Incomplete)));
+        assert!(matches!(parse(&buf[..20]), Ok(Parsed::Incomplete)));
+    }
+
+    #[test]
+    fn v2_bad_version() {
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf[12] = 0x11;
+        assert!(parse(&buf).is_err());
+    }
+
+    #[test]
+    fn no_header() {
+        assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(parse(b"\x16\x03\x01").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! A `RenewalLock` kept in Redis.
+
+use {
+    crate::{lock::holder_token, Domain, Error, RenewalLock},
+    redis::{Client, Script},
+    std::time::Duration,
+};
+
+const KEY_PREFIX: &str = "lets-encrypt:lock:";
+
+// Only touch the key if it still holds our token.
+const RENEW: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+const RELEASE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Locks held as Redis keys with an expiry, set only if absent.
+pub struct RedisLock {
+    client: Client,
+    token: String,
+}
+
+impl RedisLock {
+    /// `url` is a Redis connection URL such as `redis://127.0.0.1/`.
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(RedisLock {
+            client: Client::open(url)?,
+            token: holder_token(),
+        })
+    }
+
+    fn key(domain: &Domain) -> String {
+        format!("{}{}", KEY_PREFIX, domain)
+    }
+}
+
+impl RenewalLock for RedisLock {
+    fn acquire(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        let mut connection = self.client.get_connection()?;
+        let set: Option<String> = redis::cmd("SET")
+            .arg(Self::key(domain))
+            .arg(&self.token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query(&mut connection)?;
+        Ok(set.is_some())
+    }
+
+    fn renew(&self, domain: &Domain, ttl: Duration) -> Result<bool, Error> {
+        let mut connection = self.client.get_connection()?;
+        let renewed: i64 = Script::new(RENEW)
+            .key(Self::key(domain))
+            .arg(&self.token)
+            .arg(ttl.as_millis() as u64)
+            .invoke(&mut connection)?;
+        Ok(renewed == 1)
+    }
+
+    fn release(&self, domain: &Domain) -> Result<(), Error> {
+        let mut connection = self.client.get_connection()?;
+        let _: i64 = Script::new(RELEASE)
+            .key(Self::key(domain))
+            .arg(&self.token)
+            .invoke(&mut connection)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Reloading certificates replaced on disk by something else.
+//!
+//! Certificates renewed by another tool, or swapped by hand, are only
+//! noticed at their next check.  `LetsEncrypt::reload`, or a SIGHUP with
+//! `reload_on_sighup`, re-reads every certificate and key from the store
+//! and swaps them into the live listeners, like `nginx -s reload`.
+
+use {
+    crate::{error::DisplayChain, LetsEncrypt, NotRunning},
+    actix::prelude::*,
+    log::{error, info},
+    tokio::signal::unix::{signal, SignalKind},
+};
+
+pub(crate) struct Reload;
+
+impl Message for Reload {
+    type Result = ();
+}
+
+impl LetsEncrypt {
+    /// Re-reads every certificate and key from the store and serves them.
+    pub fn reload(&self) -> Result<(), NotRunning> {
+        self.actor()?.do_send(Reload);
+        Ok(())
+    }
+
+    pub(crate) fn start_sighup_reload(&self, ctx: &mut Context<Self>) {
+        if !self.config.reload_on_sighup {
+            return;
+        }
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                error!("could not handle SIGHUP: {}", DisplayChain(&e));
+                return;
+            }
+        };
+        let actor = ctx.address();
+        ctx.spawn(
+            async move {
+                while hangups.recv().await.is_some() {
+                    info!("SIGHUP received, reloading certificates");
+                    actor.do_send(Reload);
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Handler<Reload> for LetsEncrypt {
+    type Result = ();
+
+    fn handle(&mut self, _: Reload, ctx: &mut Self::Context) {
+        let present: Vec<_> = self
+            .config
+            .cert_builders
+            .iter()
+            .filter(|c| self.key_and_cert_present(c))
+            .cloned()
+            .collect();
+        for cert_builder in present {
+            self.install_in_background(cert_builder, ctx, |_, cert_builder, _| {
+                info!("{}: reloaded from the store", cert_builder.domains[0]);
+            });
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Optional process restarts after a certificate is issued.
+//!
+//! New certificates are hot-swapped into every listener, so none of this is
+//! needed unless something outside the server also loads them at startup.
+
+use {
+    crate::CertInfo,
+    actix_web::dev::Server,
+    log::{error, info, warn},
+    serde::{Deserialize, Serialize},
+    std::{env, os::unix::net::UnixDatagram, process::Command, sync::Arc},
+};
+
+type RestartCallback = Arc<dyn Fn(&CertInfo) + Send + Sync>;
+
+/// Run once a newly issued certificate has been installed and the
+/// `on_renewed` hooks have finished.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStrategy {
+    /// Keep running; the new certificate is already being served.
+    None,
+    /// Stop the actix `System`, leaving it to the caller to start again.
+    Stop,
+    /// Replace the process with a fresh copy of the current executable,
+    /// with the same arguments.  In-flight requests are dropped.
+    ReExec,
+    /// Tell systemd the service is stopping and stop the `System`, for
+    /// units with `Restart=always`.
+    Systemd,
+    /// Call a function, e.g. to restart some other service.
+    #[serde(skip)]
+    Callback(RestartCallback),
+}
+
+impl Default for RestartStrategy {
+    fn default() -> Self {
+        RestartStrategy::None
+    }
+}
+
+impl RestartStrategy {
+    pub fn callback<F: Fn(&CertInfo) + Send + Sync + 'static>(f: F) -> Self {
+        RestartStrategy::Callback(Arc::new(f))
+    }
+
+    pub(crate) fn is_none(&self) -> bool {
+        matches!(self, RestartStrategy::None)
+    }
+
+    /// Stopping strategies first stop `server`, if the enabler knows it,
+    /// gracefully: it stops accepting connections and waits for in-flight
+    /// requests for up to its shutdown timeout.
+    pub(crate) async fn restart(self, info: CertInfo, server: Option<Server>) {
+        let stopping = match self {
+            RestartStrategy::None | RestartStrategy::Callback(_) => false,
+            _ => true,
+        };
+        if let (true, Some(server)) = (stopping, server) {
+            info!("{}: issued, shutting the server down", info.domains[0]);
+            server.stop(true).await;
+        }
+        match self {
+            RestartStrategy::None => {}
+            RestartStrategy::Stop => {
+                info!("{}: issued, stopping the system", info.domains[0]);
+                actix::System::current().stop();
+            }
+            RestartStrategy::ReExec => re_exec(&info),
+            RestartStrategy::Systemd => {
+                info!(
+                    "{}: issued, stopping for systemd to restart us",
+                    info.domains[0]
+                );
+                sd_notify("STOPPING=1");
+                actix::System::current().stop();
+            }
+            RestartStrategy::Callback(f) => f(&info),
+        }
+    }
+}
+
+fn re_exec(info: &CertInfo) {
+    use std::os::unix::process::CommandExt;
+
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            error!(
+                "{}: can't find own executable to restart: {}",
+                info.domains[0], e
+            );
+            return;
+        }
+    };
+    info!(
+        "{}: issued, re-executing {}",
+        info.domains[0],
+        exe.display()
+    );
+    // exec only returns on failure.
+    let e = Command::new(&exe).args(env::args_os().skip(1)).exec();
+    error!(
+        "{}: could not re-execute {}: {}",
+        info.domains[0],
+        exe.display(),
+        e
+    );
+}
+
+// Best effort: only path-named sockets are supported, which is what
+// systemd uses unless told otherwise.
+fn sd_notify(state: &str) {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => {
+            warn!("NOTIFY_SOCKET isn't set, not running under systemd?");
+            return;
+        }
+    };
+    let result = UnixDatagram::unbound().and_then(|s| s.send_to(state.as_bytes(), &socket));
+    if let Err(e) = result {
+        warn!("could not notify systemd: {}", e);
+    }
+}
@@ -0,0 +1,133 @@
+//! Cron expressions restricting when renewal checks may run.
+
+use {
+    chrono::{DateTime, Utc},
+    cron::Schedule,
+    serde::{Deserialize, Serialize},
+    std::{convert::TryFrom, error, fmt, str::FromStr, time::Duration},
+};
+
+/// A cron expression, evaluated in UTC, giving the times at which a
+/// certificate may be checked and renewed.  The format has a leading
+/// seconds field, so `"0 0 3-4 * * *"` means on the hour at 03:00 and 04:00.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CheckSchedule {
+    expression: String,
+    schedule: Schedule,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleError {
+    expression: String,
+    message: String,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: not a valid cron expression: {}", self.expression, self.message)
+    }
+}
+
+impl error::Error for ScheduleError {}
+
+impl CheckSchedule {
+    pub fn new<E: AsRef<str>>(expression: E) -> Result<Self, ScheduleError> {
+        let expression = expression.as_ref().trim().to_string();
+        match Schedule::from_str(&expression) {
+            Ok(schedule) => Ok(CheckSchedule { expression, schedule }),
+            Err(e) => Err(ScheduleError {
+                expression,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.expression
+    }
+
+    /// How long from `now` until the first scheduled time that is at least
+    /// `min` away.  An expression that never fires again waits `min`.
+    pub(crate) fn delay_from(&self, now: DateTime<Utc>, min: Duration) -> Duration {
+        let earliest =
+            now + chrono::Duration::from_std(min).unwrap_or_else(|_| chrono::Duration::max_value());
+        self.schedule
+            .after(&earliest)
+            .next()
+            .and_then(|next| next.signed_duration_since(now).to_std().ok())
+            .unwrap_or(min)
+    }
+}
+
+impl fmt::Display for CheckSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl FromStr for CheckSchedule {
+    type Err = ScheduleError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Self::new(expression)
+    }
+}
+
+impl TryFrom<String> for CheckSchedule {
+    type Error = ScheduleError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        Self::new(expression)
+    }
+}
+
+impl From<CheckSchedule> for String {
+    fn from(schedule: CheckSchedule) -> String {
+        schedule.expression
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn hours(hours: u64) -> Duration {
+        Duration::from_secs(hours * 60 * 60)
+    }
+
+    #[test]
+    fn refuses_bad_expressions() {
+        assert!(CheckSchedule::new("0 0 3 * * *").is_ok());
+        assert!(CheckSchedule::new("every night").is_err());
+        // Seconds come first, so five fields are too few.
+        assert!(CheckSchedule::new("0 3 * * *").is_err());
+    }
+
+    #[test]
+    fn waits_for_the_next_scheduled_time() {
+        let schedule = CheckSchedule::new("0 0 3 * * *").unwrap();
+        let now = at("2021-01-01T01:00:00Z");
+        assert_eq!(schedule.delay_from(now, Duration::default()), hours(2));
+        assert_eq!(schedule.delay_from(now, hours(1)), hours(2));
+        assert_eq!(schedule.delay_from(now, hours(3)), hours(26));
+    }
+
+    #[test]
+    fn never_returns_the_current_time() {
+        let schedule = CheckSchedule::new("0 0 3 * * *").unwrap();
+        let now = at("2021-01-01T03:00:00Z");
+        assert_eq!(schedule.delay_from(now, Duration::default()), hours(24));
+    }
+
+    #[test]
+    fn waits_the_minimum_when_the_schedule_has_ended() {
+        let schedule = CheckSchedule::new("0 0 3 1 1 * 2000").unwrap();
+        let now = at("2021-01-01T00:00:00Z");
+        assert_eq!(schedule.delay_from(now, hours(5)), hours(5));
+    }
+}
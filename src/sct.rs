@@ -0,0 +1,140 @@
+//! Checking that a new certificate carries enough Signed Certificate
+//! Timestamps for CT-enforcing browsers before it is installed.
+//!
+//! Chrome and Safari want SCTs from at least two distinct logs for
+//! certificates valid up to 180 days, and three beyond that.  The SCTs'
+//! structure, timestamps and log IDs are checked; their signatures are
+//! not, since that needs the browsers' current log list.
+
+use {
+    crate::{Domain, Error},
+    chrono::{offset::TimeZone, Utc},
+    log::{debug, error},
+    openssl::x509::X509,
+};
+
+// The DER encoded OID 1.3.6.1.4.1.11129.2.4.2, embedded SCT list.
+const SCT_LIST_OID: &[u8] = &[
+    0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02,
+];
+
+const LOG_ID_LEN: usize = 32;
+
+// Lifetimes (in days) above which one more SCT is wanted.
+const THREE_SCTS_ABOVE_DAYS: i32 = 180;
+
+struct Sct {
+    log_id: [u8; LOG_ID_LEN],
+    timestamp_ms: u64,
+}
+
+// A cursor over DER or TLS encoded bytes; every read fails rather than
+// panicking on truncated input.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn uint(&mut self, n: usize) -> Option<u64> {
+        Some(
+            self.take(n)?
+                .iter()
+                .fold(0, |acc, b| acc << 8 | u64::from(*b)),
+        )
+    }
+
+    // A TLS vector with an n byte length prefix.
+    fn vector(&mut self, n: usize) -> Option<Reader<'a>> {
+        let len = self.uint(n)? as usize;
+        Some(Reader(self.take(len)?))
+    }
+
+    // The contents of a DER element with the given tag.
+    fn der(&mut self, tag: u8) -> Option<Reader<'a>> {
+        if self.uint(1)? != u64::from(tag) {
+            return None;
+        }
+        let first = self.uint(1)? as usize;
+        let len = if first < 0x80 {
+            first
+        } else {
+            self.uint(first & 0x7f)? as usize
+        };
+        Some(Reader(self.take(len)?))
+    }
+}
+
+fn embedded_scts(der: &[u8]) -> Option<Vec<Sct>> {
+    let at = der
+        .windows(SCT_LIST_OID.len())
+        .position(|w| w == SCT_LIST_OID)?;
+    let mut extension = Reader(&der[at + SCT_LIST_OID.len()..]);
+    // An optional `critical` BOOLEAN precedes the value.
+    if extension.0.first() == Some(&0x01) {
+        extension.der(0x01)?;
+    }
+    let mut value = extension.der(0x04)?.der(0x04)?;
+    let mut list = value.vector(2)?;
+    let mut scts = Vec::new();
+    while !list.0.is_empty() {
+        let mut sct = list.vector(2)?;
+        if sct.uint(1)? != 0 {
+            // Only v1 SCTs exist.
+            return None;
+        }
+        let mut log_id = [0; LOG_ID_LEN];
+        log_id.copy_from_slice(sct.take(LOG_ID_LEN)?);
+        let timestamp_ms = sct.uint(8)?;
+        sct.vector(2)?; // extensions
+        sct.take(2)?; // hash and signature algorithms
+        sct.vector(2)?; // signature
+        scts.push(Sct {
+            log_id,
+            timestamp_ms,
+        });
+    }
+    Some(scts)
+}
+
+/// Fails when `cert` would be rejected for too few SCTs.
+pub(crate) fn check(domain: &Domain, cert: &X509) -> Result<(), Error> {
+    let refuse = |reason: String| {
+        error!("{}: not installing the new certificate: {}", domain, reason);
+        Err(Error::CtPolicy(domain.clone(), reason))
+    };
+    let scts = match embedded_scts(&cert.to_der()?) {
+        Some(scts) => scts,
+        None => return refuse("no readable embedded SCT list".to_string()),
+    };
+    let lifetime = cert.not_before().diff(cert.not_after())?;
+    let required = if lifetime.days > THREE_SCTS_ABOVE_DAYS {
+        3
+    } else {
+        2
+    };
+
+    let now_ms = Utc::now().timestamp_millis() as u64;
+    if let Some(future) = scts.iter().find(|sct| sct.timestamp_ms > now_ms) {
+        let at = Utc.timestamp_millis(future.timestamp_ms as i64);
+        return refuse(format!("SCT timestamped in the future, {}", at));
+    }
+    let mut logs: Vec<&[u8; LOG_ID_LEN]> = scts.iter().map(|sct| &sct.log_id).collect();
+    logs.sort();
+    logs.dedup();
+    if logs.len() < required {
+        return refuse(format!(
+            "SCTs from {} distinct logs, {} are required",
+            logs.len(),
+            required
+        ));
+    }
+    debug!("{}: certificate has SCTs from {} logs", domain, logs.len());
+    Ok(())
+}
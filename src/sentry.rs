@@ -0,0 +1,41 @@
+//! Reporting failed issuance to Sentry (feature `sentry`).
+//!
+//! Events go to the current hub, so the application's own `sentry::init`
+//! decides the DSN, environment and release; nothing is sent before then.
+
+use {
+    crate::Event,
+    sentry_core::protocol::{self, Level},
+    std::borrow::Cow,
+};
+
+pub(crate) fn report(event: &Event) {
+    let (attempt, error) = match event {
+        Event::Failed { attempt, error, .. } => (attempt, error),
+        _ => return,
+    };
+    let domain = event.domain().to_string();
+    let mut report = protocol::Event {
+        message: Some(event.summary()),
+        level: Level::Error,
+        logger: Some("actix_web_lets_encrypt".to_string()),
+        ..Default::default()
+    };
+    report.tags.insert("domain".to_string(), domain.clone());
+    report
+        .extra
+        .insert("attempt".to_string(), (*attempt).into());
+    report
+        .extra
+        .insert("error".to_string(), error.clone().into());
+    report.extra.insert(
+        "domains".to_string(),
+        serde_json::to_value(&event.info().domains).unwrap_or_default(),
+    );
+    // One issue per certificate rather than one per distinct error text.
+    report.fingerprint = Cow::Owned(vec![
+        Cow::Borrowed("lets-encrypt-renewal-failed"),
+        Cow::Owned(domain),
+    ]);
+    sentry_core::capture_event(report);
+}
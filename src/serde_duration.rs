@@ -0,0 +1,59 @@
+//! Serde helpers that represent a `Duration` as a human-friendly string such
+//! as `"30d"`, `"12h"` or `"1h 30m"`, rather than serde's default
+//! `{"secs": .., "nanos": ..}` structure.
+//!
+//! A plain number of seconds and the structured form are still accepted
+//! when deserializing so that existing configurations keep working.
+
+use {
+    serde::{de::Error, Deserialize, Deserializer, Serializer},
+    std::time::Duration,
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Repr {
+    Human(String),
+    Secs(u64),
+    Struct { secs: u64, nanos: u32 },
+}
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(match Repr::deserialize(deserializer)? {
+        Repr::Human(s) => humantime::parse_duration(&s)
+            .map_err(|e| D::Error::custom(format!("{:?}: {}", s, e)))?,
+        Repr::Secs(secs) => Duration::from_secs(secs),
+        Repr::Struct { secs, nanos } => Duration::new(secs, nanos),
+    })
+}
+
+/// The same representation for an optional `Duration`.
+pub mod option {
+    use {
+        serde::{Deserialize, Deserializer, Serializer},
+        std::time::Duration,
+    };
+
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(with = "super")] Duration);
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(d)| d))
+    }
+}
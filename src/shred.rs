@@ -0,0 +1,162 @@
+//! Disposing of private keys that are no longer used: replaced by a new
+//! key, retired, or evicted.
+//!
+//! Overwriting is best effort.  Copy-on-write and journaling filesystems,
+//! snapshots and SSD wear levelling can all keep the old blocks around, so
+//! for stronger guarantees keep keys on an encrypted volume or wrap them
+//! (see `KeyWrap`).
+
+use {
+    openssl::rand::rand_bytes,
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{self, File, OpenOptions},
+        io::{self, Seek, SeekFrom, Write},
+        path::Path,
+    },
+};
+
+const CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyDeletion {
+    /// Only unlink the file.
+    Unlink,
+    /// Overwrite the file with random bytes, syncing after each of
+    /// `passes`, then unlink it.
+    Overwrite { passes: u32 },
+}
+
+impl Default for KeyDeletion {
+    fn default() -> Self {
+        KeyDeletion::Overwrite { passes: 1 }
+    }
+}
+
+fn open(path: &Path) -> io::Result<Option<File>> {
+    match OpenOptions::new().write(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn overwrite(file: &mut File, passes: u32) -> io::Result<()> {
+    let len = file.metadata()?.len() as usize;
+    let mut noise = vec![0; len.min(CHUNK)];
+    for _ in 0..passes {
+        file.seek(SeekFrom::Start(0))?;
+        let mut left = len;
+        while left > 0 {
+            let chunk = &mut noise[..left.min(CHUNK)];
+            rand_bytes(chunk)?;
+            file.write_all(chunk)?;
+            left -= chunk.len();
+        }
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_symlink())
+}
+
+impl KeyDeletion {
+    /// Deletes the key file at `path`; a missing file is fine.  A symlink
+    /// is only unlinked, leaving the file it points to alone.
+    pub(crate) fn delete(self, path: &Path) -> io::Result<()> {
+        if let (KeyDeletion::Overwrite { passes }, false) = (self, is_symlink(path)) {
+            match open(path)? {
+                Some(mut file) => overwrite(&mut file, passes)?,
+                None => return Ok(()),
+            }
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Renames `new` over the key file at `path`, then deletes the old key.
+    /// It is kept open across the rename and overwritten only once `path`
+    /// names the new key, so a reader never finds the path missing or
+    /// holding noise.  As with `delete`, a symlink is only replaced, the
+    /// rename swapping the link itself, and the file it points to is left
+    /// alone.
+    pub(crate) fn replace(self, new: &Path, path: &Path) -> io::Result<()> {
+        let old = match (self, is_symlink(path)) {
+            (KeyDeletion::Overwrite { .. }, false) => open(path)?,
+            _ => None,
+        };
+        fs::rename(new, path)?;
+        if let (KeyDeletion::Overwrite { passes }, Some(mut old)) = (self, old) {
+            overwrite(&mut old, passes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{env, os::unix::fs::symlink, path::PathBuf, process},
+    };
+
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lets-encrypt-shred-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn replace_overwrites_the_old_key() {
+        let dir = scratch_dir("replace");
+        let (key, new) = (dir.join("privkey.pem"), dir.join("privkey.pem.new"));
+        fs::write(&key, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+        let old = File::open(&key).unwrap();
+        KeyDeletion::Overwrite { passes: 1 }
+            .replace(&new, &key)
+            .unwrap();
+        assert_eq!(fs::read(&key).unwrap(), b"new");
+        assert!(!new.exists());
+        let mut noise = Vec::new();
+        io::Read::read_to_end(&mut &old, &mut noise).unwrap();
+        assert_eq!(noise.len(), 3);
+        assert_ne!(noise, b"old");
+    }
+
+    #[test]
+    fn replace_leaves_a_symlinked_key_alone() {
+        let dir = scratch_dir("replace-symlink");
+        let (target, key, new) = (
+            dir.join("privkey1.pem"),
+            dir.join("privkey.pem"),
+            dir.join("privkey.pem.new"),
+        );
+        fs::write(&target, "old").unwrap();
+        symlink(&target, &key).unwrap();
+        fs::write(&new, "new").unwrap();
+        KeyDeletion::Overwrite { passes: 1 }
+            .replace(&new, &key)
+            .unwrap();
+        assert!(!is_symlink(&key));
+        assert_eq!(fs::read(&key).unwrap(), b"new");
+        assert_eq!(fs::read(&target).unwrap(), b"old");
+    }
+
+    #[test]
+    fn delete_leaves_a_symlinked_key_alone() {
+        let dir = scratch_dir("delete-symlink");
+        let (target, key) = (dir.join("privkey1.pem"), dir.join("privkey.pem"));
+        fs::write(&target, "old").unwrap();
+        symlink(&target, &key).unwrap();
+        KeyDeletion::Overwrite { passes: 1 }.delete(&key).unwrap();
+        assert!(fs::symlink_metadata(&key).is_err());
+        assert_eq!(fs::read(&target).unwrap(), b"old");
+    }
+}
@@ -0,0 +1,178 @@
+//! Picks the certificate to present from the server name the client sends.
+//!
+//! Every listener bound by `attach_certificates_to` consults the same
+//! resolver, so a certificate added to it is served on all of them without
+//! rebinding anything.
+//!
+//! For a server name such as `api.example.com` the first of these wins:
+//!
+//! 1. a certificate listing `api.example.com` itself,
+//! 2. one listing `*.example.com`, since a wildcard covers exactly one
+//!    label: `a.api.example.com` needs `*.api.example.com`, and a name of
+//!    two labels such as `example.com` has no wildcard at all,
+//! 3. whatever on-demand issuance provides, if it's enabled,
+//! 4. the listener's default certificate, as for clients without SNI.
+
+use {
+    crate::Domain,
+    openssl::ssl::{NameType, SniError, SslAcceptorBuilder, SslAlert, SslContext, SslRef},
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, RwLock},
+        time::Instant,
+    },
+};
+
+// Called with a server name nothing has been inserted for; whatever it
+// returns is inserted and served.
+pub(crate) type MissHandler = Arc<dyn Fn(&Domain) -> Option<SslContext> + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct CertResolver {
+    contexts: Arc<RwLock<HashMap<Domain, SslContext>>>,
+    on_miss: Arc<RwLock<Option<MissHandler>>>,
+    // When each name was last resolved by a handshake.
+    last_used: Arc<Mutex<HashMap<Domain, Instant>>>,
+}
+
+impl CertResolver {
+    pub(crate) fn insert(&self, domains: &[Domain], context: SslContext) {
+        let mut contexts = self.contexts.write().unwrap();
+        for domain in domains {
+            contexts.insert(domain.clone(), context.clone());
+        }
+    }
+
+    pub(crate) fn remove(&self, domains: &[Domain]) {
+        let mut contexts = self.contexts.write().unwrap();
+        let mut last_used = self.last_used.lock().unwrap();
+        for domain in domains {
+            contexts.remove(domain);
+            last_used.remove(domain);
+        }
+    }
+
+    pub(crate) fn last_used(&self, domain: &Domain) -> Option<Instant> {
+        self.last_used.lock().unwrap().get(domain).copied()
+    }
+
+    fn touch(&self, domain: &Domain) {
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(domain.clone(), Instant::now());
+    }
+
+    pub(crate) fn set_miss_handler(&self, handler: MissHandler) {
+        *self.on_miss.write().unwrap() = Some(handler);
+    }
+
+    pub(crate) fn lookup(&self, server_name: &str) -> Option<SslContext> {
+        let domain = Domain::new(server_name).ok()?;
+        if let Some(context) = self.get(&domain) {
+            self.touch(&domain);
+            return Some(context);
+        }
+        if let Some(wildcard) = domain.wildcard_parent() {
+            if let Some(context) = self.get(&wildcard) {
+                self.touch(&wildcard);
+                return Some(context);
+            }
+        }
+        let handler = self.on_miss.read().unwrap().clone()?;
+        let context = handler(&domain)?;
+        self.touch(&domain);
+        self.insert(&[domain], context.clone());
+        Some(context)
+    }
+
+    pub(crate) fn get(&self, domain: &Domain) -> Option<SslContext> {
+        self.contexts.read().unwrap().get(domain).cloned()
+    }
+
+    fn resolve(&self, server_name: Option<&str>, default: &Domain) -> Option<SslContext> {
+        server_name
+            .and_then(|name| self.lookup(name))
+            .or_else(|| self.get(default))
+    }
+
+    /// Makes `builder` switch to the resolved certificate during the
+    /// handshake.  Clients that don't send SNI, or ask for a name we don't
+    /// manage, get the current certificate for `default`, so a renewed
+    /// certificate reaches them too once it has been inserted.
+    pub(crate) fn attach_to(&self, builder: &mut SslAcceptorBuilder, default: &Domain) {
+        let resolver = self.clone();
+        let default = default.clone();
+        builder.set_servername_callback(move |ssl: &mut SslRef, _alert: &mut SslAlert| {
+            let context = resolver.resolve(ssl.servername(NameType::HOST_NAME), &default);
+            if let Some(context) = context {
+                ssl.set_ssl_context(&context)
+                    .map_err(|_| SniError::ALERT_FATAL)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        openssl::{
+            ex_data::Index,
+            ssl::{SslContext, SslMethod},
+        },
+    };
+
+    // Contexts that tell which certificate they stand for.
+    fn context(index: Index<SslContext, &'static str>, name: &'static str) -> SslContext {
+        let mut builder = SslContext::builder(SslMethod::tls()).unwrap();
+        builder.set_ex_data(index, name);
+        builder.build()
+    }
+
+    fn resolver() -> (CertResolver, Index<SslContext, &'static str>) {
+        let index = SslContext::new_ex_index().unwrap();
+        let resolver = CertResolver::default();
+        for name in &["example.com", "*.example.com", "api.example.com"] {
+            let domain = Domain::new(name).unwrap();
+            resolver.insert(&[domain], context(index, *name));
+        }
+        (resolver, index)
+    }
+
+    fn served(
+        context: Option<SslContext>,
+        index: Index<SslContext, &'static str>,
+    ) -> Option<&'static str> {
+        context.map(|context| *context.ex_data(index).unwrap())
+    }
+
+    #[test]
+    fn exact_name_beats_wildcard() {
+        let (resolver, index) = resolver();
+        let lookup = |name| served(resolver.lookup(name), index);
+        assert_eq!(lookup("api.example.com"), Some("api.example.com"));
+        assert_eq!(lookup("www.example.com"), Some("*.example.com"));
+        assert_eq!(lookup("example.com"), Some("example.com"));
+    }
+
+    #[test]
+    fn wildcard_covers_one_label() {
+        let (resolver, index) = resolver();
+        assert_eq!(served(resolver.lookup("a.api.example.com"), index), None);
+        assert_eq!(served(resolver.lookup("a.www.example.com"), index), None);
+        assert_eq!(served(resolver.lookup("example.org"), index), None);
+    }
+
+    #[test]
+    fn default_certificate_without_a_match() {
+        let (resolver, index) = resolver();
+        let default = Domain::new("example.com").unwrap();
+        let resolve = |name| served(resolver.resolve(name, &default), index);
+        assert_eq!(resolve(None), Some("example.com"));
+        assert_eq!(resolve(Some("unknown.org")), Some("example.com"));
+        assert_eq!(resolve(Some("not a name")), Some("example.com"));
+        assert_eq!(resolve(Some("www.example.com")), Some("*.example.com"));
+    }
+}
@@ -0,0 +1,90 @@
+//! Listeners passed in by systemd socket activation.
+//!
+//! With a `.socket` unit, systemd binds the ports itself and hands the
+//! sockets over (`LISTEN_FDS`), so the service needs no privileges to use
+//! ports 80 and 443, and connections queue up while it restarts.  Each HTTPS
+//! listener goes to the `CertBuilder` whose first domain is the socket's
+//! `FileDescriptorName=`, or whose address it is bound to; the HTTP one is
+//! named `http`, or bound to port 80, and is taken with
+//! `LetsEncrypt::activated_http_listener`.
+
+use {
+    crate::LetsEncrypt,
+    std::{
+        env,
+        net::{SocketAddr, TcpListener},
+        os::unix::io::FromRawFd,
+        process,
+    },
+};
+
+const LISTEN_FDS_START: i32 = 3;
+
+pub(crate) struct Activated {
+    name: Option<String>,
+    local_addr: Option<SocketAddr>,
+    listener: TcpListener,
+}
+
+// The sockets systemd passed to this process, taken once: the variables
+// are cleared so that children don't think the sockets are theirs.
+pub(crate) fn take() -> Vec<Activated> {
+    let ours = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .map_or(false, |pid| pid == process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    if !ours {
+        return Vec::new();
+    }
+    let mut names = names.split(':');
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            // systemd names sockets without FileDescriptorName= "unknown".
+            let name = names
+                .next()
+                .filter(|name| !name.is_empty() && *name != "unknown")
+                .map(str::to_string);
+            Activated {
+                name,
+                local_addr: listener.local_addr().ok(),
+                listener,
+            }
+        })
+        .collect()
+}
+
+impl LetsEncrypt {
+    pub(crate) fn take_activated<F>(&self, matches: F) -> Option<TcpListener>
+    where
+        F: Fn(Option<&str>, Option<SocketAddr>) -> bool,
+    {
+        let mut activated = self.shared.activated.lock().unwrap();
+        let activated = activated.get_or_insert_with(take);
+        let index = activated
+            .iter()
+            .position(|a| matches(a.name.as_deref(), a.local_addr))?;
+        Some(activated.remove(index).listener)
+    }
+
+    /// The plain HTTP listener systemd passed in, if any: the socket named
+    /// `http`, or else one bound to port 80.  Hand it to
+    /// `HttpServer::listen` in place of binding port 80.
+    pub fn activated_http_listener(&self) -> Option<TcpListener> {
+        self.take_activated(|name, _| name == Some("http"))
+            .or_else(|| {
+                self.take_activated(|name, addr| {
+                    name.is_none() && addr.map_or(false, |addr| addr.port() == 80)
+                })
+            })
+    }
+}
@@ -0,0 +1,140 @@
+//! Per-certificate status, as reported by the health endpoint.
+
+use {
+    crate::{error::DisplayChain, Domain, Error},
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    },
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CertStatus {
+    pub domains: Vec<Domain>,
+    pub not_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_check: Option<DateTime<Utc>>,
+    pub last_renewal: Option<RenewalResult>,
+    /// The most recent failure, kept after later renewals succeed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<LastError>,
+    pub consecutive_failures: u32,
+    /// Renewal has failed at least `escalate_after` times in a row.
+    pub critical: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RenewalResult {
+    pub at: DateTime<Utc>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LastError {
+    pub at: DateTime<Utc>,
+    /// The error and its causes, as in the logs.
+    pub error: String,
+    /// The ACME problem document, e.g. `{"type":
+    /// "urn:acme:error:unauthorized", "detail": "..."}`, when the CA
+    /// rejected the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct StatusBoard {
+    certs: Arc<Mutex<BTreeMap<Domain, CertStatus>>>,
+}
+
+impl StatusBoard {
+    fn with_cert<F: FnOnce(&mut CertStatus)>(&self, domains: &[Domain], f: F) {
+        let mut certs = self.certs.lock().unwrap();
+        let status = certs.entry(domains[0].clone()).or_insert_with(|| CertStatus {
+            domains: domains.to_vec(),
+            not_after: None,
+            issuer: None,
+            next_check: None,
+            last_renewal: None,
+            last_error: None,
+            consecutive_failures: 0,
+            critical: false,
+        });
+        f(status);
+    }
+
+    pub(crate) fn set_expiry(&self, domains: &[Domain], not_after: Option<DateTime<Utc>>) {
+        self.with_cert(domains, |status| status.not_after = not_after);
+    }
+
+    pub(crate) fn set_issuer(&self, domains: &[Domain], issuer: Option<String>) {
+        self.with_cert(domains, |status| status.issuer = issuer);
+    }
+
+    pub(crate) fn set_next_check(&self, domains: &[Domain], next_check: Option<DateTime<Utc>>) {
+        self.with_cert(domains, |status| status.next_check = next_check);
+    }
+
+    pub(crate) fn record_renewal(
+        &self,
+        domains: &[Domain],
+        error: Option<&Error>,
+        at: DateTime<Utc>,
+    ) {
+        let message = error.map(|e| DisplayChain(e).to_string());
+        self.with_cert(domains, |status| {
+            if let Some(e) = error {
+                status.consecutive_failures += 1;
+                status.last_error = Some(LastError {
+                    at,
+                    error: message.clone().unwrap_or_default(),
+                    problem: e.problem().cloned(),
+                });
+            } else {
+                status.consecutive_failures = 0;
+                status.critical = false;
+            }
+            status.last_renewal = Some(RenewalResult {
+                at,
+                success: error.is_none(),
+                error: message,
+            })
+        });
+    }
+
+    /// The status of the certificate covering `domain`.
+    pub(crate) fn get(&self, domain: &Domain) -> Option<CertStatus> {
+        self.certs
+            .lock()
+            .unwrap()
+            .values()
+            .find(|status| status.domains.contains(domain))
+            .cloned()
+    }
+
+    pub(crate) fn set_critical(&self, domains: &[Domain]) {
+        self.with_cert(domains, |status| status.critical = true);
+    }
+
+    pub(crate) fn remove(&self, domain: &Domain) {
+        self.certs.lock().unwrap().remove(domain);
+    }
+
+    pub(crate) fn all(&self) -> Vec<CertStatus> {
+        self.certs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Healthy when every certificate is present and unexpired at `now`.
+    pub(crate) fn healthy(&self, now: DateTime<Utc>) -> bool {
+        self.certs
+            .lock()
+            .unwrap()
+            .values()
+            .all(|status| status.not_after.map_or(false, |not_after| not_after > now))
+    }
+}
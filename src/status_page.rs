@@ -0,0 +1,139 @@
+//! An HTML page summarizing the managed certificates, for deployments
+//! without a metrics stack.  It is protected by HTTP basic auth so that it
+//! can be opened straight from a browser.
+
+use {
+    crate::{credentials::Secret, status::StatusBoard, CertStatus, LetsEncrypt},
+    actix_web::{http::header, web, HttpRequest, HttpResponse},
+    chrono::{DateTime, Utc},
+    openssl::{base64, memcmp},
+    serde::{Deserialize, Serialize},
+    std::fmt::Write,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatusPage {
+    #[serde(default = "StatusPage::default_path")]
+    path: String,
+    username: String,
+    password: Secret,
+}
+
+impl StatusPage {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        StatusPage {
+            path: Self::default_path(),
+            username: username.into(),
+            password: Secret::from(password.into()),
+        }
+    }
+
+    fn default_path() -> String {
+        "/lets-encrypt/status".to_string()
+    }
+
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    pub(crate) fn route_path(&self) -> &str {
+        &self.path
+    }
+
+    fn authorized(&self, req: &HttpRequest) -> bool {
+        let expected = format!("{}:{}", self.username, self.password.expose());
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| base64::decode_block(encoded.trim()).ok())
+            .map_or(false, |presented| {
+                presented.len() == expected.len() && memcmp::eq(&presented, expected.as_bytes())
+            })
+    }
+}
+
+pub(crate) async fn page(
+    req: HttpRequest,
+    page: web::Data<StatusPage>,
+    status: web::Data<StatusBoard>,
+    enabler: web::Data<LetsEncrypt>,
+) -> HttpResponse {
+    if !page.authorized(&req) {
+        return HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Basic realm=\"certificates\"")
+            .finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render(&status.all(), enabler.now()))
+}
+
+fn render(certs: &[CertStatus], now: DateTime<Utc>) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Certificates</title>\
+         <style>body{font-family:sans-serif}td,th{padding:4px 12px;text-align:left}\
+         .bad{color:#b00}</style></head><body>\n<h1>Certificates</h1>\n<table>\n\
+         <tr><th>Domains</th><th>Issuer</th><th>Expires</th><th>Last renewal</th>\
+         <th>Next check</th></tr>\n",
+    );
+    for cert in certs {
+        let domains: Vec<&str> = cert.domains.iter().map(|d| d.as_str()).collect();
+        let expired = cert.not_after.map_or(true, |not_after| not_after <= now);
+        let last_renewal = match &cert.last_renewal {
+            None => "-".to_string(),
+            Some(r) if r.success => format!("ok, {}", countdown(r.at, now)),
+            Some(r) => format!(
+                "failed {}: {}",
+                countdown(r.at, now),
+                r.error.as_deref().unwrap_or("")
+            ),
+        };
+        writeln!(
+            out,
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            if expired || cert.critical {
+                " class=\"bad\""
+            } else {
+                ""
+            },
+            escape(&domains.join(", ")),
+            escape(cert.issuer.as_deref().unwrap_or("-")),
+            cert.not_after
+                .map_or_else(|| "missing".to_string(), |at| countdown(at, now)),
+            escape(&last_renewal),
+            cert.next_check
+                .map_or_else(|| "-".to_string(), |at| countdown(at, now)),
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n</body></html>\n");
+    out
+}
+
+// "in 3 days" or "5 hours ago", to the largest whole unit.
+fn countdown(at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let delta = at.signed_duration_since(now);
+    let secs = delta.num_seconds().abs();
+    let (n, unit) = if secs >= 86400 {
+        (secs / 86400, "day")
+    } else if secs >= 3600 {
+        (secs / 3600, "hour")
+    } else {
+        (secs / 60, "minute")
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    if delta.num_seconds() >= 0 {
+        format!("in {} {}{}", n, unit, plural)
+    } else {
+        format!("{} {}{} ago", n, unit, plural)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,66 @@
+//! Keeping the certificates of a platform's customers apart.
+//!
+//! A `CertBuilder` given a tenant keeps its files under
+//! `tenants/<name>/` in the ssl directory, registers with the CA using the
+//! tenant's email, and counts towards the tenant's order limit.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Tenant {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// Orders allowed in any 24 hours, counted from the renewal journal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    orders_per_day: Option<u32>,
+}
+
+impl Tenant {
+    /// `name` becomes a directory name, so only ASCII letters, digits, `-`
+    /// and `_` are allowed.
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        let name = name.into();
+        assert_valid_name(&name);
+        Tenant {
+            name,
+            email: None,
+            orders_per_day: None,
+        }
+    }
+
+    pub fn email<E: AsRef<str>>(mut self, email: E) -> Self {
+        self.email = Some(email.as_ref().to_string());
+        self
+    }
+
+    pub fn orders_per_day(mut self, orders: u32) -> Self {
+        self.orders_per_day = Some(orders);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn email_address(&self) -> Option<&String> {
+        self.email.as_ref()
+    }
+
+    pub(crate) fn order_limit(&self) -> Option<u32> {
+        self.orders_per_day
+    }
+}
+
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+pub(crate) fn assert_valid_name(name: &str) {
+    if !valid_name(name) {
+        panic!("{}: not a valid tenant name", name);
+    }
+}
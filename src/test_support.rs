@@ -0,0 +1,532 @@
+//! An in-process mock ACME directory, for testing issuance without network
+//! access.
+//!
+//! `MockAcme::start` serves the ACME protocol acme-client speaks on a
+//! loopback port, from a thread and actix system of its own so blocking
+//! calls into the enabler can't starve it.  Every account is accepted,
+//! every challenge is valid as soon as it is answered, and certificates
+//! are signed by a throwaway CA, also served as their intermediate.
+//! `MockAcme::enabler` starts an enabler ordering from it:
+//!
+//! ```ignore
+//! let acme = MockAcme::start()?;
+//! let enabler = acme
+//!     .enabler()
+//!     .nonce_directory(dir.join("nonce"))
+//!     .ssl_directory(dir.join("ssl"))
+//!     .add_cert(CertBuilder::new("127.0.0.1:0", &["example.com"]).email("a@example.com"));
+//! enabler.offline().issue(None, true)?;
+//! assert_eq!(acme.issued().len(), 1);
+//! ```
+//!
+//! Request signatures aren't checked, and a certificate covers the
+//! domains whose challenges were answered since the previous one was
+//! signed, so the orders must run one at a time.  `fail_challenges` makes
+//! the next challenges fail instead, as if the CA couldn't reach the
+//! server, and `stall_certificates` holds up the next certificates.
+//!
+//! A `FakeClock` given to `LetsEncrypt::clock` then moves time forward to
+//! when a certificate is due:
+//!
+//! ```ignore
+//! let clock = FakeClock::new();
+//! let enabler = enabler.clock(clock.clone());
+//! clock.advance(Duration::from_secs(61 * 24 * 60 * 60));
+//! assert_eq!(enabler.offline().issue(None, false)?.len(), 1);
+//! ```
+//!
+//! Enabled by the `test-support` feature.
+
+use {
+    crate::{Clock, Error, LetsEncrypt},
+    actix_web::{
+        dev::{HttpResponseBuilder, Server},
+        http::StatusCode,
+        rt::{time::delay_for, System},
+        web, App, HttpResponse, HttpServer,
+    },
+    chrono::{DateTime, Utc},
+    openssl::{
+        asn1::{Asn1Integer, Asn1Time},
+        base64,
+        bn::{BigNum, MsbOption},
+        ec::{EcGroup, EcKey},
+        error::ErrorStack,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        rand::rand_bytes,
+        x509::{
+            extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
+            X509NameBuilder, X509Req, X509,
+        },
+    },
+    serde_json::{json, Value},
+    std::{
+        collections::HashMap,
+        io,
+        net::{Ipv4Addr, TcpListener},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    },
+};
+
+const CERT_DAYS: u32 = 90;
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    // Challenge ids, and the domain, token and status of each.
+    challenges: HashMap<u64, (String, String, &'static str)>,
+    // How many of the next challenges answered fail.
+    failing: usize,
+    // How many of the next certificates are signed late, and how late.
+    stalling: usize,
+    stall: Duration,
+    // Domains answered for since the last certificate was signed.
+    answered: Vec<String>,
+    issued: Vec<X509>,
+    revoked: Vec<X509>,
+}
+
+struct Mock {
+    base: String,
+    ca_key: PKey<Private>,
+    ca: X509,
+    state: Mutex<State>,
+}
+
+/// A running mock ACME directory; stopped when dropped.
+pub struct MockAcme {
+    mock: web::Data<Mock>,
+    server: Server,
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_block(bytes)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn from_base64url(encoded: &str) -> Option<Vec<u8>> {
+    let mut standard = encoded.replace('-', "+").replace('_', "/");
+    while standard.len() % 4 != 0 {
+        standard.push('=');
+    }
+    base64::decode_block(&standard).ok()
+}
+
+fn random_token() -> String {
+    let mut bytes = [0; 24];
+    rand_bytes(&mut bytes).unwrap();
+    base64url(&bytes)
+}
+
+fn serial() -> Result<Asn1Integer, ErrorStack> {
+    let mut serial = BigNum::new()?;
+    serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+    serial.to_asn1_integer()
+}
+
+fn generate_ca() -> Result<(PKey<Private>, X509), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "Mock ACME CA")?;
+    let name = name.build();
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(3650)?)?;
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .key_cert_sign()
+            .crl_sign()
+            .build()?,
+    )?;
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok((key, builder.build()))
+}
+
+// Every response carries a fresh nonce, as a real directory's do.
+fn respond(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.header("Replay-Nonce", random_token());
+    response
+}
+
+fn malformed(detail: &str) -> HttpResponse {
+    respond(StatusCode::BAD_REQUEST).json(json!({
+        "type": "urn:acme:error:malformed",
+        "detail": detail,
+    }))
+}
+
+// The payload of a JWS, whose signature isn't checked.
+fn payload(body: &[u8]) -> Option<Value> {
+    let jws: Value = serde_json::from_slice(body).ok()?;
+    let payload = from_base64url(jws["payload"].as_str()?)?;
+    serde_json::from_slice(&payload).ok()
+}
+
+impl Mock {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    fn sign(&self, req: &X509Req, domains: &[String]) -> Result<X509, ErrorStack> {
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&serial()?)?;
+        builder.set_subject_name(req.subject_name())?;
+        builder.set_issuer_name(self.ca.subject_name())?;
+        builder.set_pubkey(&req.public_key()?)?;
+        builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&Asn1Time::days_from_now(CERT_DAYS)?)?;
+        let mut names = SubjectAlternativeName::new();
+        for domain in domains {
+            names.dns(domain);
+        }
+        let names = names.build(&builder.x509v3_context(Some(&self.ca), None))?;
+        builder.append_extension(names)?;
+        builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+        builder.sign(&self.ca_key, MessageDigest::sha256())?;
+        Ok(builder.build())
+    }
+}
+
+async fn directory(mock: web::Data<Mock>) -> HttpResponse {
+    respond(StatusCode::OK).json(json!({
+        "new-nonce": mock.url("/acme/new-nonce"),
+        "new-reg": mock.url("/acme/new-reg"),
+        "new-authz": mock.url("/acme/new-authz"),
+        "new-cert": mock.url("/acme/new-cert"),
+        "revoke-cert": mock.url("/acme/revoke-cert"),
+        "meta": { "terms-of-service": mock.url("/terms") },
+    }))
+}
+
+async fn new_nonce() -> HttpResponse {
+    respond(StatusCode::NO_CONTENT).finish()
+}
+
+async fn new_reg(mock: web::Data<Mock>, body: web::Bytes) -> HttpResponse {
+    let payload = match payload(&body) {
+        Some(payload) => payload,
+        None => return malformed("unreadable JWS"),
+    };
+    let id = {
+        let mut state = mock.state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    };
+    respond(StatusCode::CREATED)
+        .header("Location", mock.url(&format!("/acme/reg/{}", id)))
+        .json(json!({
+            "id": id,
+            "contact": payload["contact"],
+            "agreement": payload["agreement"],
+        }))
+}
+
+async fn new_authz(mock: web::Data<Mock>, body: web::Bytes) -> HttpResponse {
+    let domain =
+        match payload(&body).and_then(|p| p["identifier"]["value"].as_str().map(str::to_string)) {
+            Some(domain) => domain,
+            None => return malformed("no identifier"),
+        };
+    let token = random_token();
+    let id = {
+        let mut state = mock.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state
+            .challenges
+            .insert(id, (domain.clone(), token.clone(), "pending"));
+        id
+    };
+    respond(StatusCode::CREATED).json(json!({
+        "identifier": { "type": "dns", "value": domain },
+        "status": "pending",
+        "challenges": [{
+            "type": "http-01",
+            "status": "pending",
+            "uri": mock.url(&format!("/acme/challenge/{}", id)),
+            "token": token,
+        }],
+    }))
+}
+
+fn challenge_json(mock: &Mock, id: u64, token: &str, status: &str) -> Value {
+    let mut challenge = json!({
+        "type": "http-01",
+        "status": status,
+        "uri": mock.url(&format!("/acme/challenge/{}", id)),
+        "token": token,
+    });
+    if status == "invalid" {
+        challenge["error"] = json!({
+            "type": "urn:acme:error:connection",
+            "detail": "mock CA failed the challenge",
+        });
+    }
+    challenge
+}
+
+async fn answer_challenge(mock: web::Data<Mock>, id: web::Path<u64>) -> HttpResponse {
+    let id = id.into_inner();
+    let mut state = mock.state.lock().unwrap();
+    let status = if state.failing > 0 {
+        state.failing -= 1;
+        "invalid"
+    } else {
+        "valid"
+    };
+    let (domain, token) = match state.challenges.get_mut(&id) {
+        Some((domain, token, answered)) => {
+            *answered = status;
+            (domain.clone(), token.clone())
+        }
+        None => return respond(StatusCode::NOT_FOUND).finish(),
+    };
+    if status == "valid" && !state.answered.contains(&domain) {
+        state.answered.push(domain);
+    }
+    respond(StatusCode::ACCEPTED).json(challenge_json(&mock, id, &token, status))
+}
+
+async fn poll_challenge(mock: web::Data<Mock>, id: web::Path<u64>) -> HttpResponse {
+    let id = id.into_inner();
+    let state = mock.state.lock().unwrap();
+    match state.challenges.get(&id) {
+        Some((_, token, status)) => {
+            respond(StatusCode::ACCEPTED).json(challenge_json(&mock, id, token, status))
+        }
+        None => respond(StatusCode::NOT_FOUND).finish(),
+    }
+}
+
+async fn new_cert(mock: web::Data<Mock>, body: web::Bytes) -> HttpResponse {
+    let req = match payload(&body)
+        .and_then(|p| from_base64url(p["csr"].as_str()?))
+        .and_then(|der| X509Req::from_der(&der).ok())
+    {
+        Some(req) => req,
+        None => return malformed("unreadable CSR"),
+    };
+    let stall = {
+        let mut state = mock.state.lock().unwrap();
+        if state.stalling > 0 {
+            state.stalling -= 1;
+            Some(state.stall)
+        } else {
+            None
+        }
+    };
+    if let Some(stall) = stall {
+        delay_for(stall).await;
+    }
+    let mut state = mock.state.lock().unwrap();
+    let mut domains: Vec<String> = req
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+        .collect();
+    for domain in state.answered.drain(..) {
+        if !domains.contains(&domain) {
+            domains.push(domain);
+        }
+    }
+    let cert = match mock.sign(&req, &domains) {
+        Ok(cert) => cert,
+        Err(e) => return malformed(&e.to_string()),
+    };
+    let der = match cert.to_der() {
+        Ok(der) => der,
+        Err(e) => return malformed(&e.to_string()),
+    };
+    state.issued.push(cert);
+    respond(StatusCode::CREATED)
+        .header(
+            "Link",
+            format!("<{}>;rel=\"up\"", mock.url("/intermediate")),
+        )
+        .content_type("application/pkix-cert")
+        .body(der)
+}
+
+async fn revoke_cert(mock: web::Data<Mock>, body: web::Bytes) -> HttpResponse {
+    let cert = match payload(&body)
+        .and_then(|p| from_base64url(p["certificate"].as_str()?))
+        .and_then(|der| X509::from_der(&der).ok())
+    {
+        Some(cert) => cert,
+        None => return malformed("unreadable certificate"),
+    };
+    mock.state.lock().unwrap().revoked.push(cert);
+    respond(StatusCode::OK).finish()
+}
+
+async fn intermediate(mock: web::Data<Mock>) -> HttpResponse {
+    match mock.ca.to_pem() {
+        Ok(pem) => HttpResponse::Ok()
+            .content_type("application/x-pem-file")
+            .body(pem),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+impl MockAcme {
+    /// Starts serving on a loopback port.
+    pub fn start() -> Result<Self, Error> {
+        let (ca_key, ca) = generate_ca()?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let mock = web::Data::new(Mock {
+            base: format!("http://{}", listener.local_addr()?),
+            ca_key,
+            ca,
+            state: Mutex::new(State::default()),
+        });
+        let served = mock.clone();
+        let (started, server) = mpsc::channel();
+        thread::spawn(move || {
+            let system = System::new("mock-acme");
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(served.clone())
+                    .route("/directory", web::get().to(directory))
+                    .route("/acme/new-nonce", web::route().to(new_nonce))
+                    .route("/acme/new-reg", web::post().to(new_reg))
+                    .route("/acme/new-authz", web::post().to(new_authz))
+                    .route("/acme/challenge/{id}", web::post().to(answer_challenge))
+                    .route("/acme/challenge/{id}", web::get().to(poll_challenge))
+                    .route("/acme/new-cert", web::post().to(new_cert))
+                    .route("/acme/revoke-cert", web::post().to(revoke_cert))
+                    .route("/intermediate", web::get().to(intermediate))
+            })
+            .workers(1)
+            .disable_signals()
+            .system_exit()
+            .listen(listener)
+            .map(HttpServer::run);
+            let running = server.is_ok();
+            let _ = started.send(server);
+            if running {
+                let _ = system.run();
+            }
+        });
+        let server = server
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "mock ACME server didn't start"))??;
+        Ok(MockAcme { mock, server })
+    }
+
+    /// The directory's URL.
+    pub fn directory_url(&self) -> String {
+        self.mock.url("/directory")
+    }
+
+    /// The CA every certificate is signed by, e.g. for a client to trust.
+    pub fn ca(&self) -> X509 {
+        self.mock.ca.clone()
+    }
+
+    /// An enabler ordering every cert, production or test, from this
+    /// directory, one at a time and without checking CAA records.
+    pub fn enabler(&self) -> LetsEncrypt {
+        LetsEncrypt::encryption_enabler()
+            .max_concurrent_orders(1)
+            .acme_directory(self.directory_url())
+            .intermediate_url(self.mock.url("/intermediate"))
+    }
+
+    /// Fails the next `count` challenges answered, whatever their domain.
+    pub fn fail_challenges(&self, count: usize) {
+        self.mock.state.lock().unwrap().failing = count;
+    }
+
+    /// Signs the next `count` certificates only after `by`, as if the CA
+    /// were overloaded.
+    pub fn stall_certificates(&self, count: usize, by: Duration) {
+        let mut state = self.mock.state.lock().unwrap();
+        state.stalling = count;
+        state.stall = by;
+    }
+
+    /// The certificates signed so far, oldest first.
+    pub fn issued(&self) -> Vec<X509> {
+        self.mock.state.lock().unwrap().issued.clone()
+    }
+
+    /// The certificates revoked so far, oldest first.
+    pub fn revoked(&self) -> Vec<X509> {
+        self.mock.state.lock().unwrap().revoked.clone()
+    }
+
+    /// The domains whose challenges were answered, in order.
+    pub fn validated(&self) -> Vec<String> {
+        let state = self.mock.state.lock().unwrap();
+        let mut validated: Vec<(u64, String)> = state
+            .challenges
+            .iter()
+            .filter(|(_, (_, _, status))| *status == "valid")
+            .map(|(id, (domain, _, _))| (*id, domain.clone()))
+            .collect();
+        validated.sort();
+        validated.into_iter().map(|(_, domain)| domain).collect()
+    }
+}
+
+impl Drop for MockAcme {
+    fn drop(&mut self) {
+        // Stopping is requested as soon as this is called; the future only
+        // waits for it to finish.
+        let _ = self.server.stop(false);
+    }
+}
+
+/// A clock that only moves when told to.  Clones share the time.
+#[derive(Clone)]
+pub struct FakeClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl FakeClock {
+    /// Starting at the current time.
+    pub fn new() -> Self {
+        Self::at(Utc::now())
+    }
+
+    pub fn at(now: DateTime<Utc>) -> Self {
+        FakeClock(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let by = chrono::Duration::from_std(by).expect("advanced too far");
+        let mut now = self.0.lock().unwrap();
+        *now = *now + by;
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
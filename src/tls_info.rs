@@ -0,0 +1,81 @@
+//! Per-connection TLS details for handlers.
+//!
+//! With `tls_info` set, `attach_certificates_to` installs
+//! `TlsInfo::capture` as the server's `on_connect` callback, replacing any
+//! set before.  An application with a callback of its own leaves it unset
+//! and calls `TlsInfo::capture` from its callback instead.  Servers from
+//! `proxy_protocol_server` always capture it.
+
+use {
+    crate::proxy_protocol::Proxied,
+    actix_http::Extensions,
+    actix_tls::openssl::SslStream,
+    actix_web::{
+        dev::Payload, error::ErrorBadRequest, rt::net::TcpStream, FromRequest, HttpRequest,
+    },
+    openssl::{nid::Nid, ssl::NameType, x509::X509},
+    std::{
+        any::Any,
+        future::{ready, Ready},
+        net::SocketAddr,
+    },
+};
+
+/// Extracts the TLS details of the connection a request arrived on.  The
+/// extraction fails with 400 on plain HTTP; take an `Option<TlsInfo>` in
+/// handlers that serve both.
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    /// The SNI name the client asked for.
+    pub server_name: Option<String>,
+    /// The first domain of the certificate that was presented.
+    pub certificate: Option<String>,
+    /// The local address of the listener that accepted the connection.
+    pub local_addr: Option<SocketAddr>,
+    /// e.g. `TLSv1.3`.
+    pub protocol: String,
+    /// The client's certificate, when using mutual TLS.
+    pub client_certificate: Option<X509>,
+}
+
+impl TlsInfo {
+    /// Records the connection's TLS details in `ext`; pass it (or call it
+    /// from your own callback) to `HttpServer::on_connect`.
+    pub fn capture(conn: &dyn Any, ext: &mut Extensions) {
+        if let Some(stream) = conn.downcast_ref::<SslStream<TcpStream>>() {
+            TlsInfo::record(stream, stream.get_ref().local_addr().ok(), ext);
+        } else if let Some(stream) = conn.downcast_ref::<SslStream<Proxied>>() {
+            TlsInfo::record(stream, stream.get_ref().local_addr().ok(), ext);
+        }
+    }
+
+    fn record<T>(stream: &SslStream<T>, local_addr: Option<SocketAddr>, ext: &mut Extensions) {
+        let ssl = stream.ssl();
+        let certificate = ssl.certificate().and_then(|cert| {
+            let entry = cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()?;
+            entry.data().as_utf8().ok().map(|name| name.to_string())
+        });
+        ext.insert(TlsInfo {
+            server_name: ssl.servername(NameType::HOST_NAME).map(str::to_string),
+            certificate,
+            local_addr,
+            protocol: ssl.version_str().to_string(),
+            client_certificate: ssl.peer_certificate(),
+        });
+    }
+}
+
+impl FromRequest for TlsInfo {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<TlsInfo>()
+                .cloned()
+                .ok_or_else(|| ErrorBadRequest("not a TLS connection")),
+        )
+    }
+}
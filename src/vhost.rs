@@ -0,0 +1,108 @@
+//! Serving several domains from one HTTPS listener, each with its own
+//! routes and certificate.
+
+use {
+    crate::{CertBuilder, Domain, LetsEncrypt},
+    actix_service::ServiceFactory,
+    actix_web::{
+        dev::{MessageBody, ServiceRequest, ServiceResponse},
+        guard, web, App,
+    },
+    std::{net::SocketAddr, sync::Arc},
+};
+
+type Configure = Arc<dyn Fn(&mut web::ServiceConfig) + Send + Sync>;
+
+#[derive(Clone)]
+struct VirtualHost {
+    domains: Vec<Domain>,
+    configure: Configure,
+}
+
+/// Routes requests by their Host header, with a managed certificate per
+/// host picked by SNI.  Add it to the enabler with `virtual_hosts`, which
+/// creates the certificates, and to the app with `register`.
+#[derive(Clone, Default)]
+pub struct VirtualHosts {
+    hosts: Arc<Vec<VirtualHost>>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `domains`, which share a certificate, with the routes
+    /// `configure` sets up.
+    pub fn host<D, F>(mut self, domains: &[D], configure: F) -> Self
+    where
+        D: AsRef<str>,
+        F: Fn(&mut web::ServiceConfig) + Send + Sync + 'static,
+    {
+        let domains = domains
+            .iter()
+            .map(|d| Domain::new(d).unwrap_or_else(|e| panic!("{}", e)))
+            .collect();
+        Arc::make_mut(&mut self.hosts).push(VirtualHost {
+            domains,
+            configure: Arc::new(configure),
+        });
+        self
+    }
+
+    /// Adds a scope per host, guarded by its domains, to `app`.  Requests
+    /// for other hosts fall through to the app's own routes.
+    pub fn register<B, T>(&self, mut app: App<T, B>) -> App<T, B>
+    where
+        B: MessageBody,
+        T: ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse<B>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    {
+        for host in self.hosts.iter() {
+            let mut hosts = guard::Any(guard::Host(host.domains[0].as_str()));
+            for domain in &host.domains[1..] {
+                hosts = hosts.or(guard::Host(domain.as_str()));
+            }
+            let configure = host.configure.clone();
+            app = app.service(
+                web::scope("")
+                    .guard(hosts)
+                    .configure(move |config| configure(config)),
+            );
+        }
+        app
+    }
+
+    // The first host's certificate binds `addr`, the rest are served on
+    // it through SNI.
+    fn cert_builders(&self, addr: SocketAddr) -> Vec<CertBuilder> {
+        let no_addrs: &[SocketAddr] = &[];
+        self.hosts
+            .iter()
+            .enumerate()
+            .map(|(i, host)| {
+                if i == 0 {
+                    CertBuilder::new(addr, &host.domains)
+                } else {
+                    CertBuilder::new(no_addrs, &host.domains)
+                }
+            })
+            .collect()
+    }
+}
+
+impl LetsEncrypt {
+    /// Manages a certificate for each of `hosts`, all served on the HTTPS
+    /// listener at `addr`.  The cert defaults supply their settings.
+    pub fn virtual_hosts(mut self, hosts: &VirtualHosts, addr: SocketAddr) -> Self {
+        for cert_builder in hosts.cert_builders(addr) {
+            self.push_cert(cert_builder);
+        }
+        self
+    }
+}
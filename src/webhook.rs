@@ -0,0 +1,112 @@
+//! JSON webhook notifications.
+
+use {
+    crate::{credentials::Secret, Event},
+    actix_web::rt::time::delay_for,
+    log::{debug, warn},
+    openssl::{error::ErrorStack, hash::MessageDigest, pkey::PKey, sign::Signer},
+    serde::{Deserialize, Serialize},
+    std::{future::Future, time::Duration},
+};
+
+/// Header carrying `sha256=<hex HMAC of the body>` when a secret is set.
+pub const SIGNATURE_HEADER: &str = "X-Lets-Encrypt-Signature";
+
+/// An endpoint that every `Event` is POSTed to as JSON.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Webhook {
+    url: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<Secret>,
+
+    #[serde(default = "Webhook::default_retries")]
+    retries: u32,
+}
+
+impl Webhook {
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        Webhook {
+            url: url.into(),
+            secret: None,
+            retries: Self::default_retries(),
+        }
+    }
+
+    fn default_retries() -> u32 {
+        3
+    }
+
+    /// Signs each payload with HMAC-SHA256 using `secret`.
+    pub fn secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.secret = Some(Secret::from(secret.into()));
+        self
+    }
+
+    /// How many times a failed delivery is retried, with the delay doubling
+    /// from one second.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    fn signature(secret: &str, body: &[u8]) -> Result<String, ErrorStack> {
+        let key = PKey::hmac(secret.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(body)?;
+        let hmac = signer.sign_to_vec()?;
+        Ok(hmac.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub(crate) fn send(&self, event: &Event) -> impl Future<Output = ()> {
+        let webhook = self.clone();
+        let domain = event.domain().clone();
+        let body = serde_json::to_vec(event).expect("events always serialize");
+        async move {
+            let signature = match &webhook.secret {
+                None => None,
+                Some(secret) => match Self::signature(secret.expose(), &body) {
+                    Ok(signature) => Some(format!("sha256={}", signature)),
+                    Err(e) => {
+                        warn!("{}: could not sign webhook payload: {}", domain, e);
+                        return;
+                    }
+                },
+            };
+
+            let client = awc::Client::default();
+            let mut delay = Duration::from_secs(1);
+            for attempt in 0..=webhook.retries {
+                if attempt > 0 {
+                    delay_for(delay).await;
+                    delay *= 2;
+                }
+                let mut request = client
+                    .post(&webhook.url)
+                    .header("Content-Type", "application/json");
+                if let Some(signature) = &signature {
+                    request = request.header(SIGNATURE_HEADER, signature.as_str());
+                }
+                match request.send_body(body.clone()).await {
+                    Ok(response) if response.status().is_success() => {
+                        debug!("{}: delivered webhook to {}", domain, webhook.url);
+                        return;
+                    }
+                    Ok(response) => warn!(
+                        "{}: webhook {} answered {}",
+                        domain,
+                        webhook.url,
+                        response.status()
+                    ),
+                    Err(e) => warn!("{}: webhook {} failed: {}", domain, webhook.url, e),
+                }
+            }
+            warn!(
+                "{}: giving up on webhook {} after {} attempts",
+                domain,
+                webhook.url,
+                webhook.retries + 1
+            );
+        }
+    }
+}
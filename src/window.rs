@@ -0,0 +1,222 @@
+//! Daily maintenance windows restricting when certificates are renewed.
+
+use {
+    chrono::{DateTime, Duration as ChronoDuration, NaiveTime, TimeZone, Utc},
+    chrono_tz::Tz,
+    serde::{Deserialize, Serialize},
+    std::{convert::TryFrom, error, fmt, ops::Range, time::Duration},
+};
+
+const TIME_FORMAT: &str = "%H:%M";
+
+// Longer than any DST change or time zone shift skips.
+const MAX_GAP_MINUTES: i64 = 3 * 60;
+
+/// Local times between which a due certificate may be ordered and swapped
+/// in.  A window whose end is before its start runs past midnight; one
+/// whose end is its start would never open, so is refused.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "WindowConfig", into = "WindowConfig")]
+pub struct RenewWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    tz: Tz,
+}
+
+// How a window appears in the JSON configuration, e.g.
+// `{"start":"02:00","end":"05:00","tz":"Europe/Berlin"}`.
+#[derive(Deserialize, Serialize)]
+struct WindowConfig {
+    start: String,
+    end: String,
+    #[serde(default = "WindowConfig::default_tz")]
+    tz: String,
+}
+
+impl WindowConfig {
+    fn default_tz() -> String {
+        "UTC".to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WindowError {
+    Time(String),
+    TimeZone(String),
+    Empty(String),
+}
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowError::Time(t) => write!(f, "{}: not a time of day, expected HH:MM", t),
+            WindowError::TimeZone(tz) => write!(f, "{}: unknown time zone", tz),
+            WindowError::Empty(t) => write!(f, "{}-{}: the window is empty", t, t),
+        }
+    }
+}
+
+impl error::Error for WindowError {}
+
+impl RenewWindow {
+    /// `times` are `HH:MM` and `tz` is an IANA name such as
+    /// `"America/Denver"`.
+    pub fn new(times: Range<&str>, tz: &str) -> Result<Self, WindowError> {
+        let time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), TIME_FORMAT)
+                .map_err(|_| WindowError::Time(t.to_string()))
+        };
+        let (start, end) = (time(times.start)?, time(times.end)?);
+        if start == end {
+            return Err(WindowError::Empty(start.format(TIME_FORMAT).to_string()));
+        }
+        Ok(RenewWindow {
+            start,
+            end,
+            tz: tz
+                .parse()
+                .map_err(|_| WindowError::TimeZone(tz.to_string()))?,
+        })
+    }
+
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.tz).time();
+        if self.start <= self.end {
+            self.start <= local && local < self.end
+        } else {
+            local >= self.start || local < self.end
+        }
+    }
+
+    /// How long after `now` the window next opens, or `None` if it's open
+    /// then.
+    pub(crate) fn delay_until_open(&self, now: DateTime<Utc>) -> Option<Duration> {
+        if self.contains(now) {
+            return None;
+        }
+        let today = now.with_timezone(&self.tz).date().naive_local();
+        // Today's opening if it's still ahead, otherwise tomorrow's.  A
+        // start skipped by a DST change opens when the clocks land, at
+        // the first minute after it that exists.
+        (0..=2)
+            .filter_map(|days| {
+                let start = (today + ChronoDuration::days(days)).and_time(self.start);
+                (0..=MAX_GAP_MINUTES).find_map(|minutes| {
+                    let local = start + ChronoDuration::minutes(minutes);
+                    self.tz.from_local_datetime(&local).earliest()
+                })
+            })
+            .map(|open| open.with_timezone(&Utc))
+            .find(|open| *open > now)
+            .and_then(|open| open.signed_duration_since(now).to_std().ok())
+    }
+}
+
+impl fmt::Display for RenewWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} {}",
+            self.start.format(TIME_FORMAT),
+            self.end.format(TIME_FORMAT),
+            self.tz.name()
+        )
+    }
+}
+
+impl TryFrom<WindowConfig> for RenewWindow {
+    type Error = WindowError;
+
+    fn try_from(config: WindowConfig) -> Result<Self, Self::Error> {
+        Self::new(config.start.as_str()..config.end.as_str(), &config.tz)
+    }
+}
+
+impl From<RenewWindow> for WindowConfig {
+    fn from(window: RenewWindow) -> Self {
+        WindowConfig {
+            start: window.start.format(TIME_FORMAT).to_string(),
+            end: window.end.format(TIME_FORMAT).to_string(),
+            tz: window.tz.name().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn hours(hours: f64) -> Option<Duration> {
+        Some(Duration::from_secs_f64(hours * 60.0 * 60.0))
+    }
+
+    #[test]
+    fn refuses_bad_windows() {
+        assert_eq!(
+            RenewWindow::new("02:00".."02:00", "UTC"),
+            Err(WindowError::Empty("02:00".to_string()))
+        );
+        assert_eq!(
+            RenewWindow::new("2am".."05:00", "UTC"),
+            Err(WindowError::Time("2am".to_string()))
+        );
+        assert_eq!(
+            RenewWindow::new("02:00".."05:00", "Mars/Olympus"),
+            Err(WindowError::TimeZone("Mars/Olympus".to_string()))
+        );
+    }
+
+    #[test]
+    fn waits_for_a_window_within_a_day() {
+        let window = RenewWindow::new("02:00".."05:00", "UTC").unwrap();
+        let delay = |now| window.delay_until_open(at(now));
+        assert_eq!(delay("2021-01-01T01:00:00Z"), hours(1.0));
+        assert_eq!(delay("2021-01-01T02:00:00Z"), None);
+        assert_eq!(delay("2021-01-01T04:59:00Z"), None);
+        assert_eq!(delay("2021-01-01T05:00:00Z"), hours(21.0));
+    }
+
+    #[test]
+    fn waits_for_a_window_past_midnight() {
+        let window = RenewWindow::new("22:00".."02:00", "UTC").unwrap();
+        let delay = |now| window.delay_until_open(at(now));
+        assert_eq!(delay("2021-01-01T23:00:00Z"), None);
+        assert_eq!(delay("2021-01-02T01:00:00Z"), None);
+        assert_eq!(delay("2021-01-02T02:00:00Z"), hours(20.0));
+        assert_eq!(delay("2021-01-02T12:00:00Z"), hours(10.0));
+    }
+
+    #[test]
+    fn waits_in_local_time() {
+        // Berlin is an hour ahead of UTC in winter, two in summer.
+        let window = RenewWindow::new("02:00".."05:00", "Europe/Berlin").unwrap();
+        let delay = |now| window.delay_until_open(at(now));
+        assert_eq!(delay("2021-01-01T00:00:00Z"), hours(1.0));
+        assert_eq!(delay("2021-01-01T01:00:00Z"), None);
+        assert_eq!(delay("2021-07-01T00:00:00Z"), None);
+        assert_eq!(delay("2021-07-01T03:00:00Z"), hours(21.0));
+    }
+
+    #[test]
+    fn opens_when_the_clocks_land_on_a_skipped_start() {
+        // Berlin's clocks jumped from 02:00 to 03:00 on 2021-03-28, 01:00 UTC.
+        let window = RenewWindow::new("02:30".."04:00", "Europe/Berlin").unwrap();
+        let delay = |now| window.delay_until_open(at(now));
+        assert_eq!(delay("2021-03-28T00:00:00Z"), hours(1.0));
+        assert_eq!(delay("2021-03-28T01:00:00Z"), None);
+        // The next day opens at 02:30 summer time.
+        assert_eq!(delay("2021-03-28T02:00:00Z"), hours(22.5));
+    }
+
+    #[test]
+    fn opens_on_the_first_of_a_repeated_start() {
+        // Berlin's clocks went back from 03:00 to 02:00 on 2021-10-31, 01:00 UTC.
+        let window = RenewWindow::new("02:30".."02:45", "Europe/Berlin").unwrap();
+        let delay = |now| window.delay_until_open(at(now));
+        assert_eq!(delay("2021-10-30T23:30:00Z"), hours(1.0));
+    }
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use {
+    actix_web_lets_encrypt::{
+        test_support::{FakeClock, MockAcme},
+        CertBuilder, Domain, LetsEncrypt,
+    },
+    std::{path::Path, time::Duration},
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+fn enabler(acme: &MockAcme, dir: &Path) -> LetsEncrypt {
+    acme.enabler()
+        .nonce_directory(dir.join("nonce"))
+        .ssl_directory(dir.join("ssl"))
+        .add_cert(
+            CertBuilder::new("127.0.0.1:0", &["example.com"])
+                .email("admin@example.com")
+                .renew_within(&Duration::from_secs(30 * DAY))
+                .jitter(&Duration::from_secs(0)),
+        )
+}
+
+#[test]
+fn renews_once_within_renew_within() {
+    let dir = common::scratch_dir("clock-renew-within");
+    let acme = MockAcme::start().unwrap();
+    let clock = FakeClock::new();
+    let enabler = enabler(&acme, &dir).clock(clock.clone());
+    let offline = enabler.offline();
+    let example = Domain::new("example.com").unwrap();
+
+    assert_eq!(offline.issue(None, false).unwrap(), vec![example.clone()]);
+    assert!(offline.issue(None, false).unwrap().is_empty());
+
+    // The certificate is good for 90 days: 31 left isn't due yet, 29 is.
+    clock.advance(Duration::from_secs(59 * DAY));
+    assert!(offline.issue(None, false).unwrap().is_empty());
+    clock.advance(Duration::from_secs(2 * DAY));
+    assert_eq!(offline.issue(None, false).unwrap(), vec![example]);
+    assert_eq!(acme.issued().len(), 2);
+}
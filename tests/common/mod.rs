@@ -0,0 +1,9 @@
+use std::{env, fs, path::PathBuf, process};
+
+/// An empty directory for one test, under the system's temporary one.
+pub fn scratch_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("lets-encrypt-{}-{}", test, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use {
+    actix::Actor,
+    actix_web::rt::{time::delay_for, System},
+    actix_web_lets_encrypt::{test_support::MockAcme, CertBuilder, Domain, Error, LetsEncrypt},
+    chrono::Utc,
+    std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+};
+
+fn enabler(acme: &MockAcme, dir: &Path) -> LetsEncrypt {
+    acme.enabler()
+        .nonce_directory(dir.join("nonce"))
+        .ssl_directory(dir.join("ssl"))
+        .add_cert(CertBuilder::new("127.0.0.1:0", &["example.com"]).email("admin@example.com"))
+}
+
+fn results(enabler: &LetsEncrypt) -> Vec<bool> {
+    enabler
+        .journal_entries()
+        .iter()
+        .map(|entry| entry.success)
+        .collect()
+}
+
+#[test]
+fn issues_a_certificate() {
+    let dir = common::scratch_dir("issuance-success");
+    let acme = MockAcme::start().unwrap();
+    let enabler = enabler(&acme, &dir);
+
+    let issued = enabler.offline().issue(None, false).unwrap();
+    assert_eq!(issued, vec![Domain::new("example.com").unwrap()]);
+    assert_eq!(acme.validated(), vec!["example.com"]);
+    let certs = acme.issued();
+    assert_eq!(certs.len(), 1);
+    let names: Vec<_> = certs[0]
+        .subject_alt_names()
+        .unwrap()
+        .iter()
+        .filter_map(|name| name.dnsname().map(str::to_string))
+        .collect();
+    assert_eq!(names, vec!["example.com"]);
+    assert!(dir.join("ssl/example.com_cert.pem").exists());
+    assert!(dir.join("ssl/example.com_key.pem").exists());
+    assert_eq!(results(&enabler), vec![true]);
+}
+
+#[test]
+fn fails_when_the_challenge_fails() {
+    let dir = common::scratch_dir("issuance-failed-challenge");
+    let acme = MockAcme::start().unwrap();
+    acme.fail_challenges(1);
+    let enabler = enabler(&acme, &dir);
+
+    match enabler.offline().issue(None, false) {
+        Err(Error::Acme(_)) => {}
+        other => panic!("expected an ACME error, got {:?}", other),
+    }
+    assert!(acme.issued().is_empty());
+    assert!(acme.validated().is_empty());
+    assert!(!dir.join("ssl/example.com_cert.pem").exists());
+    assert_eq!(results(&enabler), vec![false]);
+}
+
+#[test]
+fn retries_after_a_failed_challenge() {
+    let dir = common::scratch_dir("issuance-retry");
+    let acme = MockAcme::start().unwrap();
+    acme.fail_challenges(1);
+    let enabler =
+        enabler(&acme, &dir).retry_backoff(&Duration::from_millis(100), &Duration::from_secs(1));
+
+    System::new("retry").block_on(async {
+        let _actor = enabler.clone().start();
+        for _ in 0..300 {
+            if results(&enabler).last() == Some(&true) {
+                break;
+            }
+            delay_for(Duration::from_millis(100)).await;
+        }
+    });
+    assert_eq!(acme.issued().len(), 1);
+    assert!(dir.join("ssl/example.com_cert.pem").exists());
+    assert_eq!(results(&enabler), vec![false, true]);
+}
+
+#[test]
+fn serves_and_reschedules_an_overdue_order() {
+    let dir = common::scratch_dir("issuance-overdue");
+    let acme = MockAcme::start().unwrap();
+    acme.stall_certificates(1, Duration::from_secs(2));
+    let renewed = Arc::new(AtomicUsize::new(0));
+    let counted = renewed.clone();
+    let enabler = enabler(&acme, &dir)
+        .order_deadline(&Duration::from_millis(200))
+        .retry_backoff(&Duration::from_millis(100), &Duration::from_secs(1))
+        .on_renewed(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async {}
+        });
+
+    System::new("overdue").block_on(async {
+        let _actor = enabler.clone().start();
+        for _ in 0..100 {
+            if renewed.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            delay_for(Duration::from_millis(100)).await;
+        }
+        // Retries while the order was overdue mustn't have ordered again.
+        delay_for(Duration::from_millis(500)).await;
+    });
+    assert_eq!(renewed.load(Ordering::SeqCst), 1);
+    assert_eq!(acme.issued().len(), 1);
+    assert!(dir.join("ssl/example.com_cert.pem").exists());
+    let next_check = enabler
+        .cert_info("example.com")
+        .unwrap()
+        .next_check
+        .unwrap();
+    assert!(next_check > Utc::now() + chrono::Duration::hours(1));
+}